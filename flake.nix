@@ -41,6 +41,7 @@
               extensions = [ "rust-src" "rust-analyzer" "rustfmt" ];
             })
             pkgs.fasm
+            pkgs.llvm
           ];
         };
       });
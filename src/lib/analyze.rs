@@ -0,0 +1,380 @@
+//! The analyze module contains static analyses of parsed programs, which answer questions about
+//! a program without running it, like `effect_signature`, and `behaviorally_equivalent`, which
+//! compares two programs by running them.
+//!
+//! # Example
+//! ```
+//! use rbfc::analyze;
+//! use rbfc::parser::Parser;
+//!
+//! let ops = Parser::new("++[>+<-]>.").parse().unwrap();
+//! assert_eq!(analyze::max_output_bytes(&ops), Some(1));
+//! ```
+
+use crate::interpreter::InterpreterSettings;
+use crate::lexer::{Token, TokenType};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// The number of ops `behaviorally_equivalent` executes of each program per input before giving
+/// up on it
+const EQUIVALENCE_MAX_STEPS: usize = 1_000_000;
+
+/// The largest number of bytes the program can write
+///
+/// This is the sum of the sizes of every `.` if none of them is inside a loop. A `.` inside a
+/// loop can be executed any number of times, so there is no bound and `None` is returned.
+///
+/// # Arguments
+/// * `ops` - The operations as returned by `Parser::parse` or the optimizer
+///
+/// # Example
+/// ```
+/// use rbfc::analyze::max_output_bytes;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new("+.>+..").parse().unwrap();
+/// assert_eq!(max_output_bytes(&ops), Some(3));
+/// let ops = Parser::new("+[.-]").parse().unwrap();
+/// assert_eq!(max_output_bytes(&ops), None);
+/// ```
+pub fn max_output_bytes(ops: &[Token]) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut bytes = 0;
+
+    for op in ops {
+        match op.token_type {
+            TokenType::OpenBracket => depth += 1,
+            TokenType::CloseBracket => depth = depth.saturating_sub(1),
+            TokenType::Dot if depth > 0 => return None,
+            TokenType::Dot => bytes += op.size.unwrap_or(1),
+            _ => {}
+        }
+    }
+
+    Some(bytes)
+}
+
+/// Whether the program can read input, which is the case if it contains a `,`
+///
+/// A `,` that is never reached, like in a loop that is always skipped, still counts, as whether
+/// it is reached can't be known in general without running the program.
+///
+/// # Arguments
+/// * `ops` - The operations as returned by `Parser::parse` or the optimizer
+///
+/// # Example
+/// ```
+/// use rbfc::analyze::requires_input;
+/// use rbfc::parser::Parser;
+///
+/// assert!(requires_input(&Parser::new("+[,.]").parse().unwrap()));
+/// assert!(!requires_input(&Parser::new("+[.-]").parse().unwrap()));
+/// ```
+pub fn requires_input(ops: &[Token]) -> bool {
+    ops.iter().any(|op| op.token_type == TokenType::Comma)
+}
+
+/// The locations of loops that likely never terminate
+///
+/// A loop is suspect if its body contains no inner loop, reads no input, moves the data pointer
+/// back to where it started and never decreases the cell it checks. Such a loop runs forever
+/// once entered, like `[]` or `[.]`, or only ends by wrapping the cell around, like `[+]`. This
+/// is a heuristic, loops that aren't flagged may still never terminate.
+///
+/// # Arguments
+/// * `ops` - The operations as returned by `Parser::parse`
+///
+/// # Example
+/// ```
+/// use rbfc::analyze::suspicious_loops;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new("+[>+<-] +[>.<]").parse().unwrap();
+/// assert_eq!(suspicious_loops(&ops), vec![9]);
+/// ```
+pub fn suspicious_loops(ops: &[Token]) -> Vec<usize> {
+    let mut suspects = Vec::new();
+    let mut body: Option<(usize, isize, isize)> = None;
+
+    for op in ops {
+        let size = op.size.unwrap_or(1) as isize;
+        match (&op.token_type, body.as_mut()) {
+            (TokenType::OpenBracket, _) => body = Some((op.loc, 0, 0)),
+            (TokenType::CloseBracket, Some(&mut (loc, 0, change))) if change >= 0 => {
+                suspects.push(loc);
+                body = None;
+            }
+            (TokenType::ShiftRight, Some((_, offset, _))) => *offset += size,
+            (TokenType::ShiftLeft, Some((_, offset, _))) => *offset -= size,
+            (TokenType::Plus, Some((_, 0, change))) => *change += size,
+            (TokenType::Minus, Some((_, 0, change))) => *change -= size,
+            (TokenType::Plus | TokenType::Minus | TokenType::Dot | TokenType::Debug, _) => {}
+            // Anything else may end the loop, or is an inner loop that can't be judged this way
+            _ => body = None,
+        }
+    }
+
+    suspects
+}
+
+/// The footprint `loop_footprints` returns for a loop whose body doesn't move the data pointer by
+/// a fixed amount, which covers every offset
+pub const UNKNOWN_FOOTPRINT: Range<isize> = isize::MIN..isize::MAX;
+
+/// The cells every top-level loop reaches
+///
+/// The footprint of a loop is the range of offsets from the cell the loop is entered at that its
+/// body reaches, including the cell it checks. Only loops that move the data pointer back to
+/// where they started, like every loop nested in them, reach the same cells in every pass. The
+/// others, as well as loops containing a `Scan`, have the `UNKNOWN_FOOTPRINT`.
+///
+/// # Arguments
+/// * `ops` - The operations as returned by `Parser::parse` or the optimizer
+///
+/// # Example
+/// ```
+/// use rbfc::analyze::{loop_footprints, UNKNOWN_FOOTPRINT};
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new("+[<->>+<-] >[>]").parse().unwrap();
+/// assert_eq!(loop_footprints(&ops), vec![(1, -1..2), (12, UNKNOWN_FOOTPRINT)]);
+/// ```
+pub fn loop_footprints(ops: &[Token]) -> Vec<(usize, Range<isize>)> {
+    let mut footprints = Vec::new();
+    // The offsets the loops that are open were entered at, the outermost first
+    let mut entries = Vec::new();
+    let mut loc = 0;
+    let mut offset = 0;
+    let mut reach = None;
+
+    for op in ops {
+        let size = op.size.unwrap_or(1) as isize;
+        if entries.is_empty() {
+            if op.token_type == TokenType::OpenBracket {
+                entries.push(0);
+                loc = op.loc;
+                offset = 0;
+                reach = Some((0, 0));
+            }
+            continue;
+        }
+        match op.token_type {
+            TokenType::OpenBracket => entries.push(offset),
+            TokenType::CloseBracket => {
+                if entries.pop() != Some(offset) {
+                    reach = None;
+                }
+                if entries.is_empty() {
+                    let footprint = reach.map_or(UNKNOWN_FOOTPRINT, |(low, high)| low..high + 1);
+                    footprints.push((loc, footprint));
+                }
+                continue;
+            }
+            TokenType::ShiftRight => offset += size,
+            TokenType::ShiftLeft => offset -= size,
+            TokenType::Scan { .. } => reach = None,
+            _ => {}
+        }
+        let reached = match op.token_type {
+            TokenType::MulAdd { offset: at, .. } | TokenType::AddAt { offset: at, .. } => {
+                offset + at
+            }
+            _ => offset,
+        };
+        reach = reach.map(|(low, high)| (low.min(reached), high.max(reached)));
+    }
+
+    footprints
+}
+
+/// The net effect of a program without loops, see `effect_signature`
+///
+/// # Fields
+/// * `shift` - How far the data pointer ends up from where it started
+/// * `deltas` - The net change of every cell the program changes, by its offset from the cell the
+///   data pointer started at. Cells whose changes cancel out are left out
+/// * `output_bytes` - The number of bytes the program writes
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Signature {
+    pub shift: isize,
+    pub deltas: BTreeMap<isize, isize>,
+    pub output_bytes: usize,
+}
+
+/// Summarize the net effect of a program, which is the same for every program that behaves the
+/// same apart from what it writes
+///
+/// Only programs that move the data pointer and add to cells have a signature, so programs with
+/// loops or input, or with optimized ops that depend on the value of a cell like `Clear` and
+/// `MulAdd`, return `None`. The ops after a `Halt` are never executed and don't count.
+///
+/// # Arguments
+/// * `ops` - The operations as returned by `Parser::parse` or the optimizer
+///
+/// # Example
+/// ```
+/// use rbfc::analyze::effect_signature;
+/// use rbfc::parser::Parser;
+///
+/// let signature = |code| effect_signature(&Parser::new(code).parse().unwrap());
+/// assert_eq!(signature(">+<+>"), signature("+>+"));
+/// assert_eq!(signature("+[-]"), None);
+/// ```
+pub fn effect_signature(ops: &[Token]) -> Option<Signature> {
+    let mut signature = Signature::default();
+
+    for op in ops {
+        let size = op.size.unwrap_or(1) as isize;
+        let delta = match op.token_type {
+            TokenType::ShiftRight => {
+                signature.shift += size;
+                continue;
+            }
+            TokenType::ShiftLeft => {
+                signature.shift -= size;
+                continue;
+            }
+            TokenType::Dot => {
+                signature.output_bytes += size as usize;
+                continue;
+            }
+            TokenType::Plus => size,
+            TokenType::Minus => -size,
+            TokenType::AddAt { offset, delta } => {
+                *signature
+                    .deltas
+                    .entry(signature.shift + offset)
+                    .or_default() += delta;
+                continue;
+            }
+            TokenType::Debug => continue,
+            TokenType::Eof | TokenType::Halt => break,
+            _ => return None,
+        };
+        *signature.deltas.entry(signature.shift).or_default() += delta;
+    }
+
+    signature.deltas.retain(|_, delta| *delta != 0);
+    Some(signature)
+}
+
+/// Whether two programs behave the same on every one of the inputs
+///
+/// Both programs are interpreted on every input, or on the empty input if there are none, and
+/// have to produce the same output and leave the same tape behind. Trailing zero cells don't
+/// count, so a program that uses more of the tape only to clear it again still matches. A program
+/// that fails on an input, by reading past its end, leaving the tape or not halting within a
+/// million steps, doesn't match any program.
+///
+/// This only tests the programs on the given inputs, so it can show that an optimized program
+/// differs from the original but not that they are equivalent on every input.
+///
+/// # Arguments
+/// * `a` - The code of the first program
+/// * `b` - The code of the second program
+/// * `inputs` - The inputs to run both programs on
+///
+/// # Example
+/// ```
+/// use rbfc::analyze::behaviorally_equivalent;
+///
+/// assert!(behaviorally_equivalent(",[.-]", ",[.-]>+-<", &[b"a", b"\x00"]));
+/// assert!(!behaviorally_equivalent(",.", ",+.", &[b"a"]));
+/// ```
+pub fn behaviorally_equivalent(a: &str, b: &str, inputs: &[&[u8]]) -> bool {
+    let settings = InterpreterSettings {
+        max_steps: Some(EQUIVALENCE_MAX_STEPS),
+        ..Default::default()
+    };
+    let empty: &[&[u8]] = &[b""];
+    let inputs = if inputs.is_empty() { empty } else { inputs };
+
+    inputs.iter().all(|input| {
+        match (
+            crate::run(a, input, settings.clone()),
+            crate::run(b, input, settings.clone()),
+        ) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_max_output_bytes() {
+        let output = |code: &str| max_output_bytes(&Parser::new(code).parse().unwrap());
+        assert_eq!(output("..."), Some(3));
+        assert_eq!(output("[.]"), None);
+        assert_eq!(output("[-]>.<[[-]]."), Some(2));
+        assert_eq!(output(",+"), Some(0));
+    }
+
+    #[test]
+    fn test_requires_input() {
+        let requires = |code: &str| requires_input(&Parser::new(code).parse().unwrap());
+        assert!(requires(",."));
+        assert!(!requires("+."));
+        assert!(requires("[,]"));
+    }
+
+    #[test]
+    fn test_suspicious_loops() {
+        let suspects = |code: &str| suspicious_loops(&Parser::new(code).parse().unwrap());
+        assert_eq!(suspects("[]"), vec![0]);
+        assert_eq!(suspects("+[+]"), vec![1]);
+        assert!(suspects("+[-]").is_empty());
+        assert!(suspects("+[>]").is_empty());
+        assert!(suspects("+[,]").is_empty());
+        assert_eq!(suspects("+[>+<.]"), vec![1]);
+        assert!(suspects("+[[-]+]").is_empty());
+    }
+
+    #[test]
+    fn test_loop_footprints() {
+        let footprints = |code: &str| loop_footprints(&Parser::new(code).parse().unwrap());
+        // The cell the loop checks and the one to its right
+        assert_eq!(footprints("[->+<]"), vec![(0, 0..2)]);
+        assert_eq!(footprints(">[-<<[->+<]>>]"), vec![(1, -2..1)]);
+        assert_eq!(footprints("[[>]<]"), vec![(0, UNKNOWN_FOOTPRINT)]);
+        assert_eq!(footprints("+."), vec![]);
+
+        // The inner loop is optimized into a `MulAdd` reaching one cell further
+        let ops = Parser::new("[>[->+<]<-]").parse().unwrap();
+        let ops = crate::optimizer::optimize(ops, 2);
+        assert_eq!(loop_footprints(&ops), vec![(0, 0..3)]);
+    }
+
+    #[test]
+    fn test_effect_signature() {
+        let signature = |code: &str| effect_signature(&Parser::new(code).parse().unwrap());
+        let a = signature(">+>++<").unwrap();
+        assert_eq!(a.shift, 1);
+        assert_eq!(a.deltas, BTreeMap::from([(1, 1), (2, 2)]));
+        let b = signature(">+").unwrap();
+        assert_eq!(b.deltas, BTreeMap::from([(1, 1)]));
+        assert_ne!(a, b);
+        assert_ne!(signature(">+"), signature("+>"));
+        assert_eq!(signature("+-.>.<"), signature(".."));
+        assert_eq!(signature(","), None);
+        assert_eq!(signature("+[>]"), None);
+    }
+
+    #[test]
+    fn test_behaviorally_equivalent() {
+        assert!(behaviorally_equivalent("[-]", "[-][-]", &[]));
+        assert!(!behaviorally_equivalent("+", "-", &[]));
+        assert!(behaviorally_equivalent(
+            ",[-]>+<",
+            ",>+<[-]",
+            &[b"a", b"\x00"]
+        ));
+        assert!(!behaviorally_equivalent(",.", ",", &[b"a"]));
+        assert!(!behaviorally_equivalent("+[]", "+[]", &[]));
+    }
+}
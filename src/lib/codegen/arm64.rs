@@ -0,0 +1,368 @@
+use super::CodeGen;
+use crate::compiler::CompilerSettings;
+use crate::interpreter::CellWidth;
+use indoc::{formatdoc, indoc};
+
+/// The AArch64 backend
+///
+/// This backend emits AArch64 assembly for the GNU assembler, which assembles and links into an
+/// executable for Linux. The data pointer is kept in `x19` and points to the lowest byte of the
+/// current cell, the start of the tape is kept in `x20`, its length in bytes in `x21` and its
+/// end in `x22`, which the wrapping moves compare against. Cells are loaded into `w9` and IO is
+/// done with `svc #0`. The `buffer_output` and `emit_as_function` settings are ignored.
+///
+/// # Example
+/// ```
+/// use rbfc::codegen::{Arm64Backend, CodeGen};
+/// use rbfc::compiler::CompilerSettings;
+///
+/// let settings = CompilerSettings::default();
+/// let backend = Arm64Backend::new(&settings);
+/// assert!(backend.emit_add(3).contains("add w9, w9, #3\n"));
+/// ```
+#[derive(Debug)]
+pub struct Arm64Backend<'a> {
+    settings: &'a CompilerSettings,
+}
+
+impl<'a> Arm64Backend<'a> {
+    /// Create a new AArch64 backend
+    ///
+    /// # Arguments
+    /// * `settings` - The settings of the compiler, used for the wrap setting and the tape
+    pub fn new(settings: &'a CompilerSettings) -> Arm64Backend<'a> {
+        Arm64Backend { settings }
+    }
+
+    /// The instruction loading a cell into a `w` register
+    fn load(&self) -> &'static str {
+        match self.settings.tape.cell_width {
+            CellWidth::U8 => "ldrb",
+            CellWidth::U16 => "ldrh",
+            CellWidth::U32 => "ldr",
+        }
+    }
+
+    /// The instruction storing a `w` register into a cell
+    fn store(&self) -> &'static str {
+        match self.settings.tape.cell_width {
+            CellWidth::U8 => "strb",
+            CellWidth::U16 => "strh",
+            CellWidth::U32 => "str",
+        }
+    }
+
+    /// Apply `op` with an immediate to a register, going through `scratch` for immediates that
+    /// don't fit into the 12 bits of `add` and `sub`
+    fn immediate(&self, op: &str, register: &str, value: u64, scratch: &str) -> String {
+        if value < 4096 {
+            format!("{op} {register}, {register}, #{value}\n")
+        } else {
+            format!("ldr {scratch}, ={value}\n{op} {register}, {register}, {scratch}\n")
+        }
+    }
+
+    /// Compute the address of the cell at `offset` into `x11`
+    fn target(&self, offset: isize) -> String {
+        let offset = offset * self.settings.tape.cell_width.bytes() as isize;
+        let op = if offset < 0 { "sub" } else { "add" };
+        format!(
+            "mov x11, x19\n{}",
+            self.immediate(op, "x11", offset.unsigned_abs() as u64, "x12")
+        )
+    }
+
+    /// Branch to `label` if `w9` holds the loop sentinel, or if it doesn't with `equal` unset
+    fn branch_sentinel(&self, equal: bool, label: &str) -> String {
+        match (self.settings.loop_sentinel, equal) {
+            (0, true) => format!("cbz w9, {label}"),
+            (0, false) => format!("cbnz w9, {label}"),
+            (sentinel, true) => format!("cmp w9, #{sentinel}\nb.eq {label}"),
+            (sentinel, false) => format!("cmp w9, #{sentinel}\nb.ne {label}"),
+        }
+    }
+
+    /// Wrap a constant around at the cell width
+    fn constant(&self, n: isize) -> u64 {
+        (n as u32 & self.settings.tape.cell_width.mask()) as u64
+    }
+}
+
+impl CodeGen for Arm64Backend<'_> {
+    fn header(&self) -> String {
+        let constants = formatdoc! {"
+            // Helper functions
+            .equ SYS_read, 63
+            .equ SYS_write, 64
+            .equ SYS_exit, 93
+
+            .equ STDIN, 0
+            .equ STDOUT, 1
+
+            .equ TAPE_SIZE, {tape_size}
+            .equ TAPE_BYTES, TAPE_SIZE * {bytes}
+
+            .text
+            .global _start
+
+            ", tape_size = self.settings.tape.tape_size, bytes = self.settings.tape.cell_width.bytes()};
+
+        let status = if self.settings.exit_from_cell {
+            "ldrb w0, [x20]"
+        } else {
+            "mov x0, 0"
+        };
+        let helper_functions = formatdoc! {"
+            WRITE_TO_STDOUT:
+            mov x0, STDOUT
+            mov x1, x19
+            mov x2, 1
+            mov x8, SYS_write
+            svc #0
+            ret
+
+            READ_FROM_STDIN:
+            mov x0, STDIN
+            mov x1, x19
+            mov x2, 1
+            mov x8, SYS_read
+            svc #0
+            ret
+
+            EXIT:
+            {status}
+            mov x8, SYS_exit
+            svc #0
+
+            "};
+        let tape_error = if self.settings.bounds_check {
+            indoc! {"
+                TAPE_ERROR:
+                mov x0, 3
+                mov x8, SYS_exit
+                svc #0
+
+                "}
+        } else {
+            ""
+        };
+
+        let main = indoc! {"
+            _start:
+            ldr x19, =TAPE
+            mov x20, x19
+            ldr x21, =TAPE_BYTES
+            add x22, x20, x21
+            "};
+
+        let zero_tape = if self.settings.assume_zeroed {
+            ""
+        } else {
+            indoc! {"
+                // Zero the tape
+                mov x9, x20
+                zero_tape:
+                strb wzr, [x9], #1
+                cmp x9, x22
+                b.lo zero_tape
+                "}
+        };
+
+        format!("{constants}{helper_functions}{tape_error}{main}{zero_tape}")
+    }
+
+    fn footer(&self) -> String {
+        indoc! {"
+
+            .bss
+            .balign 16
+            TAPE:
+            .skip TAPE_BYTES
+        "}
+        .to_string()
+    }
+
+    fn emit_add(&self, n: usize) -> String {
+        formatdoc! {"
+            // TokenType::Plus
+            {load} w9, [x19]
+            {add}{store} w9, [x19]
+        ", load = self.load(), store = self.store(),
+        add = self.immediate("add", "w9", self.constant(n as isize), "w10")}
+    }
+
+    fn emit_sub(&self, n: usize) -> String {
+        formatdoc! {"
+            // TokenType::Minus
+            {load} w9, [x19]
+            {sub}{store} w9, [x19]
+        ", load = self.load(), store = self.store(),
+        sub = self.immediate("sub", "w9", self.constant(n as isize), "w10")}
+    }
+
+    fn emit_shift_right(&self, n: usize, loc: usize) -> String {
+        let n = self.settings.shift_cells(n, true);
+        let n = (n * self.settings.tape.cell_width.bytes()) as u64;
+        let mut code = format!(
+            "// TokenType::ShiftRight\n{}",
+            self.immediate("add", "x19", n, "x10")
+        );
+        if self.settings.tape.wrap_right {
+            code.push_str(&formatdoc! {"
+                cmp x19, x22
+                b.lo no_wrap_{loc}
+                sub x19, x19, x21
+                no_wrap_{loc}:
+            "});
+        } else if self.settings.bounds_check {
+            code.push_str("cmp x19, x22\nb.hs TAPE_ERROR\n");
+        }
+        code
+    }
+
+    fn emit_shift_left(&self, n: usize, loc: usize) -> String {
+        let n = self.settings.shift_cells(n, false);
+        let n = (n * self.settings.tape.cell_width.bytes()) as u64;
+        let mut code = format!(
+            "// TokenType::ShiftLeft\n{}",
+            self.immediate("sub", "x19", n, "x10")
+        );
+        if self.settings.tape.wrap_left {
+            code.push_str(&formatdoc! {"
+                cmp x19, x20
+                b.hs no_wrap_{loc}
+                add x19, x19, x21
+                no_wrap_{loc}:
+            "});
+        } else if self.settings.bounds_check {
+            code.push_str("cmp x19, x20\nb.lo TAPE_ERROR\n");
+        }
+        code
+    }
+
+    fn emit_output(&self, n: usize) -> String {
+        let mut code = String::from("// TokenType::Dot\n");
+        for _ in 0..n {
+            code.push_str("bl WRITE_TO_STDOUT\n");
+        }
+        code
+    }
+
+    fn emit_input(&self, n: usize) -> String {
+        let mut code = String::from("// TokenType::Comma\n");
+        for _ in 0..n {
+            // Only the lowest byte is read, so wider cells are cleared first
+            if self.settings.tape.cell_width != CellWidth::U8 {
+                code.push_str(&format!("{} wzr, [x19]\n", self.store()));
+            }
+            code.push_str("bl READ_FROM_STDIN\n");
+        }
+        code
+    }
+
+    fn emit_loop_start(&self, id: usize) -> String {
+        formatdoc! {"
+
+            // TokenType::OpenBracket
+            {load} w9, [x19]
+            {branch}
+
+            loop_{id}:
+
+            ", load = self.load(), branch = self.branch_sentinel(true, &format!("after_loop_{id}"))}
+    }
+
+    fn emit_loop_end(&self, id: usize) -> String {
+        formatdoc! {"
+
+            // TokenType::CloseBracket
+            {load} w9, [x19]
+            {branch}
+
+            after_loop_{id}:
+        ", load = self.load(), branch = self.branch_sentinel(false, &format!("loop_{id}"))}
+    }
+
+    fn emit_clear(&self) -> String {
+        formatdoc! {"
+            // TokenType::Clear
+            {store} wzr, [x19]
+        ", store = self.store()}
+    }
+
+    fn emit_set_value(&self, value: isize) -> String {
+        formatdoc! {"
+            // TokenType::SetValue
+            ldr w9, ={value}
+            {store} w9, [x19]
+        ", store = self.store(), value = self.constant(value)}
+    }
+
+    fn emit_mul_add(&self, offset: isize, factor: isize) -> String {
+        formatdoc! {"
+            // TokenType::MulAdd
+            {load} w9, [x19]
+            ldr w10, ={factor}
+            mul w9, w9, w10
+            {target}{load} w10, [x11]
+            add w10, w10, w9
+            {store} w10, [x11]
+        ", load = self.load(), store = self.store(), target = self.target(offset),
+        factor = self.constant(factor)}
+    }
+
+    fn emit_add_at(&self, offset: isize, delta: isize) -> String {
+        formatdoc! {"
+            // TokenType::AddAt
+            {target}{load} w10, [x11]
+            {add}{store} w10, [x11]
+        ", load = self.load(), store = self.store(), target = self.target(offset),
+        add = self.immediate("add", "w10", self.constant(delta), "w12")}
+    }
+
+    fn emit_scan(&self, step: isize, loc: usize) -> String {
+        let shift = if step > 0 {
+            self.emit_shift_right(step.unsigned_abs(), loc)
+        } else {
+            self.emit_shift_left(step.unsigned_abs(), loc)
+        };
+        formatdoc! {"
+            // TokenType::Scan
+            scan_{loc}:
+            {load} w9, [x19]
+            {branch}
+            {shift}b scan_{loc}
+            after_scan_{loc}:
+        ", load = self.load(), branch = self.branch_sentinel(true, &format!("after_scan_{loc}"))}
+    }
+
+    fn emit_exit(&self) -> String {
+        indoc! {"
+            // TokenType::Eof
+            b EXIT
+        "}
+        .to_string()
+    }
+
+    fn emit_halt(&self) -> String {
+        indoc! {"
+            // TokenType::Halt
+            b EXIT
+        "}
+        .to_string()
+    }
+
+    fn emit_comment(&self, text: &str) -> String {
+        // A semicolon separates statements in AArch64 assembly
+        format!("// {text}\n")
+    }
+
+    fn emit_function(&self, name: &str) -> Option<(String, String, String)> {
+        // The calls to the helpers overwrite the link register, so it is kept on the stack
+        Some((
+            format!("bl {name}\n"),
+            format!("\n{name}:\nstr x30, [sp, #-16]!\n"),
+            String::from("ldr x30, [sp], #16\nret\n"),
+        ))
+    }
+}
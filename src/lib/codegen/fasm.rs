@@ -0,0 +1,655 @@
+use super::CodeGen;
+use crate::compiler::CompilerSettings;
+use crate::interpreter::CellWidth;
+use indoc::{formatdoc, indoc};
+
+/// The system calls the fasm target does its IO with
+///
+/// The default is the Linux x86-64 ABI. Another table makes the output run on systems with other
+/// syscall numbers or calling conventions, like a hobby OS. The syscall number is passed in
+/// `number_reg` and the arguments in `arg_reg`, `ptr_reg` and `len_reg`, in this order.
+///
+/// # Fields
+/// * `read` - The number of the syscall reading from a file descriptor
+/// * `write` - The number of the syscall writing to a file descriptor
+/// * `exit` - The number of the syscall ending the process
+/// * `stdin` - The file descriptor of the standard input
+/// * `stdout` - The file descriptor of the standard output
+/// * `number_reg` - The register of the syscall number
+/// * `arg_reg` - The register of the first argument, the file descriptor or the exit status
+/// * `ptr_reg` - The register of the second argument, the address of the buffer
+/// * `len_reg` - The register of the third argument, the length of the buffer
+/// * `instruction` - The instruction making the syscall
+///
+/// # Example
+/// ```
+/// use rbfc::codegen::{CodeGen, FasmBackend, SyscallAbi};
+/// use rbfc::compiler::CompilerSettings;
+///
+/// let settings = CompilerSettings {
+///     syscall_abi: SyscallAbi {
+///         write: 4,
+///         instruction: String::from("int 0x80"),
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+/// let header = FasmBackend::new(&settings).header();
+/// assert!(header.contains("SYS_write = 4\n"));
+/// assert!(header.contains("int 0x80\n"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyscallAbi {
+    pub read: u64,
+    pub write: u64,
+    pub exit: u64,
+    pub stdin: u64,
+    pub stdout: u64,
+    pub number_reg: String,
+    pub arg_reg: String,
+    pub ptr_reg: String,
+    pub len_reg: String,
+    pub instruction: String,
+}
+
+impl Default for SyscallAbi {
+    fn default() -> Self {
+        SyscallAbi {
+            read: 0,
+            write: 1,
+            exit: 60,
+            stdin: 0,
+            stdout: 1,
+            number_reg: String::from("rax"),
+            arg_reg: String::from("rdi"),
+            ptr_reg: String::from("rsi"),
+            len_reg: String::from("rdx"),
+            instruction: String::from("syscall"),
+        }
+    }
+}
+
+/// The fasm backend
+///
+/// This backend emits x86-64 assembly for the fasm assembler, which assembles into an ELF64
+/// executable for Linux. The data pointer is kept in the `ptr_register`, `r12` by default, and
+/// points to the lowest byte of the current cell. The IO is done with the syscalls of the
+/// `syscall_abi` setting, see `SyscallAbi`. With `buffer_output` the output is collected in a
+/// buffer of `OUTPUT_SIZE` bytes, which is written when it is full, before reading input and at
+/// exit.
+///
+/// With `emit_as_function` an ELF64 object is emitted instead, which defines the function
+/// `void bf_main(void *tape)` following the System V calling convention to link into a larger
+/// program. The caller passes a tape of at least `TAPE_SIZE` cells, which has to be zeroed unless
+/// `assume_zeroed` is unset, and the function returns at the end of the program.
+///
+/// # Example
+/// ```
+/// use rbfc::codegen::{CodeGen, FasmBackend};
+/// use rbfc::compiler::CompilerSettings;
+///
+/// let settings = CompilerSettings::default();
+/// let backend = FasmBackend::new(&settings);
+/// assert_eq!(backend.emit_add(3), "; TokenType::Plus\nadd byte [r12], 3\n");
+/// ```
+#[derive(Debug)]
+pub struct FasmBackend<'a> {
+    settings: &'a CompilerSettings,
+}
+
+impl<'a> FasmBackend<'a> {
+    /// Create a new fasm backend
+    ///
+    /// # Arguments
+    /// * `settings` - The settings of the compiler, used for the wrap setting and the tape
+    pub fn new(settings: &'a CompilerSettings) -> FasmBackend<'a> {
+        FasmBackend { settings }
+    }
+
+    /// The size operand of a memory access to a cell
+    pub(super) fn size(&self) -> &'static str {
+        match self.settings.tape.cell_width {
+            CellWidth::U8 => "byte",
+            CellWidth::U16 => "word",
+            CellWidth::U32 => "dword",
+        }
+    }
+
+    /// The register the value of a cell is multiplied in, the lower part of `eax`
+    fn register(&self) -> &'static str {
+        match self.settings.tape.cell_width {
+            CellWidth::U8 => "al",
+            CellWidth::U16 => "ax",
+            CellWidth::U32 => "eax",
+        }
+    }
+
+    /// The register the data pointer is kept in, set by `ptr_register`
+    pub(super) fn dp(&self) -> &str {
+        &self.settings.ptr_register
+    }
+
+    /// The register the start of the tape is kept in with `emit_as_function`, which is `r13`
+    /// unless that holds the data pointer
+    fn start(&self) -> &'static str {
+        if self.dp() == "r13" {
+            "r12"
+        } else {
+            "r13"
+        }
+    }
+
+    /// The number of bytes `n` cells take up
+    pub(super) fn cells(&self, n: usize) -> usize {
+        n * self.settings.tape.cell_width.bytes()
+    }
+
+    /// The expression for the number of bytes the tape takes up
+    pub(super) fn tape_bytes(&self) -> String {
+        match self.settings.tape.cell_width.bytes() {
+            1 => String::from("TAPE_SIZE"),
+            bytes => format!("TAPE_SIZE * {bytes}"),
+        }
+    }
+
+    /// The code of a `Scan` by `step` cells, with `shift` moving the data pointer by one step
+    ///
+    /// Scanning 8 bit cells one by one for the loop sentinel is what `repne scasb` does, so it is
+    /// used when the scan can't wrap around or leave the tape. Otherwise the cells are checked in a
+    /// loop around `shift`.
+    pub(super) fn scan(&self, step: isize, loc: usize, shift: &str) -> String {
+        let dp = self.dp();
+        let wraps = if step > 0 {
+            self.settings.tape.wrap_right
+        } else {
+            self.settings.tape.wrap_left
+        };
+        // `scasb` can't check the bounds, so with `bounds_check` the cells are checked in a loop
+        if self.settings.tape.cell_width == CellWidth::U8
+            && step.abs() == 1
+            && !wraps
+            && !self.settings.bounds_check
+        {
+            // `scasb` moves `rdi` past the sentinel, backwards with the direction flag set
+            let (set_direction, clear_direction, back) = if step > 0 {
+                ("", "", "- 1")
+            } else {
+                ("std\n", "cld\n", "+ 1")
+            };
+            let sentinel = match self.settings.loop_sentinel {
+                0 => String::from("xor eax, eax"),
+                sentinel => format!("mov eax, {sentinel}"),
+            };
+            formatdoc! {"
+                ; TokenType::Scan
+                mov rdi, {dp}
+                {sentinel}
+                mov rcx, -1
+                {set_direction}repne scasb
+                {clear_direction}lea {dp}, [rdi {back}]
+            "}
+        } else {
+            formatdoc! {"
+                ; TokenType::Scan
+                scan_{loc}:
+                cmp {size} [{dp}], {sentinel}
+                je after_scan_{loc}
+                {shift}jmp scan_{loc}
+                after_scan_{loc}:
+            ", size = self.size(), sentinel = self.settings.loop_sentinel}
+        }
+    }
+
+    /// The code jumping to `TAPE_ERROR` if the data pointer is past the last cell
+    fn check_end(&self) -> String {
+        let dp = self.dp();
+        let start = self.start();
+        if self.settings.emit_as_function {
+            formatdoc! {"
+                lea rax, [{start} + {tape_bytes}]
+                cmp {dp}, rax
+                jae TAPE_ERROR
+            ", tape_bytes = self.tape_bytes()}
+        } else {
+            formatdoc! {"
+                cmp {dp}, (TAPE + {tape_bytes})
+                jae TAPE_ERROR
+            ", tape_bytes = self.tape_bytes()}
+        }
+    }
+
+    /// The code jumping to `TAPE_ERROR` if the data pointer is before the first cell
+    fn check_start(&self) -> String {
+        let dp = self.dp();
+        let start = if self.settings.emit_as_function {
+            self.start()
+        } else {
+            "TAPE"
+        };
+        format!("cmp {dp}, {start}\njb TAPE_ERROR\n")
+    }
+
+    /// The code zeroing the tape starting at the data pointer, empty with `assume_zeroed`
+    pub(super) fn zero_tape(&self) -> String {
+        let dp = self.dp();
+        if self.settings.assume_zeroed {
+            return String::new();
+        }
+        formatdoc! {"
+            ; Zero the tape
+            mov rdi, {dp}
+            xor eax, eax
+            mov rcx, {tape_bytes}
+            rep stosb
+        ", tape_bytes = self.tape_bytes()}
+    }
+}
+
+impl CodeGen for FasmBackend<'_> {
+    fn header(&self) -> String {
+        let dp = self.dp();
+        let start = self.start();
+        let header = if self.settings.emit_as_function {
+            indoc! {"
+                format ELF64
+
+                public bf_main
+
+                section '.text' executable
+
+                "}
+        } else {
+            indoc! {"
+                format ELF64 executable 3
+
+                "}
+        };
+
+        let abi = &self.settings.syscall_abi;
+        let constants = formatdoc! {"
+            ; Helper functions
+            SYS_read = {read}
+            SYS_write = {write}
+            SYS_exit = {exit}
+
+            STDIN = {stdin}
+            STDOUT = {stdout}
+
+            ", read = abi.read, write = abi.write, exit = abi.exit, stdin = abi.stdin,
+        stdout = abi.stdout};
+
+        let SyscallAbi {
+            number_reg: number,
+            arg_reg: arg,
+            ptr_reg: ptr,
+            len_reg: len,
+            instruction: syscall,
+            ..
+        } = abi;
+        let io_functions = if self.settings.buffer_output {
+            // A full buffer is flushed with a tail jump, so `FLUSH_OUTPUT` returns to the caller
+            formatdoc! {"
+                WRITE_TO_STDOUT:
+                mov al, [{dp}]
+                mov rcx, [OUTPUT_LEN]
+                lea rdx, [OUTPUT]
+                mov [rdx + rcx], al
+                inc rcx
+                mov [OUTPUT_LEN], rcx
+                cmp rcx, OUTPUT_SIZE
+                je FLUSH_OUTPUT
+                ret
+
+                FLUSH_OUTPUT:
+                mov {number}, SYS_write
+                mov {arg}, STDOUT
+                lea {ptr}, [OUTPUT]
+                mov {len}, [OUTPUT_LEN]
+                {syscall}
+                mov qword [OUTPUT_LEN], 0
+                ret
+
+                READ_FROM_STDIN:
+                call FLUSH_OUTPUT
+                mov {number}, SYS_read
+                mov {arg}, STDIN
+                mov {ptr}, {dp}
+                mov {len}, 1
+                {syscall}
+                ret
+
+                "}
+        } else {
+            formatdoc! {"
+                WRITE_TO_STDOUT:
+                mov {number}, SYS_write
+                mov {arg}, STDOUT
+                mov {ptr}, {dp}
+                mov {len}, 1
+                {syscall}
+                ret
+
+                READ_FROM_STDIN:
+                mov {number}, SYS_read
+                mov {arg}, STDIN
+                mov {ptr}, {dp}
+                mov {len}, 1
+                {syscall}
+                ret
+
+                "}
+        };
+
+        let flush = if self.settings.buffer_output {
+            "call FLUSH_OUTPUT\n"
+        } else {
+            ""
+        };
+        // As a function `EXIT` drops its own return address and returns from `bf_main`
+        let exit = if self.settings.emit_as_function {
+            formatdoc! {"
+                EXIT:
+                {flush}add rsp, 8
+                pop {start}
+                pop {dp}
+                ret
+            "}
+        } else {
+            let status = if self.settings.exit_from_cell {
+                format!("movzx {arg}, byte [TAPE]")
+            } else {
+                format!("mov {arg}, 0")
+            };
+            formatdoc! {"
+                EXIT:
+                {flush}mov {number}, SYS_exit
+                {status}
+                {syscall}
+            "}
+        };
+
+        // Leaving the tape ends the process even from `bf_main`, as its caller can't recover
+        let tape_error = if self.settings.bounds_check {
+            formatdoc! {"
+
+                TAPE_ERROR:
+                {flush}mov {number}, SYS_exit
+                mov {arg}, 3
+                {syscall}
+            "}
+        } else {
+            String::new()
+        };
+
+        // The tape is passed in `rdi`, its start is kept in another register for wrapping around
+        let main = if self.settings.emit_as_function {
+            formatdoc! {"
+
+                bf_main:
+                push {dp}
+                push {start}
+                mov {dp}, rdi
+                mov {start}, rdi
+                "}
+        } else {
+            formatdoc! {"
+                segment readable executable
+                entry {entry}
+
+                {entry}:
+                mov {dp}, (TAPE)
+                ", entry = self.settings.entry_symbol}
+        };
+
+        let zero_tape = self.zero_tape();
+        format!("{header}{constants}{io_functions}{exit}{tape_error}{main}{zero_tape}")
+    }
+
+    fn footer(&self) -> String {
+        // The initialized length comes first, so the reserved space doesn't take up the file
+        let output_buffer = if self.settings.buffer_output {
+            indoc! {"
+                OUTPUT_LEN dq 0
+                OUTPUT_SIZE = 4096
+                OUTPUT rb OUTPUT_SIZE
+            "}
+        } else {
+            ""
+        };
+        if self.settings.emit_as_function {
+            // The tape is owned by the caller, only its size is needed to wrap around
+            let data = if self.settings.buffer_output {
+                format!("section '.data' writeable\n{output_buffer}")
+            } else {
+                String::new()
+            };
+            return formatdoc! {"
+
+                {data}TAPE_SIZE = {tape_size}
+            ", tape_size = self.settings.tape.tape_size};
+        }
+        // `rd` reserves four bytes per cell, which fits every cell width. Reserved data is not
+        // stored in the file, the loader maps it zeroed like the BSS section
+        formatdoc! {"
+
+            segment readable writeable
+            {output_buffer}TAPE_SIZE = {tape_size}
+            TAPE rd TAPE_SIZE
+        ", tape_size = self.settings.tape.tape_size}
+    }
+
+    fn emit_add(&self, n: usize) -> String {
+        let dp = self.dp();
+        formatdoc! {"
+            ; TokenType::Plus
+            add {size} [{dp}], {n}
+        ", size = self.size(), n = n as u32 & self.settings.tape.cell_width.mask()}
+    }
+
+    fn emit_sub(&self, n: usize) -> String {
+        let dp = self.dp();
+        formatdoc! {"
+            ; TokenType::Minus
+            sub {size} [{dp}], {n}
+        ", size = self.size(), n = n as u32 & self.settings.tape.cell_width.mask()}
+    }
+
+    fn emit_shift_right(&self, n: usize, loc: usize) -> String {
+        let n = self.settings.shift_cells(n, true);
+        let dp = self.dp();
+        let start = self.start();
+        let n = self.cells(n);
+        if self.settings.tape.wrap_right && self.settings.emit_as_function {
+            formatdoc! {"
+                ; TokenType::ShiftRight
+                add {dp}, {n}
+                lea rax, [{start} + {tape_bytes}]
+                cmp {dp}, rax
+                jb no_wrap_{loc}
+                sub {dp}, {tape_bytes}
+                no_wrap_{loc}:
+            ", tape_bytes = self.tape_bytes()}
+        } else if self.settings.tape.wrap_right {
+            formatdoc! {"
+                ; TokenType::ShiftRight
+                add {dp}, {n}
+                cmp {dp}, (TAPE + {tape_bytes})
+                jb no_wrap_{loc}
+                sub {dp}, {tape_bytes}
+                no_wrap_{loc}:
+            ", tape_bytes = self.tape_bytes()}
+        } else if self.settings.bounds_check {
+            format!(
+                "; TokenType::ShiftRight\nadd {dp}, {n}\n{}",
+                self.check_end()
+            )
+        } else {
+            formatdoc! {"
+                ; TokenType::ShiftRight
+                add {dp}, {n}
+            "}
+        }
+    }
+
+    fn emit_shift_left(&self, n: usize, loc: usize) -> String {
+        let n = self.settings.shift_cells(n, false);
+        let dp = self.dp();
+        let start = self.start();
+        let n = self.cells(n);
+        if self.settings.tape.wrap_left && self.settings.emit_as_function {
+            formatdoc! {"
+                ; TokenType::ShiftLeft
+                sub {dp}, {n}
+                cmp {dp}, {start}
+                jae no_wrap_{loc}
+                add {dp}, {tape_bytes}
+                no_wrap_{loc}:
+            ", tape_bytes = self.tape_bytes()}
+        } else if self.settings.tape.wrap_left {
+            formatdoc! {"
+                ; TokenType::ShiftLeft
+                sub {dp}, {n}
+                cmp {dp}, TAPE
+                jae no_wrap_{loc}
+                add {dp}, {tape_bytes}
+                no_wrap_{loc}:
+            ", tape_bytes = self.tape_bytes()}
+        } else if self.settings.bounds_check {
+            format!(
+                "; TokenType::ShiftLeft\nsub {dp}, {n}\n{}",
+                self.check_start()
+            )
+        } else {
+            formatdoc! {"
+                ; TokenType::ShiftLeft
+                sub {dp}, {n}
+            "}
+        }
+    }
+
+    fn emit_output(&self, n: usize) -> String {
+        let mut code = String::from("; TokenType::Dot\n");
+        for _ in 0..n {
+            code.push_str("  call WRITE_TO_STDOUT\n");
+        }
+        code
+    }
+
+    fn emit_input(&self, n: usize) -> String {
+        let dp = self.dp();
+        let mut code = String::from("; TokenType::Comma\n");
+        for _ in 0..n {
+            // Only the lowest byte is read, so wider cells are cleared first
+            if self.settings.tape.cell_width != CellWidth::U8 {
+                code.push_str(&format!("mov {} [{dp}], 0\n", self.size()));
+            }
+            code.push_str(&formatdoc! {"
+                call READ_FROM_STDIN
+                mov rax, [{dp}]
+            "});
+        }
+        code
+    }
+
+    fn emit_loop_start(&self, id: usize) -> String {
+        let dp = self.dp();
+        formatdoc! {"
+
+            ; TokenType::OpenBracket
+            cmp {size} [{dp}], {sentinel}
+            je after_loop_{id}
+
+            loop_{id}:
+
+            ", size = self.size(), sentinel = self.settings.loop_sentinel}
+    }
+
+    fn emit_loop_end(&self, id: usize) -> String {
+        let dp = self.dp();
+        formatdoc! {"
+
+            ; TokenType::CloseBracket
+            cmp {size} [{dp}], {sentinel}
+            jne loop_{id}
+
+            after_loop_{id}:
+        ", size = self.size(), sentinel = self.settings.loop_sentinel}
+    }
+
+    fn emit_clear(&self) -> String {
+        let dp = self.dp();
+        formatdoc! {"
+            ; TokenType::Clear
+            mov {size} [{dp}], 0
+        ", size = self.size()}
+    }
+
+    fn emit_set_value(&self, value: isize) -> String {
+        let dp = self.dp();
+        formatdoc! {"
+            ; TokenType::SetValue
+            mov {size} [{dp}], {value}
+        ", size = self.size(), value = value as u32 & self.settings.tape.cell_width.mask()}
+    }
+
+    fn emit_mul_add(&self, offset: isize, factor: isize) -> String {
+        let dp = self.dp();
+        let load = match self.settings.tape.cell_width {
+            CellWidth::U32 => format!("mov eax, dword [{dp}]"),
+            _ => format!("movzx eax, {} [{dp}]", self.size()),
+        };
+        formatdoc! {"
+            ; TokenType::MulAdd
+            {load}
+            imul eax, eax, {factor}
+            add {size} [{dp}{offset:+}], {register}
+        ", offset = offset * self.settings.tape.cell_width.bytes() as isize,
+        size = self.size(), register = self.register()}
+    }
+
+    fn emit_add_at(&self, offset: isize, delta: isize) -> String {
+        let dp = self.dp();
+        formatdoc! {"
+            ; TokenType::AddAt
+            add {size} [{dp}{offset:+}], {delta}
+        ", offset = offset * self.settings.tape.cell_width.bytes() as isize,
+        size = self.size(), delta = delta as u32 & self.settings.tape.cell_width.mask()}
+    }
+
+    fn emit_scan(&self, step: isize, loc: usize) -> String {
+        let shift = if step > 0 {
+            self.emit_shift_right(step.unsigned_abs(), loc)
+        } else {
+            self.emit_shift_left(step.unsigned_abs(), loc)
+        };
+        self.scan(step, loc, &shift)
+    }
+
+    fn emit_exit(&self) -> String {
+        indoc! {"
+            ; TokenType::Eof
+            call EXIT
+        "}
+        .to_string()
+    }
+
+    fn emit_halt(&self) -> String {
+        indoc! {"
+            ; TokenType::Halt
+            call EXIT
+        "}
+        .to_string()
+    }
+
+    fn emit_function(&self, name: &str) -> Option<(String, String, String)> {
+        // As a function `EXIT` only drops its own return address, not the one of the subroutine
+        if self.settings.emit_as_function {
+            return None;
+        }
+        Some((
+            format!("call {name}\n"),
+            format!("\n{name}:\n"),
+            String::from("ret\n"),
+        ))
+    }
+}
@@ -0,0 +1,328 @@
+use super::CodeGen;
+use crate::compiler::CompilerSettings;
+use crate::interpreter::CellWidth;
+use indoc::{formatdoc, indoc};
+
+/// The GNU assembler backend for x86-64 in Intel syntax
+///
+/// This backend emits x86-64 assembly with `.intel_syntax noprefix` for the GNU assembler, which
+/// assembles and links into an executable for Linux. The data pointer is kept in `r12` and points
+/// to the lowest byte of the current cell, the start of the tape is kept in `r13` and its end in
+/// `r14`, which the wrapping moves compare against. The tape is reserved in the BSS section with
+/// `.lcomm`. IO is done with the Linux syscalls, so the `syscall_abi`, `buffer_output` and
+/// `emit_as_function` settings are ignored.
+///
+/// # Example
+/// ```
+/// use rbfc::codegen::{CodeGen, GasIntelBackend};
+/// use rbfc::compiler::CompilerSettings;
+///
+/// let settings = CompilerSettings::default();
+/// let backend = GasIntelBackend::new(&settings);
+/// assert_eq!(backend.emit_add(3), "# TokenType::Plus\nadd byte ptr [r12], 3\n");
+/// ```
+#[derive(Debug)]
+pub struct GasIntelBackend<'a> {
+    settings: &'a CompilerSettings,
+}
+
+impl<'a> GasIntelBackend<'a> {
+    /// Create a new GNU assembler backend
+    ///
+    /// # Arguments
+    /// * `settings` - The settings of the compiler, used for the wrap setting and the tape
+    pub fn new(settings: &'a CompilerSettings) -> GasIntelBackend<'a> {
+        GasIntelBackend { settings }
+    }
+
+    /// The size operand of a memory access to a cell
+    fn size(&self) -> &'static str {
+        match self.settings.tape.cell_width {
+            CellWidth::U8 => "byte ptr",
+            CellWidth::U16 => "word ptr",
+            CellWidth::U32 => "dword ptr",
+        }
+    }
+
+    /// The register the value of a cell is multiplied in, the lower part of `eax`
+    fn register(&self) -> &'static str {
+        match self.settings.tape.cell_width {
+            CellWidth::U8 => "al",
+            CellWidth::U16 => "ax",
+            CellWidth::U32 => "eax",
+        }
+    }
+
+    /// Wrap a constant around at the cell width
+    fn constant(&self, n: isize) -> u32 {
+        n as u32 & self.settings.tape.cell_width.mask()
+    }
+
+    /// The offset in bytes of the cell `offset` cells away from the current one
+    fn offset(&self, offset: isize) -> isize {
+        offset * self.settings.tape.cell_width.bytes() as isize
+    }
+}
+
+impl CodeGen for GasIntelBackend<'_> {
+    fn header(&self) -> String {
+        let constants = formatdoc! {"
+            .intel_syntax noprefix
+
+            # Helper functions
+            .equ SYS_read, 0
+            .equ SYS_write, 1
+            .equ SYS_exit, 60
+
+            .equ STDIN, 0
+            .equ STDOUT, 1
+
+            .equ TAPE_SIZE, {tape_size}
+            .equ TAPE_BYTES, TAPE_SIZE * {bytes}
+
+            .text
+            .global _start
+
+            ", tape_size = self.settings.tape.tape_size, bytes = self.settings.tape.cell_width.bytes()};
+
+        let status = if self.settings.exit_from_cell {
+            "movzx edi, byte ptr [r13]"
+        } else {
+            "mov edi, 0"
+        };
+        let helper_functions = formatdoc! {"
+            WRITE_TO_STDOUT:
+            mov eax, SYS_write
+            mov edi, STDOUT
+            mov rsi, r12
+            mov edx, 1
+            syscall
+            ret
+
+            READ_FROM_STDIN:
+            mov eax, SYS_read
+            mov edi, STDIN
+            mov rsi, r12
+            mov edx, 1
+            syscall
+            ret
+
+            EXIT:
+            mov eax, SYS_exit
+            {status}
+            syscall
+
+            "};
+        let tape_error = if self.settings.bounds_check {
+            indoc! {"
+                TAPE_ERROR:
+                mov eax, SYS_exit
+                mov edi, 3
+                syscall
+
+                "}
+        } else {
+            ""
+        };
+
+        let main = indoc! {"
+            _start:
+            lea r12, [rip + tape]
+            mov r13, r12
+            lea r14, [r12 + TAPE_BYTES]
+            "};
+
+        let zero_tape = if self.settings.assume_zeroed {
+            ""
+        } else {
+            indoc! {"
+                # Zero the tape
+                mov rdi, r13
+                xor eax, eax
+                mov ecx, TAPE_BYTES
+                rep stosb
+                "}
+        };
+
+        format!("{constants}{helper_functions}{tape_error}{main}{zero_tape}")
+    }
+
+    fn footer(&self) -> String {
+        indoc! {"
+
+            .section .bss
+            .lcomm tape, TAPE_BYTES
+        "}
+        .to_string()
+    }
+
+    fn emit_add(&self, n: usize) -> String {
+        formatdoc! {"
+            # TokenType::Plus
+            add {size} [r12], {n}
+        ", size = self.size(), n = self.constant(n as isize)}
+    }
+
+    fn emit_sub(&self, n: usize) -> String {
+        formatdoc! {"
+            # TokenType::Minus
+            sub {size} [r12], {n}
+        ", size = self.size(), n = self.constant(n as isize)}
+    }
+
+    fn emit_shift_right(&self, n: usize, loc: usize) -> String {
+        let n = self.settings.shift_cells(n, true);
+        let n = self.offset(n as isize);
+        let mut code = format!("# TokenType::ShiftRight\nadd r12, {n}\n");
+        if self.settings.tape.wrap_right {
+            code.push_str(&formatdoc! {"
+                cmp r12, r14
+                jb no_wrap_{loc}
+                sub r12, TAPE_BYTES
+                no_wrap_{loc}:
+            "});
+        } else if self.settings.bounds_check {
+            code.push_str("cmp r12, r14\njae TAPE_ERROR\n");
+        }
+        code
+    }
+
+    fn emit_shift_left(&self, n: usize, loc: usize) -> String {
+        let n = self.settings.shift_cells(n, false);
+        let n = self.offset(n as isize);
+        let mut code = format!("# TokenType::ShiftLeft\nsub r12, {n}\n");
+        if self.settings.tape.wrap_left {
+            code.push_str(&formatdoc! {"
+                cmp r12, r13
+                jae no_wrap_{loc}
+                add r12, TAPE_BYTES
+                no_wrap_{loc}:
+            "});
+        } else if self.settings.bounds_check {
+            code.push_str("cmp r12, r13\njb TAPE_ERROR\n");
+        }
+        code
+    }
+
+    fn emit_output(&self, n: usize) -> String {
+        let mut code = String::from("# TokenType::Dot\n");
+        for _ in 0..n {
+            code.push_str("call WRITE_TO_STDOUT\n");
+        }
+        code
+    }
+
+    fn emit_input(&self, n: usize) -> String {
+        let mut code = String::from("# TokenType::Comma\n");
+        for _ in 0..n {
+            // Only the lowest byte is read, so wider cells are cleared first
+            if self.settings.tape.cell_width != CellWidth::U8 {
+                code.push_str(&format!("mov {} [r12], 0\n", self.size()));
+            }
+            code.push_str("call READ_FROM_STDIN\n");
+        }
+        code
+    }
+
+    fn emit_loop_start(&self, id: usize) -> String {
+        formatdoc! {"
+
+            # TokenType::OpenBracket
+            cmp {size} [r12], {sentinel}
+            je after_loop_{id}
+
+            loop_{id}:
+
+            ", size = self.size(), sentinel = self.settings.loop_sentinel}
+    }
+
+    fn emit_loop_end(&self, id: usize) -> String {
+        formatdoc! {"
+
+            # TokenType::CloseBracket
+            cmp {size} [r12], {sentinel}
+            jne loop_{id}
+
+            after_loop_{id}:
+        ", size = self.size(), sentinel = self.settings.loop_sentinel}
+    }
+
+    fn emit_clear(&self) -> String {
+        formatdoc! {"
+            # TokenType::Clear
+            mov {size} [r12], 0
+        ", size = self.size()}
+    }
+
+    fn emit_set_value(&self, value: isize) -> String {
+        formatdoc! {"
+            # TokenType::SetValue
+            mov {size} [r12], {value}
+        ", size = self.size(), value = self.constant(value)}
+    }
+
+    fn emit_mul_add(&self, offset: isize, factor: isize) -> String {
+        let load = match self.settings.tape.cell_width {
+            CellWidth::U32 => "mov eax, dword ptr [r12]",
+            _ => &format!("movzx eax, {} [r12]", self.size()),
+        };
+        formatdoc! {"
+            # TokenType::MulAdd
+            {load}
+            imul eax, eax, {factor}
+            add {size} [r12{offset:+}], {register}
+        ", offset = self.offset(offset), size = self.size(), register = self.register()}
+    }
+
+    fn emit_add_at(&self, offset: isize, delta: isize) -> String {
+        formatdoc! {"
+            # TokenType::AddAt
+            add {size} [r12{offset:+}], {delta}
+        ", offset = self.offset(offset), size = self.size(), delta = self.constant(delta)}
+    }
+
+    fn emit_scan(&self, step: isize, loc: usize) -> String {
+        let shift = if step > 0 {
+            self.emit_shift_right(step.unsigned_abs(), loc)
+        } else {
+            self.emit_shift_left(step.unsigned_abs(), loc)
+        };
+        formatdoc! {"
+            # TokenType::Scan
+            scan_{loc}:
+            cmp {size} [r12], {sentinel}
+            je after_scan_{loc}
+            {shift}jmp scan_{loc}
+            after_scan_{loc}:
+        ", size = self.size(), sentinel = self.settings.loop_sentinel}
+    }
+
+    fn emit_exit(&self) -> String {
+        indoc! {"
+            # TokenType::Eof
+            jmp EXIT
+        "}
+        .to_string()
+    }
+
+    fn emit_halt(&self) -> String {
+        indoc! {"
+            # TokenType::Halt
+            jmp EXIT
+        "}
+        .to_string()
+    }
+
+    fn emit_comment(&self, text: &str) -> String {
+        // A semicolon separates statements for the GNU assembler on x86
+        format!("# {text}\n")
+    }
+
+    fn emit_function(&self, name: &str) -> Option<(String, String, String)> {
+        Some((
+            format!("call {name}\n"),
+            format!("\n{name}:\n"),
+            String::from("ret\n"),
+        ))
+    }
+}
@@ -0,0 +1,353 @@
+use super::CodeGen;
+use crate::compiler::CompilerSettings;
+use crate::interpreter::CellWidth;
+use indoc::formatdoc;
+use std::cell::Cell;
+
+/// The LLVM IR backend
+///
+/// This backend emits textual LLVM IR, which can be compiled with `llc` or `clang`. The data
+/// pointer is kept as an index into the global `@tape` in the `%dp` stack slot, so every op loads
+/// it, computes the cell address with `getelementptr` and stores it back if it moved. The values
+/// of every op are suffixed with a running id to keep them unique.
+///
+/// # Example
+/// ```
+/// use rbfc::codegen::{CodeGen, LlvmIrBackend};
+/// use rbfc::compiler::CompilerSettings;
+///
+/// let settings = CompilerSettings::default();
+/// let backend = LlvmIrBackend::new(&settings);
+/// assert!(backend.emit_add(3).contains("add i8 %value0, 3"));
+/// ```
+#[derive(Debug)]
+pub struct LlvmIrBackend<'a> {
+    settings: &'a CompilerSettings,
+    next_id: Cell<usize>,
+}
+
+impl<'a> LlvmIrBackend<'a> {
+    /// Create a new LLVM IR backend
+    ///
+    /// # Arguments
+    /// * `settings` - The settings of the compiler, used for the wrap setting and the tape
+    pub fn new(settings: &'a CompilerSettings) -> LlvmIrBackend<'a> {
+        LlvmIrBackend {
+            settings,
+            next_id: Cell::new(0),
+        }
+    }
+
+    /// Get a new id to suffix the values of an op with
+    fn id(&self) -> usize {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    /// The integer type of a cell
+    fn ty(&self) -> String {
+        format!("i{}", self.settings.tape.cell_width.bits())
+    }
+
+    /// The array type of the tape
+    fn tape(&self) -> String {
+        format!("[{} x {}]", self.settings.tape.tape_size, self.ty())
+    }
+
+    /// Wrap a constant around at the cell width
+    fn constant(&self, n: isize) -> u32 {
+        n as u32 & self.settings.tape.cell_width.mask()
+    }
+
+    /// Return from `main` with the exit status, which is the lowest byte of the first cell with
+    /// `exit_from_cell`, with every line indented by `indent`
+    fn ret(&self, indent: &str) -> String {
+        if !self.settings.exit_from_cell {
+            return format!("{indent}ret i32 0\n");
+        }
+        let i = self.id();
+        formatdoc! {"
+            {indent}%first{i} = load i8, ptr @tape
+            {indent}%status{i} = zext i8 %first{i} to i32
+            {indent}ret i32 %status{i}
+            "}
+    }
+
+    /// Load the data pointer and compute the address of the current cell into `%cell{i}`
+    fn cell(&self, i: usize) -> String {
+        formatdoc! {"
+              %dp{i} = load i64, ptr %dp
+              %cell{i} = getelementptr {tape}, ptr @tape, i64 0, i64 %dp{i}
+            ", tape = self.tape()}
+    }
+}
+
+impl CodeGen for LlvmIrBackend<'_> {
+    fn header(&self) -> String {
+        let mut code = formatdoc! {"
+            @tape = global {tape} zeroinitializer
+
+            declare i32 @putchar(i32)
+            declare i32 @getchar()
+            declare i32 @fflush(ptr)
+            declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)
+
+            define i32 @main() {{
+            entry:
+              %dp = alloca i64
+              store i64 0, ptr %dp
+            ", tape = self.tape()};
+        if !self.settings.assume_zeroed {
+            let bytes = self.settings.tape.tape_size * self.settings.tape.cell_width.bytes();
+            code.push_str(&format!(
+                "  call void @llvm.memset.p0.i64(ptr @tape, i8 0, i64 {bytes}, i1 false)\n"
+            ));
+        }
+        code
+    }
+
+    fn footer(&self) -> String {
+        // The block every move off the tape branches to with `bounds_check`
+        if self.settings.bounds_check {
+            return String::from("\ntape_error:\n  ret i32 3\n}\n");
+        }
+        String::from("}\n")
+    }
+
+    fn emit_add(&self, n: usize) -> String {
+        let i = self.id();
+        let cell = self.cell(i);
+        formatdoc! {"
+              ; TokenType::Plus
+            {cell}  %value{i} = load {ty}, ptr %cell{i}
+              %new{i} = add {ty} %value{i}, {n}
+              store {ty} %new{i}, ptr %cell{i}
+            ", ty = self.ty(), n = self.constant(n as isize)}
+    }
+
+    fn emit_sub(&self, n: usize) -> String {
+        let i = self.id();
+        let cell = self.cell(i);
+        formatdoc! {"
+              ; TokenType::Minus
+            {cell}  %value{i} = load {ty}, ptr %cell{i}
+              %new{i} = sub {ty} %value{i}, {n}
+              store {ty} %new{i}, ptr %cell{i}
+            ", ty = self.ty(), n = self.constant(n as isize)}
+    }
+
+    fn emit_shift_right(&self, n: usize, _loc: usize) -> String {
+        let n = self.settings.shift_cells(n, true);
+        let i = self.id();
+        let mut code = formatdoc! {"
+              ; TokenType::ShiftRight
+              %dp{i} = load i64, ptr %dp
+              %moved{i} = add i64 %dp{i}, {n}
+            "};
+        if self.settings.tape.wrap_right {
+            code.push_str(&formatdoc! {"
+                  %overflow{i} = icmp uge i64 %moved{i}, {size}
+                  %wrapped{i} = sub i64 %moved{i}, {size}
+                  %new{i} = select i1 %overflow{i}, i64 %wrapped{i}, i64 %moved{i}
+                  store i64 %new{i}, ptr %dp
+                ", size = self.settings.tape.tape_size});
+        } else if self.settings.bounds_check {
+            code.push_str(&formatdoc! {"
+                  %overflow{i} = icmp uge i64 %moved{i}, {size}
+                  br i1 %overflow{i}, label %tape_error, label %in_tape_{i}
+
+                in_tape_{i}:
+                  store i64 %moved{i}, ptr %dp
+                ", size = self.settings.tape.tape_size});
+        } else {
+            code.push_str(&format!("  store i64 %moved{i}, ptr %dp\n"));
+        }
+        code
+    }
+
+    fn emit_shift_left(&self, n: usize, _loc: usize) -> String {
+        let n = self.settings.shift_cells(n, false);
+        let i = self.id();
+        let mut code = formatdoc! {"
+              ; TokenType::ShiftLeft
+              %dp{i} = load i64, ptr %dp
+              %moved{i} = sub i64 %dp{i}, {n}
+            "};
+        if self.settings.tape.wrap_left {
+            code.push_str(&formatdoc! {"
+                  %underflow{i} = icmp ult i64 %dp{i}, {n}
+                  %wrapped{i} = add i64 %moved{i}, {size}
+                  %new{i} = select i1 %underflow{i}, i64 %wrapped{i}, i64 %moved{i}
+                  store i64 %new{i}, ptr %dp
+                ", size = self.settings.tape.tape_size});
+        } else if self.settings.bounds_check {
+            code.push_str(&formatdoc! {"
+                  %underflow{i} = icmp ult i64 %dp{i}, {n}
+                  br i1 %underflow{i}, label %tape_error, label %in_tape_{i}
+
+                in_tape_{i}:
+                  store i64 %moved{i}, ptr %dp
+                "});
+        } else {
+            code.push_str(&format!("  store i64 %moved{i}, ptr %dp\n"));
+        }
+        code
+    }
+
+    fn emit_output(&self, n: usize) -> String {
+        let i = self.id();
+        let cell = self.cell(i);
+        // Only the lowest byte of the cell is written by `putchar`
+        let char = match self.settings.tape.cell_width {
+            CellWidth::U32 => format!("%char{i} = and i32 %value{i}, 255"),
+            _ => format!("%char{i} = zext {} %value{i} to i32", self.ty()),
+        };
+        let mut code = formatdoc! {"
+              ; TokenType::Dot
+            {cell}  %value{i} = load {ty}, ptr %cell{i}
+              {char}
+            ", ty = self.ty()};
+        for _ in 0..n {
+            code.push_str(&format!("  call i32 @putchar(i32 %char{i})\n"));
+        }
+        code
+    }
+
+    fn emit_input(&self, n: usize) -> String {
+        let i = self.id();
+        let cell = self.cell(i);
+        // The output is buffered by the C library, so it is flushed for prompts to show up
+        let mut code = formatdoc! {"
+              ; TokenType::Comma
+              call i32 @fflush(ptr null)
+            {cell}"};
+        for n in 0..n {
+            code.push_str(&format!("  %char{i}_{n} = call i32 @getchar()\n"));
+            // A 32 bit cell already has the type returned by `getchar`
+            let value = match self.settings.tape.cell_width {
+                CellWidth::U32 => format!("%char{i}_{n}"),
+                _ => {
+                    let ty = self.ty();
+                    code.push_str(&format!(
+                        "  %value{i}_{n} = trunc i32 %char{i}_{n} to {ty}\n"
+                    ));
+                    format!("%value{i}_{n}")
+                }
+            };
+            code.push_str(&format!("  store {} {value}, ptr %cell{i}\n", self.ty()));
+        }
+        code
+    }
+
+    fn emit_loop_start(&self, id: usize) -> String {
+        let i = self.id();
+        let cell = self.cell(i);
+        formatdoc! {"
+              ; TokenType::OpenBracket
+            {cell}  %value{i} = load {ty}, ptr %cell{i}
+              %zero{i} = icmp eq {ty} %value{i}, {sentinel}
+              br i1 %zero{i}, label %after_loop_{id}, label %loop_{id}
+
+            loop_{id}:
+            ", ty = self.ty(), sentinel = self.settings.loop_sentinel}
+    }
+
+    fn emit_loop_end(&self, id: usize) -> String {
+        let i = self.id();
+        let cell = self.cell(i);
+        formatdoc! {"
+              ; TokenType::CloseBracket
+            {cell}  %value{i} = load {ty}, ptr %cell{i}
+              %zero{i} = icmp eq {ty} %value{i}, {sentinel}
+              br i1 %zero{i}, label %after_loop_{id}, label %loop_{id}
+
+            after_loop_{id}:
+            ", ty = self.ty(), sentinel = self.settings.loop_sentinel}
+    }
+
+    fn emit_clear(&self) -> String {
+        let i = self.id();
+        let cell = self.cell(i);
+        formatdoc! {"
+              ; TokenType::Clear
+            {cell}  store {ty} 0, ptr %cell{i}
+            ", ty = self.ty()}
+    }
+
+    fn emit_set_value(&self, value: isize) -> String {
+        let i = self.id();
+        let cell = self.cell(i);
+        formatdoc! {"
+              ; TokenType::SetValue
+            {cell}  store {ty} {value}, ptr %cell{i}
+            ", ty = self.ty(), value = self.constant(value)}
+    }
+
+    fn emit_mul_add(&self, offset: isize, factor: isize) -> String {
+        let i = self.id();
+        let cell = self.cell(i);
+        formatdoc! {"
+              ; TokenType::MulAdd
+            {cell}  %value{i} = load {ty}, ptr %cell{i}
+              %product{i} = mul {ty} %value{i}, {factor}
+              %target_dp{i} = add i64 %dp{i}, {offset}
+              %target{i} = getelementptr {tape}, ptr @tape, i64 0, i64 %target_dp{i}
+              %old{i} = load {ty}, ptr %target{i}
+              %new{i} = add {ty} %old{i}, %product{i}
+              store {ty} %new{i}, ptr %target{i}
+            ", ty = self.ty(), tape = self.tape()}
+    }
+
+    fn emit_add_at(&self, offset: isize, delta: isize) -> String {
+        let i = self.id();
+        formatdoc! {"
+              ; TokenType::AddAt
+              %dp{i} = load i64, ptr %dp
+              %target_dp{i} = add i64 %dp{i}, {offset}
+              %target{i} = getelementptr {tape}, ptr @tape, i64 0, i64 %target_dp{i}
+              %old{i} = load {ty}, ptr %target{i}
+              %new{i} = add {ty} %old{i}, {delta}
+              store {ty} %new{i}, ptr %target{i}
+            ", ty = self.ty(), tape = self.tape(), delta = self.constant(delta)}
+    }
+
+    fn emit_scan(&self, step: isize, loc: usize) -> String {
+        let i = self.id();
+        let cell = self.cell(i);
+        let shift = if step > 0 {
+            self.emit_shift_right(step.unsigned_abs(), loc)
+        } else {
+            self.emit_shift_left(step.unsigned_abs(), loc)
+        };
+        formatdoc! {"
+              ; TokenType::Scan
+              br label %scan_{loc}
+
+            scan_{loc}:
+            {cell}  %value{i} = load {ty}, ptr %cell{i}
+              %zero{i} = icmp eq {ty} %value{i}, {sentinel}
+              br i1 %zero{i}, label %after_scan_{loc}, label %scan_body_{loc}
+
+            scan_body_{loc}:
+            {shift}  br label %scan_{loc}
+
+            after_scan_{loc}:
+            ", ty = self.ty(), sentinel = self.settings.loop_sentinel}
+    }
+
+    fn emit_exit(&self) -> String {
+        format!("; TokenType::Eof\n{}", self.ret(""))
+    }
+
+    fn emit_halt(&self) -> String {
+        // A return has to end its basic block, so the following ops start a new one
+        let ret = self.ret("  ");
+        let i = self.id();
+        formatdoc! {"
+              ; TokenType::Halt
+            {ret}
+            halt_{i}:
+            "}
+    }
+}
@@ -0,0 +1,203 @@
+use crate::compiler::{CompilerError, CompilerSettings};
+use crate::interpreter::CellWidth;
+use crate::lexer::{Token, TokenType};
+use crate::parser::ParserError;
+
+/// Assemble the ops into raw x86-64 machine code
+///
+/// The code is a function `void bf_main(uint8_t *tape)` following the System V calling
+/// convention, like the fasm target with `emit_as_function`, which can be copied into an
+/// executable page and called. The data pointer is kept in `r12`, which is saved on entry. IO is
+/// done with the Linux `read` and `write` syscalls on stdin and stdout, reading at the end of the
+/// input leaves the cell unchanged. The `syscall_abi`, `buffer_output`, `exit_from_cell` and
+/// `ptr_register` settings are ignored.
+///
+/// # Arguments
+/// * `ops` - The ops to assemble, as returned by the parser or the optimizer
+/// * `settings` - The settings of the compiler, used for the tape and the loop sentinel
+///
+/// # Example
+/// ```
+/// use rbfc::codegen::machine_code::assemble;
+/// use rbfc::compiler::CompilerSettings;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new("+").parse().unwrap();
+/// let code = assemble(&ops, &CompilerSettings::default()).unwrap();
+/// // push r12, mov r12, rdi, add byte [r12], 1, pop r12, ret
+/// assert_eq!(
+///     code,
+///     [0x41, 0x54, 0x49, 0x89, 0xfc, 0x41, 0x80, 0x04, 0x24, 0x01, 0x41, 0x5c, 0xc3]
+/// );
+/// ```
+///
+/// # Errors
+/// Returns `CompilerError::UnsupportedMachineCode` for wrapping tapes, bounds checks and cells
+/// wider than 8 bits, which only the assembly targets support, and the same errors as
+/// `Compiler::compile_code` otherwise
+pub fn assemble(ops: &[Token], settings: &CompilerSettings) -> Result<Vec<u8>, CompilerError> {
+    if settings.tape.wrap_left || settings.tape.wrap_right {
+        return Err(CompilerError::UnsupportedMachineCode(String::from(
+            "a wrapping tape",
+        )));
+    }
+    if settings.bounds_check {
+        return Err(CompilerError::UnsupportedMachineCode(String::from(
+            "bounds checks",
+        )));
+    }
+    if settings.tape.cell_width != CellWidth::U8 {
+        return Err(CompilerError::UnsupportedMachineCode(format!(
+            "{} bit cells",
+            settings.tape.cell_width.bits()
+        )));
+    }
+
+    let mut assembler = Assembler {
+        code: Vec::new(),
+        sentinel: settings.loop_sentinel,
+    };
+    // push r12; mov r12, rdi
+    assembler.emit(&[0x41, 0x54, 0x49, 0x89, 0xfc]);
+    if !settings.assume_zeroed {
+        // mov rdi, r12; xor eax, eax; mov ecx, TAPE_SIZE; rep stosb
+        assembler.emit(&[0x4c, 0x89, 0xe7, 0x31, 0xc0, 0xb9]);
+        assembler.emit(&(settings.tape.tape_size as u32).to_le_bytes());
+        assembler.emit(&[0xf3, 0xaa]);
+    }
+
+    let mut jump_stack = Vec::new();
+    let mut exited = false;
+    for op in ops {
+        let size = || op.size.ok_or(CompilerError::UnexpectedNoneSize(op.loc));
+        match op.token_type {
+            TokenType::Plus => assembler.add_cell(size()? as u8),
+            TokenType::Minus => assembler.add_cell((size()? as u8).wrapping_neg()),
+            TokenType::ShiftRight => assembler.shift(size()? as isize),
+            TokenType::ShiftLeft => assembler.shift(-(size()? as isize)),
+            TokenType::Dot => {
+                for _ in 0..size()? {
+                    assembler.syscall(1, 1);
+                }
+            }
+            TokenType::Comma => {
+                for _ in 0..size()? {
+                    assembler.syscall(0, 0);
+                }
+            }
+            TokenType::OpenBracket => {
+                assembler.compare_sentinel();
+                // je after the loop, patched at its end
+                jump_stack.push(assembler.jump(&[0x0f, 0x84]));
+            }
+            TokenType::CloseBracket => {
+                let after_open = jump_stack.pop().ok_or(CompilerError::ParsingError(
+                    ParserError::UnmatchedBracket(op.loc),
+                ))?;
+                assembler.compare_sentinel();
+                // jne to the first op of the body
+                let target = assembler.jump(&[0x0f, 0x85]);
+                assembler.patch(target, after_open + 4);
+                assembler.patch(after_open, assembler.code.len());
+            }
+            TokenType::Clear => assembler.set_cell(0),
+            TokenType::SetValue { value } => assembler.set_cell(value as u8),
+            TokenType::MulAdd { offset, factor } => {
+                // movzx eax, byte [r12]; imul eax, eax, factor; add byte [r12 + offset], al
+                assembler.emit(&[0x41, 0x0f, 0xb6, 0x04, 0x24, 0x69, 0xc0]);
+                assembler.emit(&(factor as i32).to_le_bytes());
+                assembler.emit(&[0x41, 0x00, 0x84, 0x24]);
+                assembler.emit(&(offset as i32).to_le_bytes());
+            }
+            TokenType::AddAt { offset, delta } => {
+                // add byte [r12 + offset], delta
+                assembler.emit(&[0x41, 0x80, 0x84, 0x24]);
+                assembler.emit(&(offset as i32).to_le_bytes());
+                assembler.emit(&[delta as u8]);
+            }
+            TokenType::Scan { step } => {
+                let start = assembler.code.len();
+                assembler.compare_sentinel();
+                let after = assembler.jump(&[0x0f, 0x84]);
+                assembler.shift(step);
+                let back = assembler.jump(&[0xe9]);
+                assembler.patch(back, start);
+                assembler.patch(after, assembler.code.len());
+            }
+            TokenType::Debug => {}
+            TokenType::Halt => assembler.ret(),
+            TokenType::Eof => {
+                assembler.ret();
+                exited = true;
+                break;
+            }
+        }
+    }
+    if !exited {
+        assembler.ret();
+    }
+
+    Ok(assembler.code)
+}
+
+/// The machine code being assembled, with the instructions the ops are made of
+struct Assembler {
+    code: Vec<u8>,
+    sentinel: u8,
+}
+
+impl Assembler {
+    /// Append raw bytes
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// `add byte [r12], n`, which also subtracts with the negated `n`
+    fn add_cell(&mut self, n: u8) {
+        self.emit(&[0x41, 0x80, 0x04, 0x24, n]);
+    }
+
+    /// `mov byte [r12], value`
+    fn set_cell(&mut self, value: u8) {
+        self.emit(&[0x41, 0xc6, 0x04, 0x24, value]);
+    }
+
+    /// `add r12, n` or `sub r12, -n`
+    fn shift(&mut self, n: isize) {
+        let opcode = if n < 0 { 0xec } else { 0xc4 };
+        self.emit(&[0x49, 0x81, opcode]);
+        self.emit(&(n.unsigned_abs() as u32).to_le_bytes());
+    }
+
+    /// `cmp byte [r12], sentinel`
+    fn compare_sentinel(&mut self) {
+        self.emit(&[0x41, 0x80, 0x3c, 0x24, self.sentinel]);
+    }
+
+    /// A jump with a 32 bit displacement, returning the position of the displacement to patch
+    fn jump(&mut self, opcode: &[u8]) -> usize {
+        self.emit(opcode);
+        let position = self.code.len();
+        self.emit(&[0; 4]);
+        position
+    }
+
+    /// Point the displacement at `position` to `target`, relative to the end of the jump
+    fn patch(&mut self, position: usize, target: usize) {
+        let displacement = target as i64 - (position + 4) as i64;
+        self.code[position..position + 4].copy_from_slice(&(displacement as i32).to_le_bytes());
+    }
+
+    /// A syscall transferring the current cell, `read` (0) from stdin (0) or `write` (1) to
+    /// stdout (1)
+    fn syscall(&mut self, number: u8, fd: u8) {
+        // mov eax, number; mov edi, fd; mov rsi, r12; mov edx, 1; syscall
+        self.emit(&[0xb8, number, 0, 0, 0, 0xbf, fd, 0, 0, 0]);
+        self.emit(&[0x4c, 0x89, 0xe6, 0xba, 1, 0, 0, 0, 0x0f, 0x05]);
+    }
+
+    /// `pop r12; ret`
+    fn ret(&mut self) {
+        self.emit(&[0x41, 0x5c, 0xc3]);
+    }
+}
@@ -0,0 +1,99 @@
+//! The codegen module contains the backends the compiler can emit code with.
+//!
+//! The compiler walks the parsed ops and keeps track of the loops, while the backend turns every
+//! single op into code for its target through the `CodeGen` trait.
+//!
+//! # Example
+//! ```
+//! use rbfc::codegen::{CodeGen, FasmBackend};
+//! use rbfc::compiler::{Compiler, CompilerSettings};
+//!
+//! let settings = CompilerSettings::default();
+//! let compiler = Compiler::new("+++".to_string(), CompilerSettings::default()).unwrap();
+//! let backend = FasmBackend::new(&settings);
+//! assert_eq!(compiler.compile_with(&backend).unwrap(), compiler.compile_code().unwrap());
+//! ```
+
+pub mod arm64;
+pub mod fasm;
+pub mod gas_intel;
+pub mod llvm_ir;
+pub mod machine_code;
+pub mod pe_win64;
+
+pub use arm64::Arm64Backend;
+pub use fasm::{FasmBackend, SyscallAbi};
+pub use gas_intel::GasIntelBackend;
+pub use llvm_ir::LlvmIrBackend;
+pub use pe_win64::PeWin64Backend;
+
+/// The CodeGen trait is implemented by every backend of the compiler
+///
+/// Every method returns the code for a single op. Sizes are the number of times the op is
+/// repeated, offsets are relative to the data pointer. Loops are identified by an id that is
+/// unique within the program and shared by the start and the end of a loop.
+pub trait CodeGen {
+    /// The code in front of the first op, e.g. the file header, helpers and the entry point
+    fn header(&self) -> String;
+
+    /// The code after the last op, e.g. the data segment
+    fn footer(&self) -> String;
+
+    /// Add `n` to the current cell
+    fn emit_add(&self, n: usize) -> String;
+
+    /// Subtract `n` from the current cell
+    fn emit_sub(&self, n: usize) -> String;
+
+    /// Move the data pointer `n` cells to the right, `loc` is the location of the op
+    fn emit_shift_right(&self, n: usize, loc: usize) -> String;
+
+    /// Move the data pointer `n` cells to the left, `loc` is the location of the op
+    fn emit_shift_left(&self, n: usize, loc: usize) -> String;
+
+    /// Write the current cell to the output `n` times
+    fn emit_output(&self, n: usize) -> String;
+
+    /// Read from the input into the current cell `n` times
+    fn emit_input(&self, n: usize) -> String;
+
+    /// Skip to the end of the loop with the given id if the current cell is zero
+    fn emit_loop_start(&self, id: usize) -> String;
+
+    /// Jump back to the start of the loop with the given id if the current cell is not zero
+    fn emit_loop_end(&self, id: usize) -> String;
+
+    /// Set the current cell to zero
+    fn emit_clear(&self) -> String;
+
+    /// Set the current cell to `value`, wrapped around at the cell width
+    fn emit_set_value(&self, value: isize) -> String;
+
+    /// Add the current cell multiplied by `factor` to the cell at `offset`
+    fn emit_mul_add(&self, offset: isize, factor: isize) -> String;
+
+    /// Add `delta` to the cell at `offset`
+    fn emit_add_at(&self, offset: isize, delta: isize) -> String;
+
+    /// Move the data pointer by `step` cells until the current cell is zero, `loc` is the
+    /// location of the op
+    fn emit_scan(&self, step: isize, loc: usize) -> String;
+
+    /// Exit the program
+    fn emit_exit(&self) -> String;
+
+    /// Exit the program before its end, the code emitted after it is never reached
+    fn emit_halt(&self) -> String;
+
+    /// A comment on its own line, used to annotate the code
+    fn emit_comment(&self, text: &str) -> String {
+        format!("; {text}\n")
+    }
+
+    /// Turn the body of a top-level loop into the subroutine `name`, returning the call that
+    /// replaces the loop and the code in front of and after the body, or `None` if the backend
+    /// can't emit subroutines and the loop stays inline
+    fn emit_function(&self, _name: &str) -> Option<(String, String, String)> {
+        None
+    }
+}
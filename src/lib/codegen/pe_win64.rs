@@ -0,0 +1,283 @@
+use super::{CodeGen, FasmBackend};
+use crate::compiler::CompilerSettings;
+use crate::interpreter::CellWidth;
+use indoc::{formatdoc, indoc};
+
+/// The Windows x64 backend
+///
+/// This backend emits x86-64 assembly for the fasm assembler, which assembles into a PE64 console
+/// executable for Windows. Instead of Linux syscalls the IO goes through `GetStdHandle`,
+/// `ReadFile`, `WriteFile` and `ExitProcess` from `KERNEL32.DLL`, following the Windows x64
+/// calling convention. The data pointer is kept in the `ptr_register` like in the fasm backend,
+/// which the ops that don't depend on the platform are shared with.
+///
+/// # Example
+/// ```
+/// use rbfc::codegen::{CodeGen, PeWin64Backend};
+/// use rbfc::compiler::CompilerSettings;
+///
+/// let settings = CompilerSettings::default();
+/// let backend = PeWin64Backend::new(&settings);
+/// assert!(backend.header().starts_with("format PE64 console\n"));
+/// ```
+#[derive(Debug)]
+pub struct PeWin64Backend<'a> {
+    settings: &'a CompilerSettings,
+    fasm: FasmBackend<'a>,
+}
+
+impl<'a> PeWin64Backend<'a> {
+    /// Create a new Windows x64 backend
+    ///
+    /// # Arguments
+    /// * `settings` - The settings of the compiler, used for the wrap setting and the tape
+    pub fn new(settings: &'a CompilerSettings) -> PeWin64Backend<'a> {
+        PeWin64Backend {
+            settings,
+            fasm: FasmBackend::new(settings),
+        }
+    }
+}
+
+impl CodeGen for PeWin64Backend<'_> {
+    fn header(&self) -> String {
+        let dp = self.fasm.dp();
+        let header = formatdoc! {"
+            format PE64 console
+            entry {entry}
+
+            ", entry = self.settings.entry_symbol};
+
+        // Every helper reserves the 32 bytes of shadow space plus the fifth argument of
+        // `ReadFile`/`WriteFile`, which also aligns the stack to 16 bytes for the call
+        let status = if self.settings.exit_from_cell {
+            "movzx ecx, byte [TAPE]"
+        } else {
+            "xor ecx, ecx"
+        };
+        let helper_functions = formatdoc! {"
+            section '.text' code readable executable
+
+            ; Helper functions
+            STD_INPUT_HANDLE = -10
+            STD_OUTPUT_HANDLE = -11
+
+            WRITE_TO_STDOUT:
+            sub rsp, 40
+            mov rcx, [STDOUT]
+            mov rdx, {dp}
+            mov r8, 1
+            lea r9, [TRANSFERRED]
+            mov qword [rsp + 32], 0
+            call [WriteFile]
+            add rsp, 40
+            ret
+
+            READ_FROM_STDIN:
+            sub rsp, 40
+            mov rcx, [STDIN]
+            mov rdx, {dp}
+            mov r8, 1
+            lea r9, [TRANSFERRED]
+            mov qword [rsp + 32], 0
+            call [ReadFile]
+            add rsp, 40
+            ret
+
+            EXIT:
+            {status}
+            call [ExitProcess]
+
+            "};
+        let tape_error = if self.settings.bounds_check {
+            "TAPE_ERROR:\nmov ecx, 3\ncall [ExitProcess]\n\n"
+        } else {
+            ""
+        };
+
+        let main = formatdoc! {"
+            {entry}:
+            sub rsp, 40
+            mov ecx, STD_INPUT_HANDLE
+            call [GetStdHandle]
+            mov [STDIN], rax
+            mov ecx, STD_OUTPUT_HANDLE
+            call [GetStdHandle]
+            mov [STDOUT], rax
+            lea {dp}, [TAPE]
+            ", entry = self.settings.entry_symbol};
+
+        let zero_tape = self.fasm.zero_tape();
+        format!("{header}{helper_functions}{tape_error}{main}{zero_tape}")
+    }
+
+    fn footer(&self) -> String {
+        formatdoc! {"
+
+            section '.data' data readable writeable
+            STDIN dq 0
+            STDOUT dq 0
+            TRANSFERRED dd 0
+            TAPE_SIZE = {tape_size}
+            TAPE rb {tape_bytes}
+
+            section '.idata' import data readable writeable
+            dd 0, 0, 0, RVA kernel32_name, RVA kernel32_table
+            dd 0, 0, 0, 0, 0
+
+            kernel32_table:
+            GetStdHandle dq RVA _GetStdHandle
+            ReadFile dq RVA _ReadFile
+            WriteFile dq RVA _WriteFile
+            ExitProcess dq RVA _ExitProcess
+            dq 0
+
+            kernel32_name db 'KERNEL32.DLL', 0
+            _GetStdHandle dw 0
+            db 'GetStdHandle', 0
+            _ReadFile dw 0
+            db 'ReadFile', 0
+            _WriteFile dw 0
+            db 'WriteFile', 0
+            _ExitProcess dw 0
+            db 'ExitProcess', 0
+        ", tape_size = self.settings.tape.tape_size, tape_bytes = self.fasm.tape_bytes()}
+    }
+
+    fn emit_add(&self, n: usize) -> String {
+        self.fasm.emit_add(n)
+    }
+
+    fn emit_sub(&self, n: usize) -> String {
+        self.fasm.emit_sub(n)
+    }
+
+    fn emit_shift_right(&self, n: usize, loc: usize) -> String {
+        let n = self.settings.shift_cells(n, true);
+        let dp = self.fasm.dp();
+        if self.settings.tape.wrap_right {
+            formatdoc! {"
+                ; TokenType::ShiftRight
+                add {dp}, {n}
+                lea rax, [TAPE + {tape_bytes}]
+                cmp {dp}, rax
+                jb no_wrap_{loc}
+                sub {dp}, {tape_bytes}
+                no_wrap_{loc}:
+            ", n = self.fasm.cells(n), tape_bytes = self.fasm.tape_bytes()}
+        } else if self.settings.bounds_check {
+            formatdoc! {"
+                ; TokenType::ShiftRight
+                add {dp}, {n}
+                lea rax, [TAPE + {tape_bytes}]
+                cmp {dp}, rax
+                jae TAPE_ERROR
+            ", n = self.fasm.cells(n), tape_bytes = self.fasm.tape_bytes()}
+        } else {
+            self.fasm.emit_shift_right(n, loc)
+        }
+    }
+
+    fn emit_shift_left(&self, n: usize, loc: usize) -> String {
+        let n = self.settings.shift_cells(n, false);
+        let dp = self.fasm.dp();
+        if self.settings.tape.wrap_left {
+            formatdoc! {"
+                ; TokenType::ShiftLeft
+                sub {dp}, {n}
+                lea rax, [TAPE]
+                cmp {dp}, rax
+                jae no_wrap_{loc}
+                add {dp}, {tape_bytes}
+                no_wrap_{loc}:
+            ", n = self.fasm.cells(n), tape_bytes = self.fasm.tape_bytes()}
+        } else if self.settings.bounds_check {
+            formatdoc! {"
+                ; TokenType::ShiftLeft
+                sub {dp}, {n}
+                lea rax, [TAPE]
+                cmp {dp}, rax
+                jb TAPE_ERROR
+            ", n = self.fasm.cells(n)}
+        } else {
+            self.fasm.emit_shift_left(n, loc)
+        }
+    }
+
+    fn emit_output(&self, n: usize) -> String {
+        self.fasm.emit_output(n)
+    }
+
+    fn emit_input(&self, n: usize) -> String {
+        let dp = self.fasm.dp();
+        let mut code = String::from("; TokenType::Comma\n");
+        for _ in 0..n {
+            // Only the lowest byte is read, so wider cells are cleared first
+            if self.settings.tape.cell_width != CellWidth::U8 {
+                code.push_str(&format!("mov {} [{dp}], 0\n", self.fasm.size()));
+            }
+            code.push_str("call READ_FROM_STDIN\n");
+        }
+        code
+    }
+
+    fn emit_loop_start(&self, id: usize) -> String {
+        self.fasm.emit_loop_start(id)
+    }
+
+    fn emit_loop_end(&self, id: usize) -> String {
+        self.fasm.emit_loop_end(id)
+    }
+
+    fn emit_clear(&self) -> String {
+        self.fasm.emit_clear()
+    }
+
+    fn emit_set_value(&self, value: isize) -> String {
+        self.fasm.emit_set_value(value)
+    }
+
+    fn emit_mul_add(&self, offset: isize, factor: isize) -> String {
+        self.fasm.emit_mul_add(offset, factor)
+    }
+
+    fn emit_add_at(&self, offset: isize, delta: isize) -> String {
+        self.fasm.emit_add_at(offset, delta)
+    }
+
+    fn emit_scan(&self, step: isize, loc: usize) -> String {
+        let shift = if step > 0 {
+            self.emit_shift_right(step.unsigned_abs(), loc)
+        } else {
+            self.emit_shift_left(step.unsigned_abs(), loc)
+        };
+        self.fasm.scan(step, loc, &shift)
+    }
+
+    fn emit_exit(&self) -> String {
+        // `ExitProcess` needs the stack aligned with its shadow space reserved, which it is after
+        // a jump but not after a call pushing its return address
+        indoc! {"
+            ; TokenType::Eof
+            jmp EXIT
+        "}
+        .to_string()
+    }
+
+    fn emit_halt(&self) -> String {
+        indoc! {"
+            ; TokenType::Halt
+            jmp EXIT
+        "}
+        .to_string()
+    }
+
+    fn emit_function(&self, name: &str) -> Option<(String, String, String)> {
+        // The call misaligns the stack by its return address, which the helpers expect aligned
+        Some((
+            format!("call {name}\n"),
+            format!("\n{name}:\nsub rsp, 8\n"),
+            String::from("add rsp, 8\nret\n"),
+        ))
+    }
+}
@@ -1,6 +1,15 @@
-use crate::lexer::{Token, TokenType};
+use crate::codegen::machine_code;
+use crate::codegen::{
+    Arm64Backend, CodeGen, FasmBackend, GasIntelBackend, LlvmIrBackend, PeWin64Backend, SyscallAbi,
+};
+use crate::lexer::{LexerSettings, Token, TokenType};
+use crate::memory::TapeConfig;
+use crate::optimizer;
 use crate::parser::{Parser, ParserError};
-use indoc::{formatdoc, indoc};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
 use thiserror::Error;
 
 /// Error type for the compiler
@@ -15,22 +24,223 @@ pub enum CompilerError {
     UnexpectedNoneSize(usize),
     #[error("Unexpected end of file")]
     UnexpectedEof,
+    #[error("Invalid data pointer register {0}, expected one of {}", PTR_REGISTERS.join(", "))]
+    InvalidRegister(String),
+    #[error("The tape needs at least one cell")]
+    EmptyTape,
+    #[error("Error writing file: {0}")]
+    WriteError(String),
+    #[error("Machine code can't be emitted for {0}")]
+    UnsupportedMachineCode(String),
+}
+
+/// The registers the fasm and pe-win64 targets can keep the data pointer in
+///
+/// These are preserved across calls and syscalls on Linux and Windows, and none of them is used
+/// by the emitted code otherwise.
+pub const PTR_REGISTERS: [&str; 6] = ["rbx", "rbp", "r12", "r13", "r14", "r15"];
+
+impl From<ParserError> for CompilerError {
+    fn from(error: ParserError) -> Self {
+        CompilerError::ParsingError(error)
+    }
+}
+
+/// The target the compiler emits code for
+///
+/// # Variants
+/// * `Fasm` - x86-64 assembly for the fasm assembler, producing an ELF64 executable
+/// * `LlvmIr` - Textual LLVM IR, which can be compiled with `llc` or `clang`
+/// * `PeWin64` - x86-64 assembly for the fasm assembler, producing a PE64 executable for Windows
+/// * `Arm64` - AArch64 assembly for the GNU assembler, producing an executable for Linux
+/// * `GasIntel` - x86-64 assembly in Intel syntax for the GNU assembler, producing an executable
+///   for Linux
+///
+/// # Example
+/// ```
+/// use rbfc::compiler::Target;
+/// let target: Target = "llvm-ir".parse().unwrap();
+/// assert_eq!(target, Target::LlvmIr);
+/// assert_eq!(target.extension(), "ll");
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Target {
+    #[default]
+    Fasm,
+    LlvmIr,
+    PeWin64,
+    Arm64,
+    GasIntel,
+}
+
+impl Target {
+    /// The file extension of the code emitted for the target
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Target::Fasm => "asm",
+            Target::LlvmIr => "ll",
+            Target::PeWin64 => "asm",
+            Target::Arm64 => "s",
+            Target::GasIntel => "s",
+        }
+    }
+}
+
+impl std::str::FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fasm" => Ok(Target::Fasm),
+            "llvm-ir" => Ok(Target::LlvmIr),
+            "pe-win64" => Ok(Target::PeWin64),
+            "arm64" => Ok(Target::Arm64),
+            "gas-intel" => Ok(Target::GasIntel),
+            _ => Err(format!(
+                "Unknown target {s}, expected one of: fasm, llvm-ir, pe-win64, arm64, gas-intel"
+            )),
+        }
+    }
 }
 
 /// The settings for the compiler
 ///
-/// This struct is used to represent the settings for the compiler. It contains the wrap setting
-/// which is used to determine whether the tape should wrap around or not
+/// This struct is used to represent the settings for the compiler. It contains the tape which
+/// determines whether the tape should wrap around or not and the optimization level as well as
+/// the target to emit code for
 /// # Fields
-/// * `wrap` - Whether the tape should wrap around or not
+/// * `tape` - The size, wrapping and cell width of the tape, see `TapeConfig`. Its `grow_right`
+///   is ignored, the emitted tape always has `tape_size` cells
+/// * `opt_level` - Which optimizer passes to run before emitting
+///   * `0` - No optimizations
+///   * `1` - Runs like `+-+` are emitted as their net change and clear loops like `[-]` as a
+///     single store, which includes the value added right after it like in `[-]+++`. Scan loops
+///     like `[>]` are emitted as a scan for the next zero cell
+///   * `2` - Additionally multiply loops like `[->++<]` are emitted without a loop
+///   * `3` - Additionally straight-line code like `>+>-<<` is emitted as its net effect
+///
+///   Levels above 1 are treated as 1 when the tape wraps in either direction, as the offsets
+///   they introduce are not wrapped
+/// * `target` - The target to emit code for
+/// * `debug_commands` - Whether `#` is parsed as `TokenType::Debug`, which is emitted as a comment
+/// * `halt_command` - Whether `!` is parsed as `TokenType::Halt`, which exits the program
+/// * `buffer_output` - Whether the fasm target collects the output in a buffer instead of making a
+///   syscall for every byte. The LLVM IR target is already buffered by the C library and the
+///   pe-win64 target ignores this
+/// * `emit_as_function` - Whether the fasm target emits an object defining the function `bf_main`
+///   taking the tape as argument, instead of an executable. The other targets ignore this
+/// * `assume_zeroed` - Whether the tape is assumed to be zeroed at startup. The loader zeroes the
+///   tape of an executable, but the tape passed to `bf_main` may not be, so without this the
+///   tape is zeroed explicitly before the first op
+/// * `syscall_abi` - The syscalls the fasm target does its IO with, see `SyscallAbi`. The other
+///   targets ignore this
+/// * `entry_symbol` - The name of the entry point of an executable emitted by the fasm and
+///   pe-win64 targets. The other targets ignore this
+/// * `loop_sentinel` - The value of the current cell `[` skips the loop at and `]` ends it at,
+///   which is 0 in standard Brainfuck. The optimizer assumes 0, so with another value the code
+///   is not optimized
+/// * `exit_from_cell` - Whether an executable exits with the lowest byte of the first cell as its
+///   status instead of 0, e.g. for programs used as shell predicates. The fasm target ignores this
+///   with `emit_as_function`
+/// * `functions_per_loop` - Whether every top-level loop is emitted as a subroutine
+///   `loop_body_N` that is called in its place, so a profiler attributes the time spent in it to
+///   the loop. The llvm target ignores this, as does the fasm target with `emit_as_function`
+/// * `ptr_register` - The register the fasm and pe-win64 targets keep the data pointer in, one of
+///   `PTR_REGISTERS`
+/// * `bounds_check` - Whether moving the data pointer off the tape in a direction that doesn't
+///   wrap exits with status 3, like the interpreter fails with a tape error, instead of reaching
+///   the memory next to the tape. Only the moves are checked, not the offsets of optimized ops
+///
 /// # Example
 /// ```
-/// use rbfc::compiler::{CompilerSettings};
-/// let settings = CompilerSettings { wrap: true };
+/// use rbfc::codegen::SyscallAbi;
+/// use rbfc::compiler::{CompilerSettings, Target};
+/// use rbfc::memory::TapeConfig;
+/// let settings = CompilerSettings {
+///     tape: TapeConfig::default().wrap(true),
+///     opt_level: 1,
+///     target: Target::Fasm,
+///     debug_commands: false,
+///     halt_command: false,
+///     buffer_output: false,
+///     emit_as_function: false,
+///     assume_zeroed: true,
+///     syscall_abi: SyscallAbi::default(),
+///     entry_symbol: String::from("main"),
+///     loop_sentinel: 0,
+///     exit_from_cell: false,
+///     functions_per_loop: false,
+///     ptr_register: String::from("r12"),
+///     bounds_check: false,
+/// };
 /// ```
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CompilerSettings {
-    pub wrap: bool,
+    pub tape: TapeConfig,
+    pub opt_level: u8,
+    pub target: Target,
+    pub debug_commands: bool,
+    pub halt_command: bool,
+    pub buffer_output: bool,
+    pub emit_as_function: bool,
+    pub assume_zeroed: bool,
+    pub syscall_abi: SyscallAbi,
+    pub entry_symbol: String,
+    pub loop_sentinel: u8,
+    pub exit_from_cell: bool,
+    pub functions_per_loop: bool,
+    pub ptr_register: String,
+    pub bounds_check: bool,
+}
+
+impl Default for CompilerSettings {
+    fn default() -> Self {
+        CompilerSettings {
+            tape: TapeConfig::default(),
+            opt_level: 0,
+            target: Target::default(),
+            debug_commands: false,
+            halt_command: false,
+            buffer_output: false,
+            emit_as_function: false,
+            assume_zeroed: true,
+            syscall_abi: SyscallAbi::default(),
+            entry_symbol: String::from("main"),
+            loop_sentinel: 0,
+            exit_from_cell: false,
+            functions_per_loop: false,
+            ptr_register: String::from("r12"),
+            bounds_check: false,
+        }
+    }
+}
+
+impl From<&TapeConfig> for CompilerSettings {
+    fn from(config: &TapeConfig) -> Self {
+        CompilerSettings {
+            tape: *config,
+            ..Default::default()
+        }
+    }
+}
+
+impl CompilerSettings {
+    /// The number of cells a move of `n` cells to the right or left is emitted as
+    ///
+    /// On a tape that wraps in the direction of the move, it is reduced modulo the tape size, so
+    /// that the backends bring the data pointer back onto the tape by wrapping around once.
+    pub(crate) fn shift_cells(&self, n: usize, right: bool) -> usize {
+        let wraps = if right {
+            self.tape.wrap_right
+        } else {
+            self.tape.wrap_left
+        };
+        if wraps {
+            n % self.tape.tape_size
+        } else {
+            n
+        }
+    }
 }
 
 /// The compiler struct
@@ -45,8 +255,9 @@ pub struct CompilerSettings {
 /// use rbfc::compiler::{Compiler, CompilerSettings};
 /// let compiler = Compiler::new("+++".to_string(), CompilerSettings::default()).unwrap();
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Compiler {
+    code: String,
     ops: Vec<Token>,
     settings: CompilerSettings,
 }
@@ -64,20 +275,38 @@ impl Compiler {
     /// let compiler = Compiler::new("+++".to_string(), CompilerSettings::default() ).unwrap();
     /// ```
     /// # Errors
-    /// If the code cannot be parsed, a CompilerError::ParsingError will be returned
+    /// If the code cannot be parsed, a CompilerError::ParsingError will be returned. If the
+    /// `ptr_register` is not one of `PTR_REGISTERS`, a CompilerError::InvalidRegister will be
+    /// returned. If the `tape_size` is 0, a CompilerError::EmptyTape will be returned
     /// ```
     /// use rbfc::compiler::{Compiler, CompilerError, CompilerSettings};
     /// use rbfc::parser::ParserError;
     ///
     /// matches!(Compiler::new("+++[".to_string(), CompilerSettings::default()), Err(CompilerError::ParsingError(ParserError::UnmatchedBracket(3))));
     /// ```
-    pub fn new(code: String, settings: CompilerSettings) -> Result<Compiler, CompilerError> {
-        let mut parser = Parser::new(code);
-        let ops = match parser.parse() {
-            Ok(ops) => ops,
-            Err(e) => return Err(CompilerError::ParsingError(e)),
+    pub fn new(
+        code: impl Into<String>,
+        settings: CompilerSettings,
+    ) -> Result<Compiler, CompilerError> {
+        if !PTR_REGISTERS.contains(&settings.ptr_register.as_str()) {
+            return Err(CompilerError::InvalidRegister(settings.ptr_register));
+        }
+        if settings.tape.tape_size == 0 {
+            return Err(CompilerError::EmptyTape);
+        }
+        let code = code.into();
+        let lexer_settings = LexerSettings {
+            debug_commands: settings.debug_commands,
+            halt_command: settings.halt_command,
+            ..Default::default()
         };
-        Ok(Compiler { ops, settings })
+        let mut parser = Parser::with_settings(code.clone(), lexer_settings);
+        let ops = parser.parse()?;
+        Ok(Compiler {
+            code,
+            ops,
+            settings,
+        })
     }
 
     /// Compile the code
@@ -87,187 +316,497 @@ impl Compiler {
     /// ```
     /// use rbfc::compiler::{Compiler, CompilerError, CompilerSettings};
     /// let compiler = Compiler::new("+++".to_string(), CompilerSettings::default()).unwrap();
-    /// let asm = compiler.compile_code();
+    /// let asm = compiler.compile_code().unwrap();
     /// ```
-    pub fn compile_code(&self) -> String {
-        let mut assembly = String::new();
-        let header = indoc! {"
-            format ELF64 executable 3
-
-            "};
-
-        let helper_functions = indoc! {"
-            ; Helper functions
-            SYS_read = 0
-            SYS_write = 1
-            SYS_exit = 60
-
-            STDIN = 0
-            STDOUT = 1
-
-            WRITE_TO_STDOUT:
-            mov rax, SYS_write
-            mov rdi, STDOUT
-            mov rsi, r12
-            mov rdx, 1
-            syscall
-            ret
-
-            READ_FROM_STDIN:
-            mov rax, SYS_read
-            mov rdi, STDIN
-            mov rsi, r12
-            mov rdx, 1
-            syscall
-            ret
-
-            EXIT:
-            mov rax, SYS_exit
-            mov rdi, 0
-            syscall
-        "};
+    ///
+    /// # Errors
+    /// Returns an error if an op is missing its size or a bracket is unmatched, which the parser
+    /// already rules out
+    pub fn compile_code(&self) -> Result<String, CompilerError> {
+        match self.settings.target {
+            Target::Fasm => self.compile_with(&FasmBackend::new(&self.settings)),
+            Target::LlvmIr => self.compile_with(&LlvmIrBackend::new(&self.settings)),
+            Target::PeWin64 => self.compile_with(&PeWin64Backend::new(&self.settings)),
+            Target::Arm64 => self.compile_with(&Arm64Backend::new(&self.settings)),
+            Target::GasIntel => self.compile_with(&GasIntelBackend::new(&self.settings)),
+        }
+    }
 
-        let mut main = indoc! {"
-            segment readable executable
-            entry main
+    /// Compile the code with every op annotated by the source it was generated from
+    ///
+    /// Every block of generated code is preceded by a comment containing the source characters of
+    /// the op and their location in the source, which makes the output easier to follow.
+    /// # Example
+    /// ```
+    /// use rbfc::compiler::{Compiler, CompilerSettings};
+    /// let compiler = Compiler::new("+++".to_string(), CompilerSettings::default()).unwrap();
+    /// let asm = compiler.compile_annotated().unwrap();
+    /// assert!(asm.contains("; \"+++\" at 0\n; TokenType::Plus\n"));
+    /// ```
+    ///
+    /// # Errors
+    /// Returns the same errors as `compile_code`
+    pub fn compile_annotated(&self) -> Result<String, CompilerError> {
+        let emitted = match self.settings.target {
+            Target::Fasm => self.emit(&FasmBackend::new(&self.settings), true),
+            Target::LlvmIr => self.emit(&LlvmIrBackend::new(&self.settings), true),
+            Target::PeWin64 => self.emit(&PeWin64Backend::new(&self.settings), true),
+            Target::Arm64 => self.emit(&Arm64Backend::new(&self.settings), true),
+            Target::GasIntel => self.emit(&GasIntelBackend::new(&self.settings), true),
+        };
+        emitted.map(|(code, _)| code)
+    }
 
-            main:
-            mov r12, (TAPE)
-            "}
-        .to_string();
+    /// Compile the code with the given backend
+    ///
+    /// This function runs the optimizer passes enabled in the settings and walks the resulting
+    /// ops, letting the backend emit the code for each of them
+    /// # Arguments
+    /// * `backend` - The backend to emit the code with
+    /// # Example
+    /// ```
+    /// use rbfc::codegen::FasmBackend;
+    /// use rbfc::compiler::{Compiler, CompilerSettings};
+    /// let settings = CompilerSettings::default();
+    /// let compiler = Compiler::new("+++".to_string(), CompilerSettings::default()).unwrap();
+    /// let asm = compiler.compile_with(&FasmBackend::new(&settings)).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Returns the same errors as `compile_code`
+    pub fn compile_with(&self, backend: &dyn CodeGen) -> Result<String, CompilerError> {
+        self.emit(backend, false).map(|(code, _)| code)
+    }
+
+    /// Compile the code together with a map from the lines of the output back to the source
+    ///
+    /// The source map is written next to the output as a `.map` file with `--source-map`, to find
+    /// the source of the code a debugger stopped in.
+    /// # Example
+    /// ```
+    /// use rbfc::compiler::{Compiler, CompilerSettings};
+    /// let compiler = Compiler::new("+>-".to_string(), CompilerSettings::default()).unwrap();
+    /// let (asm, source_map) = compiler.compile_with_sourcemap().unwrap();
+    /// assert_eq!(asm, compiler.compile_code().unwrap());
+    /// let line = source_map.entries[1].lines.start;
+    /// assert_eq!(asm.lines().nth(line - 1), Some("; TokenType::ShiftRight"));
+    /// assert_eq!(source_map.loc_at_line(line), Some(1));
+    /// ```
+    ///
+    /// # Errors
+    /// Returns the same errors as `compile_code`
+    pub fn compile_with_sourcemap(&self) -> Result<(String, SourceMap), CompilerError> {
+        match self.settings.target {
+            Target::Fasm => self.emit(&FasmBackend::new(&self.settings), false),
+            Target::LlvmIr => self.emit(&LlvmIrBackend::new(&self.settings), false),
+            Target::PeWin64 => self.emit(&PeWin64Backend::new(&self.settings), false),
+            Target::Arm64 => self.emit(&Arm64Backend::new(&self.settings), false),
+            Target::GasIntel => self.emit(&GasIntelBackend::new(&self.settings), false),
+        }
+    }
+
+    /// Compile the code and write it to a file
+    ///
+    /// # Arguments
+    /// * `path` - The path of the file to write, which is created or replaced
+    ///
+    /// # Example
+    /// ```no_run
+    /// use rbfc::compiler::{Compiler, CompilerSettings};
+    /// use std::path::Path;
+    ///
+    /// let compiler = Compiler::new("+++", CompilerSettings::default()).unwrap();
+    /// compiler.compile_to_file(Path::new("out.asm")).unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Returns the same errors as `compile_code`, or `CompilerError::WriteError` with the path if
+    /// the file can't be written
+    pub fn compile_to_file(&self, path: &Path) -> Result<(), CompilerError> {
+        let code = self.compile_code()?;
+        std::fs::write(path, code).or(Err(CompilerError::WriteError(path.display().to_string())))
+    }
+
+    /// Compile the code into raw x86-64 machine code instead of assembly
+    ///
+    /// The bytes are a function taking the tape in `rdi`, see `codegen::machine_code::assemble`
+    /// for its calling convention and the settings it ignores.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::compiler::{Compiler, CompilerSettings};
+    ///
+    /// let compiler = Compiler::new("+", CompilerSettings::default()).unwrap();
+    /// let code = compiler.compile_machine_code().unwrap();
+    /// // add byte [r12], 1
+    /// assert!(code.windows(5).any(|bytes| bytes == [0x41, 0x80, 0x04, 0x24, 0x01]));
+    /// ```
+    ///
+    /// # Errors
+    /// Returns `CompilerError::UnsupportedMachineCode` for wrapping tapes, bounds checks and cells
+    /// wider than 8 bits, or `CompilerError::ParsingError` for unmatched brackets
+    pub fn compile_machine_code(&self) -> Result<Vec<u8>, CompilerError> {
+        machine_code::assemble(&self.optimized_ops(), &self.settings)
+    }
+
+    /// The ops the code is emitted for, after the optimizer passes of the `opt_level`
+    ///
+    /// Levels above 1 are treated as 1 when the tape wraps and every level as 0 with a
+    /// `loop_sentinel` other than 0, like when compiling. Runs of `<` and `>` are always folded
+    /// into their net offset if the tape wraps in both directions and `bounds_check` is off, see
+    /// `optimizer::fold_shifts`, so that a wrapping tape is bounds checked once per run. A bounds
+    /// checked run isn't folded, as `<>` would skip the check of the cell before the tape.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::compiler::{Compiler, CompilerSettings};
+    /// use rbfc::lexer::TokenType;
+    ///
+    /// let settings = CompilerSettings { opt_level: 1, ..Default::default() };
+    /// let compiler = Compiler::new("[-]", settings).unwrap();
+    /// assert_eq!(compiler.optimized_ops()[0].token_type, TokenType::Clear);
+    /// ```
+    pub fn optimized_ops(&self) -> Vec<Token> {
+        let opt_level = if self.settings.loop_sentinel != 0 {
+            0
+        } else if self.settings.tape.wrap_left || self.settings.tape.wrap_right {
+            self.settings.opt_level.min(1)
+        } else {
+            self.settings.opt_level
+        };
+        let ops = optimizer::optimize(self.ops.clone(), opt_level);
+        if self.settings.tape.wrap_left
+            && self.settings.tape.wrap_right
+            && !self.settings.bounds_check
+        {
+            optimizer::fold_shifts(ops)
+        } else {
+            ops
+        }
+    }
 
+    /// Walk the ops and emit the code with the backend, optionally annotating every op with its
+    /// source, and record the lines emitted for every op
+    fn emit(
+        &self,
+        backend: &dyn CodeGen,
+        annotate: bool,
+    ) -> Result<(String, SourceMap), CompilerError> {
+        let ops = self.optimized_ops();
+
+        let mut code = backend.header();
+        let mut source_map = SourceMap::default();
+        let mut line = code.matches('\n').count() + 1;
         let mut jump_stack = Vec::new();
-        for op in self.ops.iter() {
-            if op.token_type == TokenType::Eof {
-                main.push_str(&formatdoc! {"
-                        ; TokenType::Eof
-                        call EXIT
-                    "});
-                break;
+        let mut exited = false;
+        // With `functions_per_loop` the body of a top-level loop is emitted on its own while the
+        // main code, its line and entries are put aside with the op that opened the loop
+        let mut main = None;
+        let mut functions = String::new();
+        let mut function_entries = Vec::new();
+        for (i, op) in ops.iter().enumerate() {
+            if self.settings.functions_per_loop
+                && op.token_type == TokenType::OpenBracket
+                && jump_stack.is_empty()
+            {
+                let entries = std::mem::take(&mut source_map.entries);
+                main = Some((std::mem::take(&mut code), line, entries, op));
+                line = 1;
             }
 
-            let size = match op.size {
-                Some(size) => size,
-                None => panic!(
-                    "Unexpected none size at {}, should be caught at parse",
-                    op.loc
-                ),
-            };
+            if annotate {
+                let end = ops[i..]
+                    .iter()
+                    .map(|next| next.loc)
+                    .find(|&loc| loc > op.loc)
+                    .unwrap_or(self.code.len());
+                let source: String = self.code[op.loc..end]
+                    .chars()
+                    .filter(|c| Token::is_token(c).is_some())
+                    .collect();
+                let comment = backend.emit_comment(&format!("{source:?} at {}", op.loc));
+                line += comment.matches('\n').count();
+                code.push_str(&comment);
+            }
 
-            match op.token_type {
-                TokenType::Plus => main.push_str(&formatdoc! {"
-                        ; TokenType::Plus
-                        add byte [r12], {size}
-                    "}),
-                TokenType::Minus => main.push_str(&formatdoc! {"
-                        ; TokenType::Minus
-                        sub byte [r12], {size}
-                    "}),
-                TokenType::ShiftRight => {
-                    if self.settings.wrap {
-                        main.push_str(&formatdoc! {"
-                            ; TokenType::ShiftRight
-                            add r12, {size}
-                            cmp r12, (TAPE + TAPE_SIZE)
-                            jl no_wrap_{loc}
-                            sub r12, TAPE_SIZE
-                            no_wrap_{loc}:
-                        ", loc = op.loc})
-                    } else {
-                        main.push_str(&formatdoc! {"
-                            ; TokenType::ShiftRight
-                            add r12, {size}
-                        "})
-                    }
-                }
-                TokenType::ShiftLeft => {
-                    if self.settings.wrap {
-                        main.push_str(&formatdoc! {"
-                            ; TokenType::ShiftLeft
-                            cmp r12, (TAPE + {size})
-                            jl no_wrap_{loc}
-                            add r12, TAPE_SIZE
-                            sub r12, {size}
-                            no_wrap_{loc}:
-                        ", loc = op.loc})
-                    } else {
-                        main.push_str(&formatdoc! {"
-                            ; TokenType::ShiftLeft
-                            sub r12, {size}
-                        "})
-                    }
-                }
-                TokenType::Dot => {
-                    main.push_str("; TokenType::Dot\n");
-                    for _ in 0..size {
-                        main.push_str("  call WRITE_TO_STDOUT\n");
-                    }
+            let size = || op.size.ok_or(CompilerError::UnexpectedNoneSize(op.loc));
+
+            let op_code = match op.token_type {
+                TokenType::Eof => backend.emit_exit(),
+                TokenType::Plus => backend.emit_add(size()?),
+                TokenType::Minus => backend.emit_sub(size()?),
+                TokenType::ShiftRight => backend.emit_shift_right(size()?, op.loc),
+                TokenType::ShiftLeft => backend.emit_shift_left(size()?, op.loc),
+                TokenType::Dot => backend.emit_output(size()?),
+                TokenType::Comma => backend.emit_input(size()?),
+                TokenType::OpenBracket => {
+                    jump_stack.push(size()?);
+                    backend.emit_loop_start(size()?)
                 }
-                TokenType::Comma => {
-                    main.push_str("; TokenType::Comma\n");
-                    for _ in 0..size {
-                        main.push_str(&formatdoc! {"
-                            call READ_FROM_STDIN
-                            mov rax, [r12]
-                        "});
-                    }
+                TokenType::CloseBracket => {
+                    let loop_name = jump_stack.pop().ok_or(CompilerError::ParsingError(
+                        ParserError::UnmatchedBracket(op.loc),
+                    ))?;
+                    backend.emit_loop_end(loop_name)
                 }
-                TokenType::OpenBracket => {
-                    jump_stack.push(size);
-                    let code = formatdoc! {"
+                TokenType::Clear => backend.emit_clear(),
+                TokenType::SetValue { value } => backend.emit_set_value(value),
+                TokenType::MulAdd { offset, factor } => backend.emit_mul_add(offset, factor),
+                TokenType::AddAt { offset, delta } => backend.emit_add_at(offset, delta),
+                TokenType::Scan { step } => backend.emit_scan(step, op.loc),
+                TokenType::Debug => backend.emit_comment("TokenType::Debug"),
+                TokenType::Halt => backend.emit_halt(),
+            };
+            let start = line;
+            line += op_code.matches('\n').count();
+            source_map.entries.push(SourceMapEntry {
+                lines: start..line,
+                loc: op.loc,
+            });
+            code.push_str(&op_code);
 
-                        ; TokenType::OpenBracket
-                        cmp byte [r12], 0
-                        je after_loop_{size}
+            if op.token_type == TokenType::CloseBracket && jump_stack.is_empty() {
+                if let Some((main_code, main_line, main_entries, open)) = main.take() {
+                    let body = std::mem::replace(&mut code, main_code);
+                    let body_entries = std::mem::replace(&mut source_map.entries, main_entries);
+                    line = main_line;
 
-                        loop_{size}:
+                    let id = open
+                        .size
+                        .ok_or(CompilerError::UnexpectedNoneSize(open.loc))?;
+                    match backend.emit_function(&format!("loop_body_{id}")) {
+                        Some((call, prologue, epilogue)) => {
+                            // The functions follow the main code, so their lines are offset by
+                            // it once it is complete
+                            let start =
+                                functions.matches('\n').count() + prologue.matches('\n').count();
+                            function_entries.extend(offset_entries(body_entries, start));
+                            functions.push_str(&prologue);
+                            functions.push_str(&body);
+                            functions.push_str(&epilogue);
 
-                        "};
-                    main.push_str(&code);
-                }
-                TokenType::CloseBracket => {
-                    let loop_name = jump_stack
-                        .pop()
-                        .expect("Unmatched bracket should be caught at parse");
-                    let code = formatdoc! {"
-
-                        ; TokenType::CloseBracket
-                        cmp byte [r12], 0
-                        jne loop_{loop_name}
-
-                        after_loop_{loop_name}:
-                    "};
-                    main.push_str(&code);
+                            let start = line;
+                            line += call.matches('\n').count();
+                            source_map.entries.push(SourceMapEntry {
+                                lines: start..line,
+                                loc: open.loc,
+                            });
+                            code.push_str(&call);
+                        }
+                        None => {
+                            source_map
+                                .entries
+                                .extend(offset_entries(body_entries, line - 1));
+                            line += body.matches('\n').count();
+                            code.push_str(&body);
+                        }
+                    }
                 }
-                TokenType::Eof => {}
+            }
+
+            if op.token_type == TokenType::Eof {
+                exited = true;
+                break;
             }
         }
+        // The parser ends every program with an `Eof`, but without one the code would run into
+        // the footer
+        if !exited {
+            code.push_str(&backend.emit_exit());
+        }
+        let start = code.matches('\n').count();
+        source_map
+            .entries
+            .extend(offset_entries(function_entries, start));
+        code.push_str(&functions);
+        code.push_str(&backend.footer());
+
+        Ok((code, source_map))
+    }
+}
 
-        let data = indoc! {"
+/// Move the lines of source map entries down by `offset`
+fn offset_entries(
+    entries: Vec<SourceMapEntry>,
+    offset: usize,
+) -> impl Iterator<Item = SourceMapEntry> {
+    entries.into_iter().map(move |entry| SourceMapEntry {
+        lines: entry.lines.start + offset..entry.lines.end + offset,
+        loc: entry.loc,
+    })
+}
 
-            segment readable writeable
-            TAPE_SIZE = 30000
-            TAPE rd TAPE_SIZE
-        "};
+/// A map from the lines of the compiled code back to the source
+///
+/// Its `Display` implementation writes the `.map` file, with a line `first-last loc` for every
+/// op, where `first` and `last` are the lines of the code emitted for the op, counted from 1.
+///
+/// # Fields
+/// * `entries` - The lines emitted for every op, in the order of the output
+///
+/// # Example
+/// ```
+/// use rbfc::compiler::{SourceMap, SourceMapEntry};
+/// let source_map = SourceMap {
+///     entries: vec![SourceMapEntry { lines: 3..5, loc: 0 }],
+/// };
+/// assert_eq!(source_map.to_string(), "3-4 0\n");
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    pub entries: Vec<SourceMapEntry>,
+}
+
+/// The lines emitted for a single op
+///
+/// # Fields
+/// * `lines` - The lines of the code emitted for the op, counted from 1
+/// * `loc` - The location of the op in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMapEntry {
+    pub lines: Range<usize>,
+    pub loc: usize,
+}
+
+impl SourceMap {
+    /// Find the location in the source of the op a line of the output was emitted for
+    ///
+    /// # Arguments
+    /// * `line` - The line of the output, counted from 1
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::compiler::{Compiler, CompilerSettings};
+    /// let compiler = Compiler::new("+++.".to_string(), CompilerSettings::default()).unwrap();
+    /// let (_, source_map) = compiler.compile_with_sourcemap().unwrap();
+    /// assert_eq!(source_map.loc_at_line(1), None);
+    /// ```
+    pub fn loc_at_line(&self, line: usize) -> Option<usize> {
+        self.entries
+            .iter()
+            .find(|entry| entry.lines.contains(&line))
+            .map(|entry| entry.loc)
+    }
+}
+
+impl fmt::Display for SourceMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in self.entries.iter().filter(|entry| !entry.lines.is_empty()) {
+            writeln!(
+                f,
+                "{}-{} {}",
+                entry.lines.start,
+                entry.lines.end - 1,
+                entry.loc
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Check that the loop labels of the emitted code are consistent
+///
+/// Every `loop_N` and `after_loop_N` label has to be defined exactly once, on a line of its own
+/// ending with a colon, and every label that is referenced has to be defined. This works for the
+/// output of every backend and catches backends that reuse a loop id.
+///
+/// # Arguments
+/// * `asm` - The code emitted by the compiler
+///
+/// # Example
+/// ```
+/// use rbfc::compiler::{verify_output, Compiler, CompilerSettings};
+/// let compiler = Compiler::new("+[-]".to_string(), CompilerSettings::default()).unwrap();
+/// let asm = compiler.compile_code().unwrap();
+/// assert!(verify_output(&asm).is_ok());
+/// assert!(verify_output("jne loop_1\n").is_err());
+/// ```
+///
+/// # Errors
+/// Returns a description of the first label that is defined more than once or never defined
+pub fn verify_output(asm: &str) -> Result<(), String> {
+    let is_label = |word: &str| {
+        word.strip_prefix("after_")
+            .unwrap_or(word)
+            .strip_prefix("loop_")
+            .is_some_and(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+    };
 
-        assembly.push_str(header);
-        assembly.push_str(helper_functions);
-        assembly.push_str(&main);
-        assembly.push_str(data);
+    let mut definitions: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut references = Vec::new();
+    for line in asm.lines() {
+        let line = line.trim();
+        if let Some(label) = line.strip_suffix(':').filter(|label| is_label(label)) {
+            *definitions.entry(label).or_default() += 1;
+            continue;
+        }
+        references.extend(
+            line.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .filter(|word| is_label(word)),
+        );
+    }
 
-        assembly
+    if let Some((label, count)) = definitions.iter().find(|(_, &count)| count > 1) {
+        return Err(format!("Label {label} is defined {count} times"));
+    }
+    if let Some(label) = references
+        .iter()
+        .find(|label| !definitions.contains_key(*label))
+    {
+        return Err(format!("Label {label} is referenced but never defined"));
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
-    use indoc::formatdoc;
+    use indoc::{formatdoc, indoc};
+
+    #[test]
+    fn new_from_str_test() {
+        use super::{Compiler, CompilerSettings};
+        let compiler = Compiler::new("+++", CompilerSettings::default()).unwrap();
+        assert!(compiler
+            .compile_code()
+            .unwrap()
+            .contains("add byte [r12], 3"));
+    }
+
+    #[test]
+    fn parser_error_test() {
+        use super::{Compiler, CompilerError, CompilerSettings};
+        use crate::parser::ParserError;
+        assert!(matches!(
+            Compiler::new("+[", CompilerSettings::default()),
+            Err(CompilerError::ParsingError(ParserError::UnexpectedEof {
+                eof: 2,
+                open: 1
+            }))
+        ));
+    }
+
+    #[test]
+    fn clone_test() {
+        use super::{Compiler, CompilerSettings, Target};
+        let settings = CompilerSettings {
+            opt_level: 2,
+            target: Target::LlvmIr,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("++[->+<]>.", settings).unwrap();
+        let clone = compiler.clone();
+        assert_eq!(clone, compiler);
+        assert_eq!(
+            clone.compile_code().unwrap(),
+            compiler.compile_code().unwrap()
+        );
+    }
 
     #[test]
     fn compiler_test() {
         use super::{Compiler, CompilerSettings};
         let compiler = Compiler::new("+++".to_string(), CompilerSettings::default()).unwrap();
-        let asm = compiler.compile_code();
+        let asm = compiler.compile_code().unwrap();
         assert_eq!(
             asm,
             formatdoc! {
@@ -317,4 +856,896 @@ mod test {
             "}
         );
     }
+
+    #[test]
+    fn opt_level_test() {
+        use super::{Compiler, CompilerSettings};
+        // The code of `main`, without the helpers before it and the tape after it
+        let main = |opt_level| {
+            let settings = CompilerSettings {
+                opt_level,
+                ..Default::default()
+            };
+            let compiler = Compiler::new("[-]".to_string(), settings).unwrap();
+            let asm = compiler.compile_code().unwrap();
+            let start = asm.find("main:\n").unwrap();
+            let end = asm.find("segment readable writeable").unwrap();
+            asm[start..end].to_string()
+        };
+        assert_eq!(
+            main(0),
+            indoc! {"
+                main:
+                mov r12, (TAPE)
+
+                ; TokenType::OpenBracket
+                cmp byte [r12], 0
+                je after_loop_3
+
+                loop_3:
+
+                ; TokenType::Minus
+                sub byte [r12], 1
+
+                ; TokenType::CloseBracket
+                cmp byte [r12], 0
+                jne loop_3
+
+                after_loop_3:
+                ; TokenType::Eof
+                call EXIT
+
+            "}
+        );
+        assert_eq!(
+            main(1),
+            indoc! {"
+                main:
+                mov r12, (TAPE)
+                ; TokenType::Clear
+                mov byte [r12], 0
+                ; TokenType::Eof
+                call EXIT
+
+            "}
+        );
+    }
+
+    #[test]
+    fn llvm_ir_test() {
+        use super::{Compiler, CompilerSettings, Target};
+        let settings = CompilerSettings {
+            target: Target::LlvmIr,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+[>+<-]".to_string(), settings).unwrap();
+        let ir = compiler.compile_code().unwrap();
+        assert!(ir.contains("getelementptr"));
+        assert!(ir.contains("br i1"));
+        assert!(ir.contains("declare i32 @putchar(i32)"));
+
+        let settings = CompilerSettings {
+            target: Target::LlvmIr,
+            ..Default::default()
+        };
+        let compiler = Compiler::new(".,".to_string(), settings).unwrap();
+        let ir = compiler.compile_code().unwrap();
+        let flush = ir.find("call i32 @fflush(ptr null)").unwrap();
+        assert!(ir.find("call i32 @putchar").unwrap() < flush);
+        assert!(flush < ir.find("call i32 @getchar()").unwrap());
+    }
+
+    #[test]
+    fn fasm_backend_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig};
+        use crate::codegen::FasmBackend;
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().wrap(true),
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+[->+<]>.,<".to_string(), settings).unwrap();
+        let asm = compiler
+            .compile_with(&FasmBackend::new(&compiler.settings))
+            .unwrap();
+        assert_eq!(
+            asm,
+            indoc! {"
+                format ELF64 executable 3
+
+                ; Helper functions
+                SYS_read = 0
+                SYS_write = 1
+                SYS_exit = 60
+
+                STDIN = 0
+                STDOUT = 1
+
+                WRITE_TO_STDOUT:
+                mov rax, SYS_write
+                mov rdi, STDOUT
+                mov rsi, r12
+                mov rdx, 1
+                syscall
+                ret
+
+                READ_FROM_STDIN:
+                mov rax, SYS_read
+                mov rdi, STDIN
+                mov rsi, r12
+                mov rdx, 1
+                syscall
+                ret
+
+                EXIT:
+                mov rax, SYS_exit
+                mov rdi, 0
+                syscall
+                segment readable executable
+                entry main
+
+                main:
+                mov r12, (TAPE)
+                ; TokenType::Plus
+                add byte [r12], 1
+
+                ; TokenType::OpenBracket
+                cmp byte [r12], 0
+                je after_loop_7
+
+                loop_7:
+
+                ; TokenType::Minus
+                sub byte [r12], 1
+                ; TokenType::ShiftRight
+                add r12, 1
+                cmp r12, (TAPE + TAPE_SIZE)
+                jb no_wrap_3
+                sub r12, TAPE_SIZE
+                no_wrap_3:
+                ; TokenType::Plus
+                add byte [r12], 1
+                ; TokenType::ShiftLeft
+                sub r12, 1
+                cmp r12, TAPE
+                jae no_wrap_5
+                add r12, TAPE_SIZE
+                no_wrap_5:
+
+                ; TokenType::CloseBracket
+                cmp byte [r12], 0
+                jne loop_7
+
+                after_loop_7:
+                ; TokenType::ShiftRight
+                add r12, 1
+                cmp r12, (TAPE + TAPE_SIZE)
+                jb no_wrap_7
+                sub r12, TAPE_SIZE
+                no_wrap_7:
+                ; TokenType::Dot
+                  call WRITE_TO_STDOUT
+                ; TokenType::Comma
+                call READ_FROM_STDIN
+                mov rax, [r12]
+                ; TokenType::ShiftLeft
+                sub r12, 1
+                cmp r12, TAPE
+                jae no_wrap_10
+                add r12, TAPE_SIZE
+                no_wrap_10:
+                ; TokenType::Eof
+                call EXIT
+
+                segment readable writeable
+                TAPE_SIZE = 30000
+                TAPE rd TAPE_SIZE
+            "}
+        );
+    }
+
+    #[test]
+    fn compile_annotated_test() {
+        use super::{Compiler, CompilerSettings};
+        let compiler = Compiler::new("+++ >-".to_string(), CompilerSettings::default()).unwrap();
+        let asm = compiler.compile_annotated().unwrap();
+        assert!(asm.contains("; \"+++\" at 0\n; TokenType::Plus\nadd byte [r12], 3\n"));
+        assert!(asm.contains("; \">\" at 4\n; TokenType::ShiftRight\n"));
+        assert!(asm.contains("; \"-\" at 5\n; TokenType::Minus\n"));
+        assert_eq!(
+            asm.lines()
+                .filter(|line| !line.starts_with("; \""))
+                .collect::<Vec<_>>(),
+            compiler.compile_code().unwrap().lines().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn sourcemap_test() {
+        use super::{Compiler, CompilerSettings};
+        let compiler = Compiler::new("+++.", CompilerSettings::default()).unwrap();
+        let (asm, source_map) = compiler.compile_with_sourcemap().unwrap();
+        let locs: Vec<usize> = source_map.entries.iter().map(|entry| entry.loc).collect();
+        assert_eq!(locs, vec![0, 3, 4]);
+
+        let lines: Vec<&str> = asm.lines().map(str::trim).collect();
+        let output = &source_map.entries[1].lines;
+        assert_eq!(
+            lines[output.start - 1..output.end - 1],
+            ["; TokenType::Dot", "call WRITE_TO_STDOUT"]
+        );
+        assert_eq!(source_map.loc_at_line(output.start), Some(3));
+        assert!(source_map.to_string().starts_with(&format!(
+            "{}-{} 0\n",
+            source_map.entries[0].lines.start,
+            source_map.entries[0].lines.end - 1
+        )));
+    }
+
+    #[test]
+    fn wrap_one_direction_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig, Target};
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().wrap_right(true),
+            target: Target::LlvmIr,
+            ..Default::default()
+        };
+        let compiler = Compiler::new(">+<", settings).unwrap();
+        let ir = compiler.compile_code().unwrap();
+        assert!(ir.contains("%overflow0 = icmp uge i64 %moved0, 30000\n"));
+        assert!(!ir.contains("%underflow"));
+
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().wrap_left(true),
+            ..Default::default()
+        };
+        let compiler = Compiler::new(">+<", settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.contains("sub r12, 1\ncmp r12, TAPE\njae no_wrap_2\n"));
+        assert!(!asm.contains("no_wrap_0"));
+    }
+
+    #[test]
+    fn wrap_long_shift_test() {
+        use super::{Compiler, CompilerError, CompilerSettings, TapeConfig, Target};
+        // Moves of more than the tape size wrap around as often as needed
+        for (target, right, left) in [
+            (Target::Fasm, "add r12, 1\n", "sub r12, 3\n"),
+            (Target::PeWin64, "add r12, 1\n", "sub r12, 3\n"),
+            (Target::GasIntel, "add r12, 1\n", "sub r12, 3\n"),
+            (Target::Arm64, "add x19, x19, #1\n", "sub x19, x19, #3\n"),
+            (Target::LlvmIr, "add i64 %dp0, 1\n", "sub i64 %dp2, 3\n"),
+        ] {
+            let settings = CompilerSettings {
+                tape: TapeConfig::default().wrap(true).tape_size(4),
+                target,
+                ..Default::default()
+            };
+            let compiler = Compiler::new(">>>>>>>>>+<<<<<<<<<<<+", settings).unwrap();
+            let code = compiler.compile_code().unwrap();
+            assert!(code.contains(right), "{target:?}");
+            assert!(code.contains(left), "{target:?}");
+        }
+
+        // There is no cell to wrap around to on an empty tape
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().wrap(true).tape_size(0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Compiler::new(">+", settings),
+            Err(CompilerError::EmptyTape)
+        ));
+    }
+
+    #[test]
+    fn fold_shifts_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig};
+        let labels = |code: &str| {
+            let settings = CompilerSettings {
+                tape: TapeConfig::default().wrap(true),
+                ..Default::default()
+            };
+            let asm = Compiler::new(code, settings)
+                .unwrap()
+                .compile_code()
+                .unwrap();
+            asm.lines()
+                .filter(|line| line.starts_with("no_wrap_"))
+                .count()
+        };
+        assert_eq!(labels(">>>>"), 1);
+        assert_eq!(labels(">><>><"), 1);
+        assert_eq!(labels("><+<>"), 0);
+
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().wrap_right(true),
+            ..Default::default()
+        };
+        let compiler = Compiler::new("><", settings).unwrap();
+        assert_eq!(compiler.optimized_ops().len(), 3);
+    }
+
+    #[test]
+    fn syscall_abi_test() {
+        use super::{Compiler, CompilerSettings, SyscallAbi};
+        let settings = CompilerSettings {
+            syscall_abi: SyscallAbi {
+                write: 42,
+                number_reg: String::from("r8"),
+                ..Default::default()
+            },
+            buffer_output: true,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+.", settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.contains("SYS_write = 42\nSYS_exit = 60\n"));
+        assert!(asm.contains("FLUSH_OUTPUT:\nmov r8, SYS_write\nmov rdi, STDOUT\n"));
+        assert!(!asm.contains("mov rax, SYS_"));
+    }
+
+    #[test]
+    fn scan_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig, Target};
+        use crate::interpreter::CellWidth;
+        let compile = |code: &str, settings: CompilerSettings| {
+            let settings = CompilerSettings {
+                opt_level: 1,
+                ..settings
+            };
+            Compiler::new(code, settings)
+                .unwrap()
+                .compile_code()
+                .unwrap()
+        };
+
+        let asm = compile("+[>]", CompilerSettings::default());
+        assert!(asm.contains("; TokenType::Scan\nmov rdi, r12\nxor eax, eax\nmov rcx, -1\nrepne scasb\nlea r12, [rdi - 1]\n"));
+        assert!(!asm.contains("loop_"));
+        let asm = compile("+[<]", CompilerSettings::default());
+        assert!(asm.contains("std\nrepne scasb\ncld\nlea r12, [rdi + 1]\n"));
+
+        let asm = compile(
+            "+[>]",
+            CompilerSettings {
+                tape: TapeConfig::default().wrap_right(true),
+                ..Default::default()
+            },
+        );
+        assert!(asm.contains(
+            "scan_1:\ncmp byte [r12], 0\nje after_scan_1\n; TokenType::ShiftRight\nadd r12, 1\n"
+        ));
+        assert!(asm
+            .contains("jb no_wrap_1\nsub r12, TAPE_SIZE\nno_wrap_1:\njmp scan_1\nafter_scan_1:\n"));
+        assert!(!asm.contains("scasb"));
+
+        let asm = compile(
+            "+[>>]",
+            CompilerSettings {
+                tape: TapeConfig::default().cell_width(CellWidth::U16),
+                target: Target::PeWin64,
+                ..Default::default()
+            },
+        );
+        assert!(asm
+            .contains("cmp word [r12], 0\nje after_scan_1\n; TokenType::ShiftRight\nadd r12, 4\n"));
+    }
+
+    #[test]
+    fn loop_sentinel_test() {
+        use super::{Compiler, CompilerSettings, Target};
+        let settings = CompilerSettings {
+            loop_sentinel: 255,
+            opt_level: 2,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("[-]", settings.clone()).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.contains("cmp byte [r12], 255\nje after_loop_3\n"));
+        assert!(asm.contains("cmp byte [r12], 255\njne loop_3\n"));
+        assert!(!asm.contains("Clear"));
+
+        let settings = CompilerSettings {
+            target: Target::Arm64,
+            ..settings
+        };
+        let compiler = Compiler::new("[-]", settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.contains("cmp w9, #255\nb.eq after_loop_3\n"));
+        assert!(asm.contains("cmp w9, #255\nb.ne loop_3\n"));
+    }
+
+    #[test]
+    fn empty_program_test() {
+        use super::{Compiler, CompilerSettings, Target};
+        for code in ["", "just a comment"] {
+            let compiler = Compiler::new(code, CompilerSettings::default()).unwrap();
+            let asm = compiler.compile_code().unwrap();
+            assert!(asm.contains("mov r12, (TAPE)\n; TokenType::Eof\ncall EXIT\n"));
+            assert_eq!(asm.matches("; TokenType::").count(), 1);
+
+            let settings = CompilerSettings {
+                target: Target::LlvmIr,
+                ..Default::default()
+            };
+            let compiler = Compiler::new(code, settings).unwrap();
+            let asm = compiler.compile_code().unwrap();
+            assert!(asm.ends_with("store i64 0, ptr %dp\n; TokenType::Eof\nret i32 0\n}\n"));
+        }
+    }
+
+    #[test]
+    fn long_run_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig, Target};
+        use crate::interpreter::CellWidth;
+        let code = "+".repeat(300) + &"-".repeat(70000);
+        for target in [Target::Fasm, Target::PeWin64] {
+            let settings = CompilerSettings {
+                target,
+                ..Default::default()
+            };
+            let compiler = Compiler::new(code.as_str(), settings).unwrap();
+            let asm = compiler.compile_code().unwrap();
+            assert!(asm.contains("add byte [r12], 44\n"));
+            assert!(asm.contains("sub byte [r12], 112\n"));
+        }
+
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().cell_width(CellWidth::U16),
+            ..Default::default()
+        };
+        let compiler = Compiler::new(code, settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.contains("add word [r12], 300\n"));
+        assert!(asm.contains("sub word [r12], 4464\n"));
+    }
+
+    #[test]
+    fn entry_symbol_test() {
+        use super::{Compiler, CompilerSettings, Target};
+        let settings = CompilerSettings {
+            entry_symbol: String::from("_bf_start"),
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+", settings.clone()).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.contains("entry _bf_start\n\n_bf_start:\n"));
+        assert!(!asm.contains("main"));
+
+        let settings = CompilerSettings {
+            target: Target::PeWin64,
+            ..settings
+        };
+        let compiler = Compiler::new("+", settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.contains("entry _bf_start\n"));
+        assert!(asm.contains("\n_bf_start:\n"));
+    }
+
+    #[test]
+    fn compile_to_file_test() {
+        use super::{Compiler, CompilerError, CompilerSettings};
+        let compiler = Compiler::new("+++", CompilerSettings::default()).unwrap();
+        let path = std::env::temp_dir().join(format!("rbfc-compile-{}.asm", std::process::id()));
+        compiler.compile_to_file(&path).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, compiler.compile_code().unwrap());
+
+        let missing = path.join("out.asm");
+        assert!(matches!(
+            compiler.compile_to_file(&missing),
+            Err(CompilerError::WriteError(file)) if file == missing.display().to_string()
+        ));
+    }
+
+    #[test]
+    fn compile_machine_code_test() {
+        use super::{Compiler, CompilerError, CompilerSettings, TapeConfig};
+        let compiler = Compiler::new("+", CompilerSettings::default()).unwrap();
+        let code = compiler.compile_machine_code().unwrap();
+        // add byte [r12], 1
+        assert!(code
+            .windows(5)
+            .any(|bytes| bytes == [0x41, 0x80, 0x04, 0x24, 0x01]));
+
+        // cmp byte [r12], 0; je after the loop; sub byte [r12], 1; cmp; jne to the body
+        let compiler = Compiler::new("[-]", CompilerSettings::default()).unwrap();
+        let code = compiler.compile_machine_code().unwrap();
+        let body = [0x41, 0x80, 0x04, 0x24, 0xff, 0x41, 0x80, 0x3c, 0x24, 0x00];
+        let mut expected = vec![0x41, 0x80, 0x3c, 0x24, 0x00, 0x0f, 0x84, 16, 0, 0, 0];
+        expected.extend_from_slice(&body);
+        expected.extend_from_slice(&[0x0f, 0x85, 0xf0, 0xff, 0xff, 0xff]);
+        assert!(code.windows(expected.len()).any(|bytes| bytes == expected));
+
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().wrap(true),
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+", settings).unwrap();
+        assert!(matches!(
+            compiler.compile_machine_code(),
+            Err(CompilerError::UnsupportedMachineCode(_))
+        ));
+    }
+
+    #[test]
+    fn bounds_check_test() {
+        use super::{Compiler, CompilerSettings, Target};
+        let compile = |target, bounds_check| {
+            let settings = CompilerSettings {
+                target,
+                bounds_check,
+                ..Default::default()
+            };
+            Compiler::new(">+<", settings)
+                .unwrap()
+                .compile_code()
+                .unwrap()
+        };
+        let code = compile(Target::Fasm, true);
+        assert!(code.contains("add r12, 1\ncmp r12, (TAPE + TAPE_SIZE)\njae TAPE_ERROR\n"));
+        assert!(code.contains("sub r12, 1\ncmp r12, TAPE\njb TAPE_ERROR\n"));
+        assert!(code.contains("TAPE_ERROR:\nmov rax, SYS_exit\nmov rdi, 3\n"));
+        assert!(!compile(Target::Fasm, false).contains("TAPE_ERROR"));
+
+        let settings = CompilerSettings {
+            bounds_check: true,
+            ..Default::default()
+        };
+        let code = Compiler::new("<>+", settings)
+            .unwrap()
+            .compile_code()
+            .unwrap();
+        assert!(code.contains(
+            "sub r12, 1
+cmp r12, TAPE
+jb TAPE_ERROR
+"
+        ));
+
+        for target in [
+            Target::PeWin64,
+            Target::Arm64,
+            Target::GasIntel,
+            Target::LlvmIr,
+        ] {
+            let code = compile(target, true);
+            assert!(code.contains("tape_error:") || code.contains("TAPE_ERROR:"));
+        }
+    }
+
+    #[test]
+    fn ptr_register_test() {
+        use super::{Compiler, CompilerError, CompilerSettings, TapeConfig, Target};
+        let compile = |target, emit_as_function| {
+            let settings = CompilerSettings {
+                target,
+                tape: TapeConfig::default().wrap(true),
+                emit_as_function,
+                assume_zeroed: false,
+                ptr_register: String::from("r13"),
+                ..Default::default()
+            };
+            Compiler::new("+[->+<]>.,<", settings)
+                .unwrap()
+                .compile_code()
+                .unwrap()
+        };
+        let asm = compile(Target::Fasm, false);
+        assert!(!asm.contains("r12"));
+        assert!(asm.contains("add byte [r13], 1\n"));
+        assert!(asm.contains("mov r13, (TAPE)\n"));
+        assert!(!compile(Target::PeWin64, false).contains("r12"));
+        // As a function the start of the tape moves to the register that is free
+        assert!(compile(Target::Fasm, true).contains("push r13\npush r12\nmov r13, rdi\n"));
+
+        let settings = CompilerSettings {
+            ptr_register: String::from("rax"),
+            ..Default::default()
+        };
+        assert!(matches!(
+            Compiler::new("+", settings),
+            Err(CompilerError::InvalidRegister(register)) if register == "rax"
+        ));
+    }
+
+    #[test]
+    fn exit_from_cell_test() {
+        use super::{Compiler, CompilerSettings, Target};
+        let compile = |target, exit_from_cell| {
+            let settings = CompilerSettings {
+                target,
+                exit_from_cell,
+                ..Default::default()
+            };
+            Compiler::new("+++", settings)
+                .unwrap()
+                .compile_code()
+                .unwrap()
+        };
+        let asm = compile(Target::Fasm, true);
+        assert!(asm.contains("EXIT:\nmov rax, SYS_exit\nmovzx rdi, byte [TAPE]\nsyscall\n"));
+        assert!(!asm.contains("mov rdi, 0"));
+        assert!(compile(Target::Fasm, false).contains("mov rdi, 0\n"));
+
+        assert!(compile(Target::PeWin64, true).contains("EXIT:\nmovzx ecx, byte [TAPE]\n"));
+        assert!(compile(Target::Arm64, true).contains("EXIT:\nldrb w0, [x20]\n"));
+        let ir = compile(Target::LlvmIr, true);
+        assert!(ir.contains("%first1 = load i8, ptr @tape\n"));
+        assert!(ir.contains("ret i32 %status1\n"));
+    }
+
+    #[test]
+    fn functions_per_loop_test() {
+        use super::{verify_output, Compiler, CompilerSettings};
+        let compile = |functions_per_loop| {
+            let settings = CompilerSettings {
+                functions_per_loop,
+                ..Default::default()
+            };
+            Compiler::new("+[>+[-]<-].", settings)
+                .unwrap()
+                .compile_code()
+                .unwrap()
+        };
+        let asm = compile(true);
+        let call = asm.find("call loop_body_").unwrap();
+        let name = asm[call + 5..].lines().next().unwrap();
+        let body = asm.find(&format!("\n{name}:\n")).unwrap();
+        // The body follows the main code with the nested loop inside of it
+        assert!(body > asm.find("call EXIT").unwrap());
+        let ret = asm[body..].find("ret\n").unwrap();
+        assert_eq!(asm[body..body + ret].matches("je after_loop_").count(), 2);
+        assert_eq!(asm.matches("call loop_body_").count(), 1);
+        assert!(verify_output(&asm).is_ok());
+
+        assert!(!compile(false).contains("loop_body_"));
+    }
+
+    #[test]
+    fn pe_win64_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig, Target};
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().wrap(true),
+            target: Target::PeWin64,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+[->+<]>.,<".to_string(), settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.starts_with("format PE64 console\n"));
+        assert!(asm.contains("call [WriteFile]\n"));
+        assert!(asm.contains("WriteFile dq RVA _WriteFile\n"));
+        assert!(asm.contains("jae no_wrap_10\nadd r12, TAPE_SIZE\n"));
+        assert!(asm.contains("; TokenType::Eof\njmp EXIT\n"));
+        assert!(!asm.contains("call EXIT"));
+        assert!(!asm.contains("syscall"));
+    }
+
+    #[test]
+    fn set_value_test() {
+        use super::{Compiler, CompilerSettings};
+        let settings = CompilerSettings {
+            opt_level: 1,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+[-]+++++", settings).unwrap();
+        assert!(compiler
+            .compile_code()
+            .unwrap()
+            .contains("; TokenType::SetValue\nmov byte [r12], 5\n"));
+    }
+
+    #[test]
+    fn arm64_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig, Target};
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().wrap(true),
+            target: Target::Arm64,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+[->+<].", settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.contains("svc #0\n"));
+        assert!(asm.contains("cbz w9, after_loop_7\n"));
+        assert!(asm.contains("cbnz w9, loop_7\n"));
+        assert!(asm.contains("ldrb w9, [x19]\nadd w9, w9, #1\nstrb w9, [x19]\n"));
+        assert!(asm.contains("cmp x19, x22\nb.lo no_wrap_3\nsub x19, x19, x21\n"));
+        assert!(!asm.contains(';'));
+    }
+
+    #[test]
+    fn gas_intel_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig, Target};
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().wrap(true),
+            target: Target::GasIntel,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+[->+<].", settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.starts_with(".intel_syntax noprefix\n"));
+        assert!(asm.contains(".global _start\n"));
+        assert!(asm.contains("\n_start:\nlea r12, [rip + tape]\n"));
+        assert!(asm.contains(".section .bss\n.lcomm tape, TAPE_BYTES\n"));
+        assert!(asm.contains(".equ TAPE_SIZE, 30000\n"));
+        assert!(asm.contains("cmp byte ptr [r12], 0\nje after_loop_7\n"));
+        assert!(asm.contains("add r12, 1\ncmp r12, r14\njb no_wrap_3\n"));
+        assert!(!asm.contains(';'));
+    }
+
+    #[test]
+    fn cell_width_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig, Target};
+        use crate::interpreter::CellWidth;
+        let settings = CompilerSettings {
+            tape: TapeConfig::default()
+                .tape_size(1024)
+                .cell_width(CellWidth::U16),
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+>-".to_string(), settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.contains("add word [r12], 1\n"));
+        assert!(asm.contains("add r12, 2\n"));
+        assert!(asm.contains("TAPE_SIZE = 1024\n"));
+
+        let settings = CompilerSettings {
+            target: Target::LlvmIr,
+            tape: TapeConfig::default()
+                .tape_size(1024)
+                .cell_width(CellWidth::U16),
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+>-".to_string(), settings).unwrap();
+        let ir = compiler.compile_code().unwrap();
+        assert!(ir.starts_with("@tape = global [1024 x i16] zeroinitializer\n"));
+        assert!(ir.contains("sub i16 %value"));
+    }
+
+    #[test]
+    fn buffer_output_test() {
+        use super::{Compiler, CompilerSettings};
+        let settings = CompilerSettings {
+            buffer_output: true,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+.,.".to_string(), settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.contains("OUTPUT_SIZE = 4096\nOUTPUT rb OUTPUT_SIZE\n"));
+        assert!(asm.contains("EXIT:\ncall FLUSH_OUTPUT\n"));
+        assert!(asm.contains("READ_FROM_STDIN:\ncall FLUSH_OUTPUT\n"));
+        assert_eq!(asm.matches("call WRITE_TO_STDOUT").count(), 2);
+    }
+
+    #[test]
+    fn emit_as_function_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig};
+        let settings = CompilerSettings {
+            tape: TapeConfig::default().wrap(true),
+            emit_as_function: true,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+<.".to_string(), settings).unwrap();
+        let asm = compiler.compile_code().unwrap();
+        assert!(asm.starts_with("format ELF64\n\npublic bf_main\n"));
+        assert!(asm.contains("bf_main:\npush r12\npush r13\nmov r12, rdi\n"));
+        assert!(asm.contains("EXIT:\nadd rsp, 8\npop r13\npop r12\nret\n"));
+        assert!(asm.contains("cmp r12, r13\n"));
+        assert!(!asm.contains("entry"));
+        assert!(!asm.contains("SYS_exit\n"));
+        assert!(!asm.contains("TAPE rd"));
+    }
+
+    #[test]
+    fn assume_zeroed_test() {
+        use super::{Compiler, CompilerSettings, TapeConfig, Target};
+        use crate::interpreter::CellWidth;
+        for target in [Target::Fasm, Target::PeWin64] {
+            let settings = CompilerSettings {
+                target,
+                tape: TapeConfig::default().cell_width(CellWidth::U16),
+                assume_zeroed: false,
+                ..Default::default()
+            };
+            let compiler = Compiler::new("+", settings).unwrap();
+            let asm = compiler.compile_code().unwrap();
+            assert!(asm.contains(indoc! {"
+                ; Zero the tape
+                mov rdi, r12
+                xor eax, eax
+                mov rcx, TAPE_SIZE * 2
+                rep stosb
+                ; TokenType::Plus
+            "}));
+        }
+
+        let settings = CompilerSettings {
+            target: Target::LlvmIr,
+            assume_zeroed: false,
+            ..Default::default()
+        };
+        let ir = Compiler::new("+", settings)
+            .unwrap()
+            .compile_code()
+            .unwrap();
+        assert!(ir.contains("call void @llvm.memset.p0.i64(ptr @tape, i8 0, i64 30000, i1 false)"));
+
+        let compiler = Compiler::new("+", CompilerSettings::default()).unwrap();
+        assert!(!compiler.compile_code().unwrap().contains("Zero the tape"));
+    }
+
+    #[test]
+    fn debug_commands_test() {
+        use super::{Compiler, CompilerSettings};
+        let settings = CompilerSettings {
+            debug_commands: true,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+#".to_string(), settings).unwrap();
+        assert!(compiler
+            .compile_code()
+            .unwrap()
+            .contains("add byte [r12], 1\n; TokenType::Debug\n"));
+    }
+
+    #[test]
+    fn halt_command_test() {
+        use super::{Compiler, CompilerSettings, Target};
+        let settings = CompilerSettings {
+            halt_command: true,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+!+".to_string(), settings).unwrap();
+        assert!(compiler
+            .compile_code()
+            .unwrap()
+            .contains("; TokenType::Halt\ncall EXIT\n"));
+
+        let settings = CompilerSettings {
+            halt_command: true,
+            target: Target::LlvmIr,
+            ..Default::default()
+        };
+        let compiler = Compiler::new("+!+".to_string(), settings).unwrap();
+        assert!(compiler
+            .compile_code()
+            .unwrap()
+            .contains("  ret i32 0\n\nhalt_1:\n"));
+    }
+
+    #[test]
+    fn verify_output_test() {
+        use super::{verify_output, Compiler, CompilerSettings, Target};
+        for target in [
+            Target::Fasm,
+            Target::LlvmIr,
+            Target::PeWin64,
+            Target::Arm64,
+            Target::GasIntel,
+        ] {
+            let settings = CompilerSettings {
+                target,
+                ..Default::default()
+            };
+            let compiler = Compiler::new("+[>[-]<[->+<]]".to_string(), settings).unwrap();
+            assert_eq!(verify_output(&compiler.compile_code().unwrap()), Ok(()));
+        }
+
+        let asm = indoc! {"
+            je after_loop_1
+            loop_1:
+            jne loop_1
+            after_loop_1:
+            loop_1:
+        "};
+        assert_eq!(
+            verify_output(asm),
+            Err("Label loop_1 is defined 2 times".to_string())
+        );
+        assert_eq!(
+            verify_output("je after_loop_3\n"),
+            Err("Label after_loop_3 is referenced but never defined".to_string())
+        );
+    }
 }
@@ -0,0 +1,116 @@
+//! The fuzz module contains an entry point for fuzzing the whole pipeline
+//!
+//! The library is meant to never panic on any input, only to return errors. `fuzz_pipeline`
+//! runs an arbitrary input through every stage, so a fuzzer like `cargo fuzz` can check that:
+//!
+//! ```ignore
+//! #![no_main]
+//! use libfuzzer_sys::fuzz_target;
+//!
+//! fuzz_target!(|data: &[u8]| rbfc::fuzz::fuzz_pipeline(data));
+//! ```
+
+use crate::compiler::{verify_output, Compiler, CompilerSettings, Target};
+use crate::interpreter::{Interpreter, InterpreterSettings};
+use crate::memory::TapeConfig;
+use crate::optimizer;
+use crate::parser::Parser;
+
+/// The number of ops a fuzzed program may execute, so programs that never halt still end
+const MAX_STEPS: usize = 10000;
+
+/// Run arbitrary data through the lexer, parser, optimizer, interpreter and compiler
+///
+/// The data is read as lossy UTF-8. Programs that parse are interpreted without IO and with a
+/// step limit, once as parsed and once optimized, and compiled for every target at every
+/// optimization level, checking the labels of the output. Errors are expected and ignored, this
+/// function only panics on a bug.
+///
+/// # Arguments
+/// * `data` - The input to run, usually produced by a fuzzer
+///
+/// # Example
+/// ```
+/// use rbfc::fuzz::fuzz_pipeline;
+///
+/// fuzz_pipeline(b"+[->+<]>.");
+/// fuzz_pipeline(b"+[[]");
+/// ```
+pub fn fuzz_pipeline(data: &[u8]) {
+    let code = String::from_utf8_lossy(data).to_string();
+    let Ok(ops) = Parser::new(code.clone()).parse() else {
+        return;
+    };
+
+    for wrap in [false, true] {
+        let settings = || InterpreterSettings {
+            tape: TapeConfig::default().wrap(wrap),
+            no_io: true,
+            max_steps: Some(MAX_STEPS),
+            ..Default::default()
+        };
+        let _ = Interpreter::from_ops(ops.clone(), settings()).interpret();
+        let optimized = optimizer::optimize(ops.clone(), 3);
+        let _ = Interpreter::from_ops(optimized, settings()).interpret();
+    }
+
+    for target in [
+        Target::Fasm,
+        Target::LlvmIr,
+        Target::PeWin64,
+        Target::Arm64,
+        Target::GasIntel,
+    ] {
+        for opt_level in 0..=3 {
+            let settings = CompilerSettings {
+                opt_level,
+                target,
+                ..Default::default()
+            };
+            if let Ok(asm) = Compiler::new(code.clone(), settings).and_then(|c| c.compile_code()) {
+                if let Err(e) = verify_output(&asm) {
+                    panic!("Invalid output for {target:?} at -O{opt_level}: {e}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A xorshift generator, good enough to produce varied inputs without a dependency
+    fn random_bytes(seed: &mut u64, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                *seed ^= *seed << 13;
+                *seed ^= *seed >> 7;
+                *seed ^= *seed << 17;
+                *seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fuzz_random_bytes() {
+        let mut seed = 0x2545f4914f6cdd1d;
+        for len in 0..200 {
+            fuzz_pipeline(&random_bytes(&mut seed, len % 64));
+        }
+    }
+
+    #[test]
+    fn test_fuzz_random_programs() {
+        // Mostly operators, so that the inputs get past the parser
+        let commands = b"+-<>[].,# ";
+        let mut seed = 0x9e3779b97f4a7c15;
+        for len in 0..500 {
+            let program: Vec<u8> = random_bytes(&mut seed, len % 48)
+                .iter()
+                .map(|byte| commands[*byte as usize % commands.len()])
+                .collect();
+            fuzz_pipeline(&program);
+        }
+    }
+}
@@ -1,7 +1,14 @@
-use crate::lexer::{Token, TokenType};
+use crate::lexer::{LexerSettings, Token, TokenType};
+use crate::memory::{Memory, MemoryError, TapeConfig};
 use crate::parser::{Parser, ParserError};
 use log::{debug, trace};
-use std::io::Read;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 /// Error type for the interpreter
@@ -16,31 +23,426 @@ pub enum InterpreterError {
     InputError,
     #[error("Parsing error: {0}")]
     ParserError(ParserError),
-    #[error("Tape overflow at {0}")]
-    TapeOverflow(usize),
-    #[error("Tape underflow at {0}")]
-    TapeUnderflow(usize),
+    #[error("Tape overflow at {loc}, moving to cell {attempted} of a tape of {len} cells")]
+    TapeOverflow {
+        loc: usize,
+        attempted: isize,
+        len: usize,
+    },
+    #[error("Tape underflow at {loc}, moving to cell {attempted} of a tape of {len} cells")]
+    TapeUnderflow {
+        loc: usize,
+        attempted: isize,
+        len: usize,
+    },
+    #[error("Cell overflow at {0}")]
+    CellOverflow(usize),
+    #[error("Read of an uninitialized cell at {0}")]
+    UninitRead(usize),
+    #[error("Unexpected output error")]
+    OutputError,
+    #[error("Step limit of {0} exceeded")]
+    StepLimitExceeded(usize),
+    #[error("Timeout of {0:?} exceeded")]
+    Timeout(Duration),
+    #[error("Interpretation cancelled")]
+    Cancelled,
+    #[error("Region {start}..{end} is not self-contained")]
+    InvalidRegion { start: usize, end: usize },
+    #[error("Initial data pointer {dp} is not on the tape of {len} cells")]
+    InvalidInitialDp { dp: usize, len: usize },
+    #[cfg(feature = "jit")]
+    #[error("JIT error: {0}")]
+    JitError(String),
 }
 
+impl From<ParserError> for InterpreterError {
+    fn from(error: ParserError) -> Self {
+        InterpreterError::ParserError(error)
+    }
+}
+
+/// The number of ops executed between checks of the `timeout` setting and the cancel flag of
+/// `interpret_cancellable`, reading the clock after every op would slow down the interpreter
+/// noticeably
+const TIMEOUT_CHECK_INTERVAL: usize = 65536;
+
 /// The settings for the interpreter
 ///
-/// This struct is used to represent the settings for the interpreter. It contains the wrap
-/// setting which is used to determine whether the tape should wrap around
-/// or not
+/// This struct is used to represent the settings for the interpreter. It contains the tape
+/// which determines whether the tape should wrap around or not
 ///
 /// # Fields
-/// * `wrap` - Whether the tape should wrap around or not
+/// * `tape` - The size, wrapping and cell width of the tape, see `TapeConfig`
+/// * `detect_overflow` - Whether `+` and `-` return an error instead of wrapping the cell around,
+///   as do the optimized ops adding to or setting a cell
+/// * `no_io` - Whether `.` does nothing and `,` writes 0 instead of using stdout and stdin, which
+///   is useful for benchmarking the interpreter without the cost of IO
+/// * `track_uninit` - Whether reading a cell that was never written returns an error. The tape
+///   still starts zeroed, this only checks that programs don't rely on it. Cells are read by loop
+///   conditions, `.` and the optimized ops replacing loops, and written by `+`, `-`, `,` and the
+///   optimized ops adding to other cells
+/// * `buffer_output` - Whether the output is collected and only written at the end of
+///   `interpret`, before reading input or on `flush_output`, instead of after every `.`
+/// * `debug_commands` - Whether `#` dumps the tape, see `TokenType::Debug`. The tape is passed to
+///   the trace hook, or written to stderr if there is none
+/// * `halt_command` - Whether `!` ends the program, see `TokenType::Halt`
+/// * `max_steps` - The number of ops `interpret` executes before returning an error, which keeps
+///   programs that never halt from running forever
+/// * `timeout` - The time `interpret` runs before returning an error. The clock is only checked
+///   every `TIMEOUT_CHECK_INTERVAL` ops, so the program can run slightly longer
+/// * `track_access` - Whether the reads and writes of every cell are counted, see
+///   `Interpreter::access_heatmap`
+/// * `encoding` - How the bytes written by `.` are turned into characters, see `OutputEncoding`
+/// * `line_buffered_input` - Whether `,` reads a whole line of input at once and serves the
+///   following `,` from it until it is used up, instead of reading a single byte
+/// * `echo_input` - Whether every byte read by `,` is written to the output as well, like a
+///   terminal echoes what is typed, for input from a source that doesn't echo
+/// * `trace_format` - How the records written to the trace sink are encoded, see `TraceFormat`
+///   and `Interpreter::with_trace_sink`
+/// * `loop_sentinel` - The value of the current cell `[` skips the loop at and `]` ends it at,
+///   which is 0 in standard Brainfuck. `Scan` ops stop at it as well, the other optimized ops
+///   assume it is 0
+/// * `initial_dp` - The cell the data pointer starts at, which lets programs move left of their
+///   start without wrapping around when it is in the middle of the tape
+/// * `flush_every` - The number of bytes of output after which the output is flushed, written
+///   from the buffer with `buffer_output`, which shows the progress of programs that write their
+///   output over a long time. Without it the output is only flushed at the end and before reading
+///   input
+/// * `eof_policy` - What `,` does when the input has ended, see `EofPolicy`
+/// * `track_memory` - Whether the cells the program reaches are recorded, see
+///   `Interpreter::memory_report`
 ///
 /// # Example
 /// ```
-/// use rbfc::interpreter::{InterpreterSettings};
-/// let settings = InterpreterSettings { wrap: true };
+/// use rbfc::interpreter::{EofPolicy, InterpreterSettings, OutputEncoding, TraceFormat};
+/// use rbfc::memory::TapeConfig;
+/// let settings = InterpreterSettings {
+///     tape: TapeConfig::default().wrap(true),
+///     detect_overflow: false,
+///     no_io: false,
+///     track_uninit: false,
+///     buffer_output: false,
+///     debug_commands: false,
+///     halt_command: false,
+///     max_steps: None,
+///     timeout: None,
+///     track_access: false,
+///     line_buffered_input: false,
+///     echo_input: false,
+///     encoding: OutputEncoding::Latin1,
+///     trace_format: TraceFormat::Csv,
+///     loop_sentinel: 0,
+///     initial_dp: 0,
+///     flush_every: None,
+///     eof_policy: EofPolicy::Error,
+///     track_memory: false,
+/// };
 /// ```
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct InterpreterSettings {
-    pub wrap: bool,
+    pub tape: TapeConfig,
+    pub detect_overflow: bool,
+    pub no_io: bool,
+    pub track_uninit: bool,
+    pub buffer_output: bool,
+    pub debug_commands: bool,
+    pub halt_command: bool,
+    pub max_steps: Option<usize>,
+    pub timeout: Option<Duration>,
+    pub track_access: bool,
+    pub line_buffered_input: bool,
+    pub echo_input: bool,
+    pub encoding: OutputEncoding,
+    pub trace_format: TraceFormat,
+    pub loop_sentinel: u8,
+    pub initial_dp: usize,
+    pub flush_every: Option<usize>,
+    pub eof_policy: EofPolicy,
+    pub track_memory: bool,
+}
+
+impl From<&TapeConfig> for InterpreterSettings {
+    fn from(config: &TapeConfig) -> Self {
+        InterpreterSettings {
+            tape: *config,
+            ..Default::default()
+        }
+    }
+}
+
+/// The size of the tape of the interpreter, see `TapeConfig::kind`
+///
+/// # Variants
+/// * `Fixed` - A tape with the given number of cells, moving past its ends wraps around or
+///   returns an error depending on the `wrap_left` and `wrap_right` of the `TapeConfig`
+/// * `GrowRight` - A tape that starts with 30000 cells and grows when moving past its right end,
+///   moving past its left end always returns an error
+///
+/// # Example
+/// ```
+/// use rbfc::interpreter::Tape;
+/// assert_eq!(Tape::default(), Tape::Fixed(30000));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tape {
+    Fixed(usize),
+    GrowRight,
+}
+
+impl Tape {
+    /// The number of cells the tape starts with
+    pub(crate) fn initial_len(&self) -> usize {
+        match self {
+            Tape::Fixed(len) => *len,
+            Tape::GrowRight => 30000,
+        }
+    }
+}
+
+impl Default for Tape {
+    fn default() -> Self {
+        Tape::Fixed(30000)
+    }
+}
+
+/// The number of bits of a cell on the tape
+///
+/// Cells wrap around at their width, the output of a cell is its lowest byte.
+///
+/// # Example
+/// ```
+/// use rbfc::interpreter::CellWidth;
+/// let cell_width: CellWidth = "16".parse().unwrap();
+/// assert_eq!(cell_width, CellWidth::U16);
+/// assert_eq!(cell_width.mask(), 0xffff);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum CellWidth {
+    #[default]
+    U8,
+    U16,
+    U32,
+}
+
+impl CellWidth {
+    /// The number of bytes of a cell
+    pub fn bytes(&self) -> usize {
+        match self {
+            CellWidth::U8 => 1,
+            CellWidth::U16 => 2,
+            CellWidth::U32 => 4,
+        }
+    }
+
+    /// The number of bits of a cell
+    pub fn bits(&self) -> usize {
+        self.bytes() * 8
+    }
+
+    /// The largest value of a cell
+    pub fn mask(&self) -> u32 {
+        (u64::MAX >> (64 - self.bits())) as u32
+    }
+}
+
+impl std::str::FromStr for CellWidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(CellWidth::U8),
+            "16" => Ok(CellWidth::U16),
+            "32" => Ok(CellWidth::U32),
+            _ => Err(format!(
+                "Unknown cell width {s}, expected one of: 8, 16, 32"
+            )),
+        }
+    }
+}
+
+/// How the bytes written by `.` are turned into characters
+///
+/// # Variants
+/// * `Latin1` - Every byte is the character with the same code point, which is written as UTF-8
+/// * `Utf8` - The bytes are written as they are, a character made up of several bytes is only
+///   written once it is complete. Invalid sequences are replaced by U+FFFD
+/// * `Cp437` - Bytes above 127 are mapped through code page 437, the character set of the IBM PC
+///
+/// # Example
+/// ```
+/// use rbfc::interpreter::OutputEncoding;
+/// let encoding: OutputEncoding = "utf8".parse().unwrap();
+/// assert_eq!(encoding, OutputEncoding::Utf8);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum OutputEncoding {
+    #[default]
+    Latin1,
+    Utf8,
+    Cp437,
+}
+
+/// U+FFFD encoded in UTF-8, written for bytes that are not valid UTF-8 with `OutputEncoding::Utf8`
+const REPLACEMENT_BYTES: &[u8] = "\u{FFFD}".as_bytes();
+
+/// The characters of the bytes 128 to 255 in code page 437
+const CP437_HIGH: &str = concat!(
+    "ÇüéâäàåçêëèïîìÄÅ",
+    "ÉæÆôöòûùÿÖÜ¢£¥₧ƒ",
+    "áíóúñÑªº¿⌐¬½¼¡«»",
+    "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐",
+    "└┴┬├─┼╞╟╚╔╩╦╠═╬╧",
+    "╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀",
+    "αßΓπΣσµτΦΘΩδ∞φε∩",
+    "≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}",
+);
+
+impl std::str::FromStr for OutputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "latin1" => Ok(OutputEncoding::Latin1),
+            "utf8" => Ok(OutputEncoding::Utf8),
+            "cp437" => Ok(OutputEncoding::Cp437),
+            _ => Err(format!(
+                "Unknown encoding {s}, expected one of: latin1, utf8, cp437"
+            )),
+        }
+    }
+}
+
+/// What `,` does when the input has ended
+///
+/// # Variants
+/// * `Error` - Interpretation stops with `InterpreterError::InputError`
+/// * `Unchanged` - The current cell keeps its value and execution continues
+/// * `Value` - The current cell is set to the value and execution continues, which covers the
+///   dialects marking the end of the input with 0, 255 or another value
+///
+/// # Example
+/// ```
+/// use rbfc::interpreter::EofPolicy;
+/// let policy: EofPolicy = "255".parse().unwrap();
+/// assert_eq!(policy, EofPolicy::Value(255));
+/// assert_eq!("unchanged".parse(), Ok(EofPolicy::Unchanged));
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum EofPolicy {
+    #[default]
+    Error,
+    Unchanged,
+    Value(u8),
+}
+
+impl std::str::FromStr for EofPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(EofPolicy::Error),
+            "unchanged" => Ok(EofPolicy::Unchanged),
+            _ => s.parse().map(EofPolicy::Value).map_err(|_| {
+                format!("Unknown EOF policy {s}, expected one of: error, unchanged, 0 to 255")
+            }),
+        }
+    }
+}
+
+/// How the records written to the trace sink are encoded
+///
+/// Every executed op appends a record of the op, the program counter, the data pointer and the
+/// value of the current cell, in the state the op is executed in.
+///
+/// # Variants
+/// * `Csv` - A line `op,pc,dp,cell` per record, where `op` is the name of the `TokenType`
+/// * `Binary` - 21 bytes per record: the index of the `TokenType` as a byte, `pc` and `dp` as
+///   64 bit and `cell` as 32 bit little endian integers
+///
+/// # Example
+/// ```
+/// use rbfc::interpreter::TraceFormat;
+/// assert_eq!(TraceFormat::default(), TraceFormat::Csv);
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    #[default]
+    Csv,
+    Binary,
+}
+
+/// The index and the name of a `TokenType` in a trace record
+fn trace_op(token_type: &TokenType) -> (u8, &'static str) {
+    match token_type {
+        TokenType::Eof => (0, "Eof"),
+        TokenType::ShiftLeft => (1, "ShiftLeft"),
+        TokenType::ShiftRight => (2, "ShiftRight"),
+        TokenType::Plus => (3, "Plus"),
+        TokenType::Minus => (4, "Minus"),
+        TokenType::Dot => (5, "Dot"),
+        TokenType::Comma => (6, "Comma"),
+        TokenType::OpenBracket => (7, "OpenBracket"),
+        TokenType::CloseBracket => (8, "CloseBracket"),
+        TokenType::Clear => (9, "Clear"),
+        TokenType::MulAdd { .. } => (10, "MulAdd"),
+        TokenType::AddAt { .. } => (11, "AddAt"),
+        TokenType::SetValue { .. } => (12, "SetValue"),
+        TokenType::Debug => (13, "Debug"),
+        TokenType::Halt => (14, "Halt"),
+        TokenType::Scan { .. } => (15, "Scan"),
+    }
+}
+
+/// An event passed to the trace hook of the interpreter for every executed op
+///
+/// # Fields
+/// * `op` - The op that is about to be executed
+/// * `pc` - The program counter, the index of the op
+/// * `dp` - The data pointer
+/// * `cell` - The value of the current cell
+/// * `tape` - The whole tape, only given for `TokenType::Debug` ops to dump it
+/// * `depth` - The number of loops the op is nested in, see `Interpreter::current_depth`
+#[derive(Debug)]
+pub struct ExecEvent<'a> {
+    pub op: &'a Token,
+    pub pc: usize,
+    pub dp: usize,
+    pub cell: u32,
+    pub tape: Option<&'a [u32]>,
+    pub depth: usize,
+}
+
+/// A snapshot of the state of a running interpreter
+///
+/// Together with the operations of the program this is everything needed to continue running it
+/// later, see `Interpreter::save_state` and `Interpreter::restore_state`.
+///
+/// # Fields
+/// * `tape` - The contents of the tape
+/// * `pc` - The program counter
+/// * `dp` - The data pointer
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterpreterState {
+    pub tape: Vec<u32>,
+    pub pc: usize,
+    pub dp: usize,
+}
+
+/// How much of the tape a program used, see `Interpreter::memory_report`
+///
+/// # Fields
+/// * `peak_offset` - The rightmost cell that was reached
+/// * `cells_touched` - The number of distinct cells that were reached
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryReport {
+    pub peak_offset: usize,
+    pub cells_touched: usize,
 }
 
+/// A callback registered with `Interpreter::set_trace_hook`
+pub type TraceHook = Box<dyn FnMut(&ExecEvent)>;
+
 /// The interpreter struct
 ///
 /// This struct is used to represent the interpreter. It contains the tape, the operations
@@ -48,11 +450,34 @@ pub struct InterpreterSettings {
 /// such as the ascii flag
 ///
 /// # Fields
-/// * `tape` - The tape for the program
+/// * `memory` - The tape for the program and the data pointer into it
 /// * `ops` - The operations for the program
 /// * `pc` - The program counter
-/// * `dp` - The data pointer
 /// * `settings` - The settings for the interpreter
+/// * `trace_hook` - A callback that is called for every executed op
+/// * `trace_sink` - Where a record of every executed op is written to, see `with_trace_sink`
+/// * `written` - Which cells have been written, only tracked with `track_uninit`
+/// * `touched` - Which cells have been reached, only tracked with `track_memory`
+/// * `max_dp` - The rightmost cell that has been reached, only tracked with `track_memory`
+/// * `depth` - The number of loops whose body is being executed
+/// * `reads` - How often every cell has been read, only tracked with `track_access`
+/// * `writes` - How often every cell has been written, only tracked with `track_access`
+/// * `output` - Where the output is written to, stdout by default
+/// * `output_buffer` - The output that is not written yet, only used with `buffer_output`
+/// * `partial_char` - The bytes of a character that is not complete yet, only used with the
+///   `Utf8` encoding
+/// * `capture_output` - Whether `.` collects its output in `pending_output`, which is done by
+///   `run_to_output`
+/// * `pending_output` - The output that is not returned by `run_to_output` yet
+/// * `input` - Where the input is read from, stdin by default
+/// * `input_line` - The rest of the last line of input, only used with `line_buffered_input`
+/// * `cancel` - The flag that stops `interpret_cancellable` once it is set
+/// * `unflushed` - The number of bytes written since the output was last flushed, only counted
+///   with `flush_every`
+/// * `executed` - The number of ops executed since the interpreter was created or reset
+/// * `observed` - Whether every op is checked or recorded before it is executed, by the tracking
+///   settings, the trace hook or the trace sink, which keeps the other runs from testing each of
+///   them for every op
 ///
 /// # Example
 /// ```
@@ -71,14 +496,32 @@ pub struct InterpreterSettings {
 /// use rbfc::parser::ParserError;
 ///
 /// let mut interpreter = Interpreter::new("+++[->+<".to_string(), InterpreterSettings::default());
-/// matches!(interpreter, Err(InterpreterError::ParserError(ParserError::UnexpectedEof(6, 1))));
+/// matches!(interpreter, Err(InterpreterError::ParserError(ParserError::UnexpectedEof { eof: 6, open: 1 })));
 /// ```
 pub struct Interpreter {
-    tape: [u8; 30000],
+    memory: Memory,
     ops: Vec<Token>,
     pc: usize,
-    dp: usize,
     settings: InterpreterSettings,
+    trace_hook: Option<TraceHook>,
+    trace_sink: Option<Box<dyn Write>>,
+    written: Vec<bool>,
+    touched: Vec<bool>,
+    max_dp: usize,
+    depth: usize,
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    output: Box<dyn Write>,
+    output_buffer: Vec<u8>,
+    partial_char: Vec<u8>,
+    capture_output: bool,
+    pending_output: VecDeque<u8>,
+    input: Box<dyn Read>,
+    input_line: VecDeque<u8>,
+    cancel: Option<Arc<AtomicBool>>,
+    unflushed: usize,
+    executed: usize,
+    observed: bool,
 }
 
 impl Interpreter {
@@ -94,209 +537,2638 @@ impl Interpreter {
     /// let input = String::from("+++.>+++.>.>.");
     /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
     /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the code can't be parsed or the `initial_dp` is not on the tape
     pub fn new(
-        code: String,
+        code: impl Into<String>,
         settings: InterpreterSettings,
     ) -> Result<Interpreter, InterpreterError> {
-        let mut parser = Parser::new(code);
-        let ops = match parser.parse() {
-            Ok(ops) => ops,
-            Err(e) => return Err(InterpreterError::ParserError(e)),
+        let len = settings.tape.kind().initial_len();
+        if settings.initial_dp >= len {
+            return Err(InterpreterError::InvalidInitialDp {
+                dp: settings.initial_dp,
+                len,
+            });
+        }
+        let ops = Interpreter::parse(code.into(), &settings)?;
+        Ok(Interpreter::from_ops(ops, settings))
+    }
+
+    /// Parse the code with the lexer settings given by the interpreter settings
+    fn parse(code: String, settings: &InterpreterSettings) -> Result<Vec<Token>, InterpreterError> {
+        let lexer_settings = LexerSettings {
+            debug_commands: settings.debug_commands,
+            halt_command: settings.halt_command,
+            ..Default::default()
         };
-        Ok(Interpreter {
-            tape: [u8::default(); 30000],
+        let mut parser = Parser::with_settings(code, lexer_settings);
+        Ok(parser.parse()?)
+    }
+
+    /// Replace the program with new code, keeping the tape and the data pointer
+    ///
+    /// The new program starts at its first op, which allows running a program piece by piece
+    /// against the same tape, e.g. in a REPL.
+    ///
+    /// # Arguments
+    /// * `code` - The code of the new program
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let settings = InterpreterSettings::default();
+    /// let mut interpreter = Interpreter::new(String::from("+++>"), settings).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// interpreter.load(String::from("++<")).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// assert_eq!(interpreter.render_tape(4), "[03] 02");
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the code can't be parsed, the old program is kept then
+    pub fn load(&mut self, code: impl Into<String>) -> Result<(), InterpreterError> {
+        self.ops = Interpreter::parse(code.into(), &self.settings)?;
+        self.pc = 0;
+        self.depth = 0;
+        Ok(())
+    }
+
+    /// Clear the tape and move the data pointer back to the `initial_dp` and the program counter
+    /// back to the start, which also resets the count of `executed_ops`, the `memory_report` and
+    /// the `access_heatmap`
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let settings = InterpreterSettings::default();
+    /// let mut interpreter = Interpreter::new(String::from("+++>"), settings).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// interpreter.reset();
+    /// assert_eq!(interpreter.render_tape(4), "[00]");
+    /// ```
+    pub fn reset(&mut self) {
+        self.memory.clear();
+        self.memory.set_dp(self.settings.initial_dp);
+        self.written.fill(false);
+        self.touched.fill(false);
+        self.max_dp = self.settings.initial_dp;
+        self.reads.fill(0);
+        self.writes.fill(0);
+        self.pc = 0;
+        self.depth = 0;
+        self.executed = 0;
+    }
+
+    /// Create a new instance of the interpreter from already parsed operations
+    ///
+    /// This skips lexing and parsing, which is useful when running the same program many times.
+    /// The operations have to be valid output of the parser (or the optimizer), otherwise
+    /// `interpret` may return an error. Unlike in `new` the `initial_dp` is not checked, the
+    /// interpreter panics if it is not on the tape.
+    ///
+    /// # Arguments
+    /// * `ops` - The operations to be interpreted
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    /// use rbfc::parser::Parser;
+    ///
+    /// let ops = Parser::new(String::from("+++.")).parse().unwrap();
+    /// let mut interpreter = Interpreter::from_ops(ops, InterpreterSettings::default());
+    /// interpreter.interpret().unwrap();
+    /// ```
+    pub fn from_ops(ops: Vec<Token>, settings: InterpreterSettings) -> Interpreter {
+        let len = settings.tape.kind().initial_len();
+        let tracked_cells = if settings.track_uninit { len } else { 0 };
+        let counted_cells = if settings.track_access { len } else { 0 };
+        let reached_cells = if settings.track_memory { len } else { 0 };
+        let observed = settings.track_uninit || settings.track_access || settings.track_memory;
+        let mut memory = Memory::new(
+            settings.tape.kind(),
+            settings.tape.wrap_left,
+            settings.tape.wrap_right,
+        );
+        memory.set_dp(settings.initial_dp);
+        let max_dp = settings.initial_dp;
+        Interpreter {
+            memory,
             ops,
             pc: 0,
-            dp: 0,
             settings,
-        })
+            trace_hook: None,
+            trace_sink: None,
+            written: vec![false; tracked_cells],
+            touched: vec![false; reached_cells],
+            max_dp,
+            depth: 0,
+            reads: vec![0; counted_cells],
+            writes: vec![0; counted_cells],
+            output: Box::new(std::io::stdout()),
+            output_buffer: Vec::new(),
+            partial_char: Vec::new(),
+            capture_output: false,
+            pending_output: VecDeque::new(),
+            input: Box::new(std::io::stdin()),
+            input_line: VecDeque::new(),
+            cancel: None,
+            unflushed: 0,
+            executed: 0,
+            observed,
+        }
     }
 
-    /// Execute the operations
+    /// Clone the interpreter without its IO
     ///
-    /// This method is used to execute the operations. It iterates over the operations and executes
-    /// them. It returns a Result with the unit type or an InterpreterError
+    /// The program, the settings, the tape and the position in the program are copied, so the
+    /// clone continues where the interpreter is. The writers, the reader and the trace hook can't
+    /// be cloned, the clone uses stdout and stdin and has no trace hook or sink. Output that is
+    /// buffered or not returned by `run_to_output` yet and the rest of a buffered input line stay
+    /// with the original.
     ///
     /// # Example
     /// ```
     /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
     ///
-    /// let input = String::from("+++.>+++.>.>.");
+    /// let mut interpreter = Interpreter::new("+[->+<]", InterpreterSettings::default()).unwrap();
+    /// let mut clone = interpreter.clone_without_io();
+    /// interpreter.interpret().unwrap();
+    /// clone.interpret().unwrap();
+    /// assert_eq!(interpreter.memory(), clone.memory());
+    /// ```
+    pub fn clone_without_io(&self) -> Interpreter {
+        let mut clone = Interpreter::from_ops(self.ops.clone(), self.settings.clone());
+        clone.memory = self.memory.clone();
+        clone.pc = self.pc;
+        clone.written = self.written.clone();
+        clone.touched = self.touched.clone();
+        clone.max_dp = self.max_dp;
+        clone.depth = self.depth;
+        clone.reads = self.reads.clone();
+        clone.writes = self.writes.clone();
+        clone
+    }
+
+    /// Set the cell at the given position, e.g. to seed the tape before running
+    ///
+    /// # Arguments
+    /// * `dp` - The position of the cell
+    /// * `value` - The value to set the cell to, wrapped around at the cell width
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from("[->+<]");
     /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.set_cell(0, 5);
     /// interpreter.interpret().unwrap();
     /// ```
     ///
-    /// # Errors
-    /// Returns an error if the input string contains unmatched brackets
-    /// or if the input string ends unexpectedly
+    /// # Panics
+    /// Panics if the position is outside of a fixed size tape
+    pub fn set_cell(&mut self, dp: usize, value: u32) {
+        self.memory
+            .set_at(dp, value & self.settings.tape.cell_width.mask());
+        self.grow_to(dp);
+        if let Some(written) = self.written.get_mut(dp) {
+            *written = true;
+        }
+    }
+
+    /// Copy the values into the start of the tape, e.g. to seed the tape before running
+    ///
+    /// # Arguments
+    /// * `values` - The values to copy, the first one ends up in the first cell
+    ///
+    /// # Example
     /// ```
-    /// use rbfc::interpreter::{Interpreter, InterpreterSettings, InterpreterError};
-    /// use rbfc::parser::ParserError;
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
     ///
-    /// let mut interpreter = Interpreter::new("+++[->+<".to_string(), InterpreterSettings::default());
-    /// matches!(interpreter, Err(InterpreterError::ParserError(ParserError::UnexpectedEof(6, 1))));
+    /// let input = String::from("[>]");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.load_tape(&[1, 2, 3]);
+    /// interpreter.interpret().unwrap();
     /// ```
-    pub fn interpret(&mut self) -> Result<(), InterpreterError> {
-        while self.pc < self.ops.len() {
-            let op = &self.ops[self.pc];
-            trace!("Tape: {:?}", self.tape[0..10].to_vec());
-            match op.token_type {
-                TokenType::Eof => break,
-                TokenType::Plus => {
-                    if let Some(size) = op.size {
-                        self.tape[self.dp] = self.tape[self.dp].wrapping_add(size as u8);
-                    } else {
-                        return Err(InterpreterError::UnexpectedNoneSize(op.loc));
-                    }
-                    debug!(
-                        "Plus: (loc: {loc}, dp: {dp}, tape: {tape})",
-                        loc = op.loc,
-                        dp = self.dp,
-                        tape = self.tape[self.dp]
-                    );
-                }
-                TokenType::Minus => {
-                    if let Some(size) = op.size {
-                        self.tape[self.dp] = self.tape[self.dp].wrapping_sub(size as u8);
-                    } else {
-                        return Err(InterpreterError::UnexpectedNoneSize(op.loc));
-                    }
-                    debug!(
-                        "Minus: (loc: {loc}, dp: {dp}, tape: {tape})",
-                        loc = op.loc,
-                        dp = self.dp,
-                        tape = self.tape[self.dp]
-                    );
-                }
-                TokenType::ShiftRight => {
-                    if let Some(size) = op.size {
-                        if self.dp + size >= self.tape.len() {
-                            if self.settings.wrap {
-                                self.dp = self.dp + size - self.tape.len();
-                            } else {
-                                return Err(InterpreterError::TapeOverflow(op.loc));
-                            }
-                        } else {
-                            self.dp += size;
-                        }
-                    } else {
-                        return Err(InterpreterError::UnexpectedNoneSize(op.loc));
-                    }
-                    debug!(
-                        "ShiftRight: (loc: {loc}, dp: {dp}, tape: {tape})",
-                        loc = op.loc,
-                        dp = self.dp,
-                        tape = self.tape[self.dp]
-                    );
-                }
-                TokenType::ShiftLeft => {
-                    if let Some(size) = op.size {
-                        if self.dp < size {
-                            if self.settings.wrap {
-                                self.dp += self.tape.len() - (size - self.dp);
-                            } else {
-                                return Err(InterpreterError::TapeUnderflow(op.loc));
-                            }
-                        } else {
-                            self.dp -= size;
-                        }
-                    } else {
-                        return Err(InterpreterError::UnexpectedNoneSize(op.loc));
-                    }
-                    debug!(
-                        "ShiftLeft: (loc: {loc}, dp: {dp}, tape: {tape})",
-                        loc = op.loc,
-                        dp = self.dp,
-                        tape = self.tape[self.dp]
-                    );
-                }
-                TokenType::Dot => {
-                    debug!(
-                        "Dot: (loc: {loc}, dp: {dp}, tape: {tape})",
-                        loc = op.loc,
-                        dp = self.dp,
-                        tape = self.tape[self.dp]
-                    );
-                    let op = &self.ops[self.pc];
-                    match op.size {
-                        Some(size) => {
-                            for _ in 0..size {
-                                print!("{}", self.tape[self.dp] as char);
-                            }
-                        }
-                        None => return Err(InterpreterError::UnexpectedNoneSize(op.loc)),
-                    }
-                }
-                TokenType::Comma => {
-                    debug!(
-                        "Comma: (loc: {loc}, dp: {dp}, tape: {tape})",
-                        loc = op.loc,
-                        dp = self.dp,
-                        tape = self.tape[self.dp]
-                    );
-                    if let Some(size) = op.size {
-                        for _ in 0..size {
-                            let c = std::io::stdin()
-                                .bytes()
-                                .next()
-                                .and_then(|result| result.ok());
-                            match c {
-                                Some(c) => self.tape[self.dp] = c,
-                                None => return Err(InterpreterError::InputError),
-                            }
-                        }
-                    } else {
-                        return Err(InterpreterError::UnexpectedNoneSize(op.loc));
-                    }
-                }
-                TokenType::OpenBracket => {
-                    debug!(
-                        "OpenBracket: (loc: {loc}, dp: {dp}, tape: {tape})",
-                        loc = op.loc,
-                        dp = self.dp,
-                        tape = self.tape[self.dp]
-                    );
-                    if self.tape[self.dp] == 0 {
-                        if let Some(size) = op.size {
-                            self.pc = size;
-                        } else {
-                            let op = &self.ops[self.pc];
-                            return Err(InterpreterError::UnexpectedNoneSize(op.loc));
-                        }
-                    }
-                }
-                TokenType::CloseBracket => {
-                    debug!(
-                        "CloseBracket: (loc: {loc}, dp: {dp}, tape: {tape})",
-                        loc = op.loc,
-                        dp = self.dp,
-                        tape = self.tape[self.dp]
-                    );
-                    if self.tape[self.dp] != 0 {
-                        if let Some(size) = op.size {
-                            self.pc = size;
-                        } else {
-                            let op = &self.ops[self.pc];
-                            return Err(InterpreterError::UnexpectedNoneSize(op.loc));
-                        }
-                    }
-                }
+    ///
+    /// # Panics
+    /// Panics if there are more values than cells on a fixed size tape
+    pub fn load_tape(&mut self, values: &[u32]) {
+        for (dp, &value) in values.iter().enumerate() {
+            self.set_cell(dp, value);
+        }
+    }
+
+    /// Take a snapshot of the state of the interpreter
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from("+++>++");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// let state = interpreter.save_state();
+    /// assert_eq!(state.tape[..2], [3, 2]);
+    /// assert_eq!(state.dp, 1);
+    /// ```
+    pub fn save_state(&self) -> InterpreterState {
+        InterpreterState {
+            tape: self.memory.cells().to_vec(),
+            pc: self.pc,
+            dp: self.memory.dp(),
+        }
+    }
+
+    /// Continue from a snapshot taken with `save_state`
+    ///
+    /// The snapshot has to be taken from an interpreter running the same operations. Cells that
+    /// are not part of the snapshot are set to zero. The snapshot doesn't record which cells have
+    /// been written, so with `track_uninit` only its non-zero cells count as written.
+    ///
+    /// # Arguments
+    /// * `state` - The snapshot to continue from
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings, InterpreterState};
+    ///
+    /// let input = String::from("[->+<]");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.restore_state(InterpreterState { tape: vec![5], pc: 0, dp: 0 });
+    /// interpreter.interpret().unwrap();
+    /// assert_eq!(interpreter.save_state().tape[..2], [0, 5]);
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the snapshot has more cells than a fixed size tape
+    pub fn restore_state(&mut self, state: InterpreterState) {
+        self.memory.cells_mut().fill(0);
+        self.written.fill(false);
+        for (dp, &value) in state.tape.iter().enumerate() {
+            let value = value & self.settings.tape.cell_width.mask();
+            self.memory.set_at(dp, value);
+            self.grow_to(dp);
+            if let Some(written) = self.written.get_mut(dp) {
+                *written = value != 0;
             }
-            self.pc += 1;
         }
-        Ok(())
+        self.pc = state.pc;
+        self.memory.set_dp(state.dp);
+        // Every loop that contains the program counter is being executed
+        self.depth = self.ops[..self.pc.min(self.ops.len())]
+            .iter()
+            .filter(|op| op.token_type == TokenType::OpenBracket)
+            .filter(|op| op.size.is_some_and(|after| after > self.pc))
+            .count();
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// Replace where the output of the program is written to
+    ///
+    /// # Arguments
+    /// * `output` - The writer to write the output to
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from("+++.");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.set_output(Box::new(std::io::sink()));
+    /// interpreter.interpret().unwrap();
+    /// ```
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
 
-    #[test]
-    fn test_interpreter() {
-        let input = String::from("++[->+<]");
-        let settings: InterpreterSettings = Default::default();
-        let mut interpreter = Interpreter::new(input, settings).unwrap();
-        interpreter.interpret().unwrap();
+    /// Replace where the input of the program is read from
+    ///
+    /// # Arguments
+    /// * `input` - The reader to read the input from
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from(",.");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.set_input(Box::new("a".as_bytes()));
+    /// interpreter.set_output(Box::new(std::io::sink()));
+    /// interpreter.interpret().unwrap();
+    /// ```
+    pub fn set_input(&mut self, input: Box<dyn Read>) {
+        self.input = input;
+        self.input_line.clear();
+    }
+
+    /// Write the buffered output
+    ///
+    /// This is done automatically at the end of `interpret` and before reading input, so it is
+    /// only needed to see the output of a program that is still running, e.g. from a trace hook.
+    /// With the `Utf8` encoding the bytes of a character that is not complete yet are written as
+    /// U+FFFD.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let settings = InterpreterSettings { buffer_output: true, ..Default::default() };
+    /// let mut interpreter = Interpreter::new(String::from("+++."), settings).unwrap();
+    /// interpreter.set_output(Box::new(std::io::sink()));
+    /// interpreter.interpret().unwrap();
+    /// interpreter.flush_output().unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the output can't be written
+    pub fn flush_output(&mut self) -> Result<(), InterpreterError> {
+        if !self.partial_char.is_empty() {
+            self.partial_char.clear();
+            self.write_encoded(REPLACEMENT_BYTES)?;
+        }
+        self.write_buffer()
+    }
+
+    /// Write the buffered output, keeping the bytes of a character that is not complete yet
+    pub(crate) fn write_buffer(&mut self) -> Result<(), InterpreterError> {
+        self.output
+            .write_all(&self.output_buffer)
+            .and_then(|_| self.output.flush())
+            .map_err(|_| InterpreterError::OutputError)?;
+        self.output_buffer.clear();
+        self.unflushed = 0;
+        Ok(())
+    }
+
+    /// Register a callback that is called for every executed op
+    ///
+    /// The callback is called before the op is executed, so the event contains the state the op
+    /// is executed in. This replaces a previously registered callback.
+    ///
+    /// # Arguments
+    /// * `hook` - The callback to call with every event
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from("+>+");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.set_trace_hook(Box::new(|event| println!("{:?} at {}", event.op, event.dp)));
+    /// interpreter.interpret().unwrap();
+    /// ```
+    pub fn set_trace_hook(&mut self, hook: TraceHook) {
+        self.trace_hook = Some(hook);
+        self.observed = true;
+    }
+
+    /// Write a record of every executed op to a sink
+    ///
+    /// This keeps a trace of very long runs for offline analysis without holding it in memory.
+    /// The records are encoded in the `trace_format` of the settings and the sink is flushed at
+    /// the end of `interpret`. A `BufWriter` keeps the many small writes cheap.
+    ///
+    /// # Arguments
+    /// * `sink` - The writer to write the records to
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let interpreter = Interpreter::new("+>+", InterpreterSettings::default()).unwrap();
+    /// let mut interpreter = interpreter.with_trace_sink(Box::new(std::io::sink()));
+    /// interpreter.interpret().unwrap();
+    /// ```
+    pub fn with_trace_sink(mut self, sink: Box<dyn Write>) -> Interpreter {
+        self.trace_sink = Some(sink);
+        self.observed = true;
+        self
+    }
+
+    /// Execute the operations
+    ///
+    /// This method is used to execute the operations. It iterates over the operations and executes
+    /// them. It returns a Result with the unit type or an InterpreterError
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from("+++.>+++.>.>.");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the input string contains unmatched brackets
+    /// or if the input string ends unexpectedly
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings, InterpreterError};
+    /// use rbfc::parser::ParserError;
+    ///
+    /// let mut interpreter = Interpreter::new("+++[->+<".to_string(), InterpreterSettings::default());
+    /// matches!(interpreter, Err(InterpreterError::ParserError(ParserError::UnexpectedEof { eof: 6, open: 1 })));
+    /// ```
+    pub fn interpret(&mut self) -> Result<(), InterpreterError> {
+        let result = self.run();
+        self.flush_output()?;
+        if let Some(sink) = &mut self.trace_sink {
+            sink.flush().map_err(|_| InterpreterError::OutputError)?;
+        }
+        result
+    }
+
+    /// Execute the operations like `interpret` until the program ends or the flag is set
+    ///
+    /// The flag is checked every `TIMEOUT_CHECK_INTERVAL` ops, so setting it from another thread,
+    /// e.g. from the stop button of a GUI, stops the program shortly after. The interpreter keeps
+    /// its state, so the program can be continued with `interpret`.
+    ///
+    /// # Arguments
+    /// * `cancel` - The flag that stops the program once it is set
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterError, InterpreterSettings};
+    /// use std::sync::atomic::AtomicBool;
+    /// use std::sync::Arc;
+    ///
+    /// let mut interpreter = Interpreter::new("+[]", InterpreterSettings::default()).unwrap();
+    /// let result = interpreter.interpret_cancellable(Arc::new(AtomicBool::new(true)));
+    /// assert_eq!(result, Err(InterpreterError::Cancelled));
+    /// ```
+    ///
+    /// # Errors
+    /// Returns `InterpreterError::Cancelled` once the flag is set, or any error of `interpret`
+    pub fn interpret_cancellable(
+        &mut self,
+        cancel: Arc<AtomicBool>,
+    ) -> Result<(), InterpreterError> {
+        self.cancel = Some(cancel);
+        let result = self.interpret();
+        self.cancel = None;
+        result
+    }
+
+    /// Execute the operations until the program outputs a byte and return it
+    ///
+    /// The byte is returned instead of being written to the output. Calling this again continues
+    /// after the output, so the output of a program can be pulled one byte at a time. Once the
+    /// program has halted `None` is returned. `max_steps` only applies to `interpret`.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from("+.+.");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// assert_eq!(interpreter.run_to_output().unwrap(), Some(1));
+    /// assert_eq!(interpreter.run_to_output().unwrap(), Some(2));
+    /// assert_eq!(interpreter.run_to_output().unwrap(), None);
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if an op fails like in `interpret`
+    pub fn run_to_output(&mut self) -> Result<Option<u8>, InterpreterError> {
+        self.capture_output = true;
+        let result = loop {
+            if let Some(byte) = self.pending_output.pop_front() {
+                break Ok(Some(byte));
+            }
+            match self.step_op() {
+                Ok(true) => {}
+                Ok(false) => break Ok(None),
+                Err(e) => break Err(e),
+            }
+        };
+        self.capture_output = false;
+        result
+    }
+
+    /// Run the program on the bytes of the queue as its input and return its output together with
+    /// the input it left unread
+    ///
+    /// Unread input often means the program stopped reading too early, which makes this useful
+    /// for testing. Bytes already read into the line buffer of `line_buffered_input` count as
+    /// unread. The input and output set before are restored afterwards.
+    ///
+    /// # Arguments
+    /// * `input` - The input of the program
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let mut interpreter = Interpreter::new(",+.", InterpreterSettings::default()).unwrap();
+    /// let (output, leftover) = interpreter.run_with_input_queue(b"ab".iter().copied().collect()).unwrap();
+    /// assert_eq!(output, b"b");
+    /// assert_eq!(leftover, [b'b']);
+    /// ```
+    ///
+    /// # Errors
+    /// Returns the same errors as `interpret`, reading past the end of the queue returns
+    /// `InterpreterError::InputError`
+    pub fn run_with_input_queue(
+        &mut self,
+        input: VecDeque<u8>,
+    ) -> Result<(Vec<u8>, VecDeque<u8>), InterpreterError> {
+        let input = SharedBuffer::new(input);
+        let output = SharedBuffer::new(Vec::new());
+        let previous_input = std::mem::replace(&mut self.input, Box::new(input.clone()));
+        let previous_output = std::mem::replace(&mut self.output, Box::new(output.clone()));
+        self.input_line.clear();
+
+        let result = self.interpret();
+        self.input = previous_input;
+        self.output = previous_output;
+        result?;
+
+        let mut leftover: VecDeque<u8> = self.input_line.drain(..).collect();
+        leftover.extend(input.take_contents());
+        Ok((output.take_contents(), leftover))
+    }
+
+    /// Execute only the operations in `start..end`, on the current tape and data pointer
+    ///
+    /// This runs a piece of a program, like the body of a loop, in isolation. Every bracket in the
+    /// region has to be matched by a bracket inside of it. The output is flushed at the end like
+    /// in `interpret`, but `max_steps` and `timeout` only apply to `interpret`.
+    ///
+    /// # Arguments
+    /// * `start` - The index of the first op to execute
+    /// * `end` - The index after the last op to execute
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from("[->+<]");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.load_tape(&[4]);
+    /// interpreter.run_region(1, 5).unwrap();
+    /// assert_eq!(interpreter.memory().cells()[..2], [3, 1]);
+    /// ```
+    ///
+    /// # Errors
+    /// Returns `InterpreterError::InvalidRegion` if the region is not within the ops or contains a
+    /// bracket matched outside of it, otherwise the errors of the ops like in `interpret`
+    pub fn run_region(&mut self, start: usize, end: usize) -> Result<(), InterpreterError> {
+        let invalid = InterpreterError::InvalidRegion { start, end };
+        if start > end || end > self.ops.len() {
+            return Err(invalid);
+        }
+        // An opening bracket jumps past its closing bracket, which may be the end of the region
+        let escapes = self.ops[start..end].iter().any(|op| match op.token_type {
+            TokenType::OpenBracket => !op
+                .size
+                .is_some_and(|target| target > start && target <= end),
+            TokenType::CloseBracket => !op
+                .size
+                .is_some_and(|target| target > start && target <= end),
+            _ => false,
+        });
+        if escapes {
+            return Err(invalid);
+        }
+
+        self.pc = start;
+        let mut result = Ok(());
+        while self.pc < end {
+            match self.step_op() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.write_buffer()?;
+        result
+    }
+
+    /// Execute the operations up to the end of the program or the first error
+    fn run(&mut self) -> Result<(), InterpreterError> {
+        let start = Instant::now();
+        let mut steps = 0;
+        while self.step_op()? {
+            steps += 1;
+            if let Some(max_steps) = self.settings.max_steps.filter(|&max| steps >= max) {
+                return Err(InterpreterError::StepLimitExceeded(max_steps));
+            }
+            if steps % TIMEOUT_CHECK_INTERVAL == 0 {
+                if let Some(timeout) = self.settings.timeout.filter(|&t| start.elapsed() >= t) {
+                    return Err(InterpreterError::Timeout(timeout));
+                }
+                if self
+                    .cancel
+                    .as_ref()
+                    .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+                {
+                    return Err(InterpreterError::Cancelled);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Execute the operation at the program counter, returning whether the program continues
+    ///
+    /// This is the loop body of every run, so it is inlined into them
+    #[inline(always)]
+    fn step_op(&mut self) -> Result<bool, InterpreterError> {
+        if self.pc >= self.ops.len() {
+            return Ok(false);
+        }
+        let Token {
+            token_type,
+            size,
+            loc,
+            ..
+        } = self.ops[self.pc];
+        if self.observed {
+            self.observe(token_type)?;
+        }
+        trace!("Tape:\n{}", self.render_tape(16));
+        if token_type != TokenType::Eof {
+            self.executed += 1;
+        }
+        match token_type {
+            TokenType::Eof | TokenType::Halt => return Ok(false),
+            TokenType::Plus => {
+                if let Some(size) = size {
+                    if self.settings.detect_overflow
+                        && self.memory.get() as usize + size > self.mask() as usize
+                    {
+                        return Err(InterpreterError::CellOverflow(loc));
+                    }
+                    self.memory
+                        .set(self.memory.get().wrapping_add(size as u32) & self.mask());
+                } else {
+                    return Err(InterpreterError::UnexpectedNoneSize(loc));
+                }
+                debug!(
+                    "Plus: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+            }
+            TokenType::Minus => {
+                if let Some(size) = size {
+                    if self.settings.detect_overflow && (self.memory.get() as usize) < size {
+                        return Err(InterpreterError::CellOverflow(loc));
+                    }
+                    self.memory
+                        .set(self.memory.get().wrapping_sub(size as u32) & self.mask());
+                } else {
+                    return Err(InterpreterError::UnexpectedNoneSize(loc));
+                }
+                debug!(
+                    "Minus: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+            }
+            TokenType::ShiftRight => {
+                if let Some(size) = size {
+                    self.shift(size as isize, loc)?;
+                } else {
+                    return Err(InterpreterError::UnexpectedNoneSize(loc));
+                }
+                debug!(
+                    "ShiftRight: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+            }
+            TokenType::ShiftLeft => {
+                if let Some(size) = size {
+                    self.shift(-(size as isize), loc)?;
+                } else {
+                    return Err(InterpreterError::UnexpectedNoneSize(loc));
+                }
+                debug!(
+                    "ShiftLeft: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+            }
+            TokenType::Dot => {
+                debug!(
+                    "Dot: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+                match size {
+                    Some(_) if self.settings.no_io => {}
+                    Some(size) if self.capture_output => {
+                        let byte = self.memory.get() as u8;
+                        self.pending_output.extend(std::iter::repeat_n(byte, size));
+                    }
+                    Some(size) => {
+                        let byte = self.memory.get() as u8;
+                        for _ in 0..size {
+                            self.write_output(byte)?;
+                        }
+                    }
+                    None => return Err(InterpreterError::UnexpectedNoneSize(loc)),
+                }
+            }
+            TokenType::Comma => {
+                debug!(
+                    "Comma: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+                if self.settings.no_io && size.is_some() {
+                    self.memory.set(0);
+                } else if let Some(size) = size {
+                    // Flushing before blocking on input makes sure prompts are visible, even when
+                    // the output is not buffered by the interpreter but by the writer, like stdout
+                    // is by line
+                    self.write_buffer()?;
+                    for _ in 0..size {
+                        let value = self.input_cell(self.memory.get())?;
+                        self.memory.set(value);
+                    }
+                } else {
+                    return Err(InterpreterError::UnexpectedNoneSize(loc));
+                }
+            }
+            TokenType::OpenBracket => {
+                debug!(
+                    "OpenBracket: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+                if self.memory.get() == self.settings.loop_sentinel as u32 {
+                    // The target is already past the closing bracket, so it isn't stepped over
+                    if let Some(size) = size {
+                        self.pc = size;
+                        return Ok(true);
+                    } else {
+                        return Err(InterpreterError::UnexpectedNoneSize(loc));
+                    }
+                } else {
+                    self.depth += 1;
+                }
+            }
+            TokenType::CloseBracket => {
+                debug!(
+                    "CloseBracket: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+                if self.memory.get() != self.settings.loop_sentinel as u32 {
+                    // The target is the first op of the body, the condition was just tested
+                    if let Some(size) = size {
+                        self.pc = size;
+                        return Ok(true);
+                    } else {
+                        return Err(InterpreterError::UnexpectedNoneSize(loc));
+                    }
+                } else {
+                    self.depth = self.depth.saturating_sub(1);
+                }
+            }
+            TokenType::Clear => {
+                self.memory.set(0);
+                debug!(
+                    "Clear: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+            }
+            TokenType::MulAdd { offset, factor } => {
+                debug!(
+                    "MulAdd: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+                if self.memory.get() != 0 {
+                    let target = self.offset_dp(offset, loc)?;
+                    self.touch(target);
+                    let delta = (self.memory.get() as i64).wrapping_mul(factor as i64);
+                    let value = self.add_to_cell(self.memory.get_at(target), delta, loc)?;
+                    self.memory.set_at(target, value);
+                }
+            }
+            TokenType::SetValue { value } => {
+                let value = self.add_to_cell(0, value as i64, loc)?;
+                self.memory.set(value);
+                debug!(
+                    "SetValue: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+            }
+            TokenType::AddAt { offset, delta } => {
+                let target = self.offset_dp(offset, loc)?;
+                self.touch(target);
+                let value = self.add_to_cell(self.memory.get_at(target), delta as i64, loc)?;
+                self.memory.set_at(target, value);
+                debug!(
+                    "AddAt: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = target,
+                    tape = self.memory.get_at(target)
+                );
+            }
+            TokenType::Scan { step } => {
+                // The first cell was already checked and counted before the op
+                while self.memory.get() != self.settings.loop_sentinel as u32 {
+                    self.shift(step, loc)?;
+                    self.touch(self.memory.dp());
+                    if self.settings.track_uninit && !self.written[self.memory.dp()] {
+                        return Err(InterpreterError::UninitRead(loc));
+                    }
+                    if self.settings.track_access {
+                        self.reads[self.memory.dp()] += 1;
+                    }
+                }
+                debug!(
+                    "Scan: (loc: {loc}, dp: {dp}, tape: {tape})",
+                    dp = self.memory.dp(),
+                    tape = self.memory.get()
+                );
+            }
+            TokenType::Debug => {
+                // With a trace hook the tape was already passed to it
+                if self.trace_hook.is_none() {
+                    eprintln!("{}", self.render_tape(16));
+                }
+            }
+        }
+        self.pc += 1;
+        Ok(true)
+    }
+
+    /// Execute the operations by compiling them to native code first
+    ///
+    /// This is only available with the `jit` feature. It produces the same tape and output as
+    /// `interpret`, but is much faster for long running programs. IO goes through the input and
+    /// output of the interpreter with all of its IO settings, and errors report the location of
+    /// the failing operation like `interpret`. Trace hooks and sinks are not called and debug
+    /// commands are skipped.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from("++++++++[>++++++++<-]>+.");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.jit_run().unwrap();
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if cranelift fails to compile the operations or if the program fails in
+    /// the same way `interpret` would. Returns `InterpreterError::JitError` for the settings the
+    /// compiled code can't honor: cells wider than 8 bit, a `GrowRight` tape, `detect_overflow`,
+    /// `max_steps`, `timeout`, `track_uninit`, `track_access` and `track_memory`
+    #[cfg(feature = "jit")]
+    pub fn jit_run(&mut self) -> Result<(), InterpreterError> {
+        let unsupported = [
+            (
+                self.settings.tape.cell_width != CellWidth::U8,
+                "cells wider than 8 bit",
+            ),
+            (self.settings.tape.grow_right, "a growing tape"),
+            (self.settings.detect_overflow, "detect_overflow"),
+            (self.settings.max_steps.is_some(), "max_steps"),
+            (self.settings.timeout.is_some(), "timeout"),
+            (self.settings.track_uninit, "track_uninit"),
+            (self.settings.track_access, "track_access"),
+            (self.settings.track_memory, "track_memory"),
+        ];
+        if let Some((_, setting)) = unsupported.iter().find(|(used, _)| *used) {
+            return Err(InterpreterError::JitError(format!(
+                "{setting} is not supported"
+            )));
+        }
+        let mut tape: Vec<u8> = self.memory.cells().iter().map(|&cell| cell as u8).collect();
+        let mut dp = self.memory.dp();
+        let (ops, settings) = (self.ops.clone(), self.settings.clone());
+        let result = crate::jit::run(&ops, &mut tape, &mut dp, &settings, self);
+        for (cell, value) in self.memory.cells_mut().iter_mut().zip(tape) {
+            *cell = value as u32;
+        }
+        self.memory.set_dp(dp);
+        self.flush_output()?;
+        result?;
+        self.pc = self.ops.len();
+        Ok(())
+    }
+
+    /// How much of the tape the program has used so far
+    ///
+    /// A cell counts as reached if the data pointer has been on it when an op was executed, or if
+    /// an optimized op changed it. The report helps choosing the smallest `--tape-size` a compiled
+    /// program still runs with, which is `peak_offset + 1`. Cells are only recorded with the
+    /// `track_memory` setting, without it no cell counts as reached.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings, MemoryReport};
+    ///
+    /// let input = String::from(">>+<+");
+    /// let settings = InterpreterSettings { track_memory: true, ..Default::default() };
+    /// let mut interpreter = Interpreter::new(input, settings).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// assert_eq!(
+    ///     interpreter.memory_report(),
+    ///     MemoryReport { peak_offset: 2, cells_touched: 3 }
+    /// );
+    /// ```
+    pub fn memory_report(&self) -> MemoryReport {
+        MemoryReport {
+            peak_offset: self.max_dp,
+            cells_touched: self.touched.iter().filter(|&&touched| touched).count(),
+        }
+    }
+
+    /// The number of ops that have been executed, by `interpret` as well as by `run_to_output` and
+    /// `run_region`
+    ///
+    /// A run like `+++` is a single op, and the `Eof` the program ends with doesn't count. Every
+    /// pass through a loop executes its closing bracket, but not its opening bracket again.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let mut interpreter = Interpreter::new("++[-]", InterpreterSettings::default()).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// assert_eq!(interpreter.executed_ops(), 6);
+    /// ```
+    pub fn executed_ops(&self) -> usize {
+        self.executed
+    }
+
+    /// The tape of the program and the data pointer into it
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let mut interpreter = Interpreter::new("+>++", InterpreterSettings::default()).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// assert_eq!(interpreter.memory().dp(), 1);
+    /// assert_eq!(interpreter.memory().cells()[..2], [1, 2]);
+    /// ```
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// The number of loops whose body is currently being executed
+    ///
+    /// A loop is entered when its opening bracket finds a non zero cell and left when its closing
+    /// bracket finds a zero cell, skipped loops are never entered. The trace hook gets the depth
+    /// of every op as well, e.g. to show the progress of a long running program.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from("+[-]");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// assert_eq!(interpreter.current_depth(), 0);
+    /// ```
+    pub fn current_depth(&self) -> usize {
+        self.depth
+    }
+
+    /// How often the cells have been read and written, only counted with `track_access`
+    ///
+    /// Every executed op counts as a single access, so a run like `+++` reads and writes its cell
+    /// once. Loop conditions and `.` read the current cell, `,` and clear loops write it. Only
+    /// cells that have been accessed are listed, as `(position, reads, writes)` in tape order.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let settings = InterpreterSettings { track_access: true, ..Default::default() };
+    /// let mut interpreter = Interpreter::new(String::from("+[->+<]"), settings).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// assert_eq!(interpreter.access_heatmap(), vec![(0, 4, 2), (1, 1, 1)]);
+    /// ```
+    pub fn access_heatmap(&self) -> Vec<(usize, u64, u64)> {
+        self.reads
+            .iter()
+            .zip(&self.writes)
+            .enumerate()
+            .filter(|(_, (&reads, &writes))| reads > 0 || writes > 0)
+            .map(|(position, (&reads, &writes))| (position, reads, writes))
+            .collect()
+    }
+
+    /// Render the used region of the tape as a grid of hex values
+    ///
+    /// The region starts at the first cell and ends at the last nonzero cell or the data pointer,
+    /// whichever is further right. Every cell is rendered as a hex value with two digits per byte
+    /// of the cell width, the cell under the data pointer is put in brackets.
+    ///
+    /// # Arguments
+    /// * `width` - The number of cells in a row of the grid
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::{Interpreter, InterpreterSettings};
+    ///
+    /// let input = String::from("+++>++");
+    /// let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+    /// interpreter.interpret().unwrap();
+    /// assert_eq!(interpreter.render_tape(4), " 03 [02]");
+    /// ```
+    pub fn render_tape(&self, width: usize) -> String {
+        let last_nonzero = self
+            .memory
+            .cells()
+            .iter()
+            .rposition(|&cell| cell != 0)
+            .unwrap_or(0);
+        let end = last_nonzero.max(self.memory.dp());
+        let width = width.max(1);
+        let digits = self.settings.tape.cell_width.bytes() * 2;
+
+        self.memory.cells()[..=end]
+            .chunks(width)
+            .enumerate()
+            .map(|(row, cells)| {
+                let line: String = cells
+                    .iter()
+                    .enumerate()
+                    .map(|(column, cell)| {
+                        if row * width + column == self.memory.dp() {
+                            format!("[{cell:0digits$x}]")
+                        } else {
+                            format!(" {cell:0digits$x} ")
+                        }
+                    })
+                    .collect();
+                line.trim_end().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The largest value of a cell
+    fn mask(&self) -> u32 {
+        self.settings.tape.cell_width.mask()
+    }
+
+    /// The value of a cell after adding `delta` to it, wrapped around or, with `detect_overflow`,
+    /// a `CellOverflow` error at `loc` if it leaves the range of a cell
+    fn add_to_cell(&self, value: u32, delta: i64, loc: usize) -> Result<u32, InterpreterError> {
+        let sum = (value as i64).wrapping_add(delta);
+        if self.settings.detect_overflow && !(0..=self.mask() as i64).contains(&sum) {
+            return Err(InterpreterError::CellOverflow(loc));
+        }
+        Ok(sum as u32 & self.mask())
+    }
+
+    /// Write a byte to the output or the output buffer, encoded with the `encoding` setting
+    pub(crate) fn write_output(&mut self, byte: u8) -> Result<(), InterpreterError> {
+        let mut encoded = [0; 4];
+        let bytes = match self.settings.encoding {
+            OutputEncoding::Latin1 => (byte as char).encode_utf8(&mut encoded).as_bytes(),
+            OutputEncoding::Cp437 if byte >= 128 => {
+                let c = CP437_HIGH.chars().nth(byte as usize - 128).unwrap_or('?');
+                c.encode_utf8(&mut encoded).as_bytes()
+            }
+            OutputEncoding::Cp437 => std::slice::from_ref(&byte),
+            OutputEncoding::Utf8 => {
+                self.partial_char.push(byte);
+                loop {
+                    match std::str::from_utf8(&self.partial_char) {
+                        Ok(c) => {
+                            encoded[..c.len()].copy_from_slice(c.as_bytes());
+                            let len = c.len();
+                            self.partial_char.clear();
+                            break &encoded[..len];
+                        }
+                        Err(e) => match e.error_len() {
+                            // The character is not complete yet
+                            None => return Ok(()),
+                            // The invalid sequence is replaced, the bytes after it may start the
+                            // next character
+                            Some(len) => {
+                                let end = e.valid_up_to() + len;
+                                let mut replaced = self.partial_char[..e.valid_up_to()].to_vec();
+                                replaced.extend_from_slice(REPLACEMENT_BYTES);
+                                self.partial_char.drain(..end);
+                                self.write_encoded(&replaced)?;
+                                if self.partial_char.is_empty() {
+                                    return Ok(());
+                                }
+                            }
+                        },
+                    }
+                }
+            }
+        };
+        self.write_encoded(bytes)
+    }
+
+    /// Write encoded bytes to the output or the output buffer, flushing with `flush_every`
+    fn write_encoded(&mut self, bytes: &[u8]) -> Result<(), InterpreterError> {
+        let len = bytes.len();
+        if self.settings.buffer_output {
+            self.output_buffer.extend_from_slice(bytes);
+        } else {
+            self.output
+                .write_all(bytes)
+                .map_err(|_| InterpreterError::OutputError)?;
+        }
+
+        if let Some(every) = self.settings.flush_every {
+            self.unflushed += len;
+            if self.unflushed >= every {
+                self.write_buffer()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check and record the current op before it is executed, with the tracking settings, the
+    /// trace hook and the trace sink
+    fn observe(&mut self, token_type: TokenType) -> Result<(), InterpreterError> {
+        if self.settings.track_uninit {
+            self.track_uninit()?;
+        }
+        if self.settings.track_access {
+            self.track_access()?;
+        }
+        self.touch(self.memory.dp());
+        if let Some(hook) = &mut self.trace_hook {
+            hook(&ExecEvent {
+                op: &self.ops[self.pc],
+                pc: self.pc,
+                dp: self.memory.dp(),
+                cell: self.memory.get(),
+                tape: (token_type == TokenType::Debug).then_some(self.memory.cells()),
+                depth: self.depth,
+            });
+        }
+        if self.trace_sink.is_some() {
+            self.write_trace(token_type)?;
+        }
+        Ok(())
+    }
+
+    /// Check that the current op only reads written cells and mark the cells it writes
+    fn track_uninit(&mut self) -> Result<(), InterpreterError> {
+        let Token {
+            token_type, loc, ..
+        } = self.ops[self.pc];
+        match token_type {
+            TokenType::Dot
+            | TokenType::OpenBracket
+            | TokenType::CloseBracket
+            | TokenType::Scan { .. } => {
+                if !self.written[self.memory.dp()] {
+                    return Err(InterpreterError::UninitRead(loc));
+                }
+            }
+            TokenType::Plus | TokenType::Minus | TokenType::Comma => {
+                self.written[self.memory.dp()] = true;
+            }
+            // These replace loops like `[-]`, which read the cell before writing it
+            TokenType::Clear | TokenType::SetValue { .. } => {
+                if !self.written[self.memory.dp()] {
+                    return Err(InterpreterError::UninitRead(loc));
+                }
+            }
+            TokenType::MulAdd { offset, .. } => {
+                if !self.written[self.memory.dp()] {
+                    return Err(InterpreterError::UninitRead(loc));
+                }
+                // The loop it replaces only reaches the target if the cell isn't zero
+                if self.memory.get() != 0 {
+                    let target = self.offset_dp(offset, loc)?;
+                    self.written[target] = true;
+                }
+            }
+            TokenType::AddAt { offset, .. } => {
+                let target = self.offset_dp(offset, loc)?;
+                self.written[target] = true;
+            }
+            TokenType::Eof
+            | TokenType::ShiftLeft
+            | TokenType::ShiftRight
+            | TokenType::Debug
+            | TokenType::Halt => {}
+        }
+        Ok(())
+    }
+
+    /// Write the record of an op about to be executed to the trace sink
+    fn write_trace(&mut self, token_type: TokenType) -> Result<(), InterpreterError> {
+        let (index, name) = trace_op(&token_type);
+        let record = match self.settings.trace_format {
+            TraceFormat::Csv => format!(
+                "{name},{},{},{}\n",
+                self.pc,
+                self.memory.dp(),
+                self.memory.get()
+            )
+            .into_bytes(),
+            TraceFormat::Binary => {
+                let mut record = vec![index];
+                record.extend((self.pc as u64).to_le_bytes());
+                record.extend((self.memory.dp() as u64).to_le_bytes());
+                record.extend(self.memory.get().to_le_bytes());
+                record
+            }
+        };
+        if let Some(sink) = &mut self.trace_sink {
+            sink.write_all(&record)
+                .map_err(|_| InterpreterError::OutputError)?;
+        }
+        Ok(())
+    }
+
+    /// Count the reads and writes of the cells the current op accesses
+    fn track_access(&mut self) -> Result<(), InterpreterError> {
+        let op = self.ops[self.pc].clone();
+        match op.token_type {
+            TokenType::Dot
+            | TokenType::OpenBracket
+            | TokenType::CloseBracket
+            | TokenType::Scan { .. } => {
+                self.reads[self.memory.dp()] += 1;
+            }
+            TokenType::Plus | TokenType::Minus => {
+                self.reads[self.memory.dp()] += 1;
+                self.writes[self.memory.dp()] += 1;
+            }
+            TokenType::Comma | TokenType::Clear | TokenType::SetValue { .. } => {
+                self.writes[self.memory.dp()] += 1;
+            }
+            TokenType::MulAdd { offset, .. } => {
+                self.reads[self.memory.dp()] += 1;
+                if self.memory.get() != 0 {
+                    let target = self.offset_dp(offset, op.loc)?;
+                    self.reads[target] += 1;
+                    self.writes[target] += 1;
+                }
+            }
+            TokenType::AddAt { offset, .. } => {
+                let target = self.offset_dp(offset, op.loc)?;
+                self.reads[target] += 1;
+                self.writes[target] += 1;
+            }
+            TokenType::Eof
+            | TokenType::ShiftLeft
+            | TokenType::ShiftRight
+            | TokenType::Debug
+            | TokenType::Halt => {}
+        }
+        Ok(())
+    }
+
+    /// Read a byte of input into a cell, returning its new value
+    ///
+    /// At the end of the input the `eof_policy` decides the value, a byte that was read is echoed
+    /// with `echo_input`.
+    ///
+    /// # Arguments
+    /// * `cell` - The current value of the cell
+    pub(crate) fn input_cell(&mut self, cell: u32) -> Result<u32, InterpreterError> {
+        let byte = match (self.read_input()?, self.settings.eof_policy) {
+            (Some(byte), _) => byte,
+            (None, EofPolicy::Error) => return Err(InterpreterError::InputError),
+            (None, EofPolicy::Unchanged) => return Ok(cell),
+            (None, EofPolicy::Value(value)) => return Ok(value as u32),
+        };
+        if self.settings.echo_input && self.capture_output {
+            self.pending_output.push_back(byte);
+        } else if self.settings.echo_input {
+            self.write_output(byte)?;
+        }
+        Ok(byte as u32)
+    }
+
+    /// Read a byte of input, refilling the line buffer first with `line_buffered_input`, or
+    /// `None` if the input has ended
+    fn read_input(&mut self) -> Result<Option<u8>, InterpreterError> {
+        let mut c = [0];
+        if !self.settings.line_buffered_input {
+            return match self.input.read_exact(&mut c) {
+                Ok(()) => Ok(Some(c[0])),
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+                Err(_) => Err(InterpreterError::InputError),
+            };
+        }
+
+        if self.input_line.is_empty() {
+            while self
+                .input
+                .read(&mut c)
+                .map_err(|_| InterpreterError::InputError)?
+                == 1
+            {
+                self.input_line.push_back(c[0]);
+                if c[0] == b'\n' {
+                    break;
+                }
+            }
+        }
+        Ok(self.input_line.pop_front())
+    }
+
+    /// Mark the cell at the given position as reached, with `track_memory`
+    fn touch(&mut self, position: usize) {
+        if !self.settings.track_memory {
+            return;
+        }
+        self.touched[position] = true;
+        self.max_dp = self.max_dp.max(position);
+    }
+
+    /// Grow the tracked state of the cells along with the tape, so that the given position is on
+    /// it
+    fn grow_to(&mut self, position: usize) {
+        // The state of a fixed size tape is tracked for all of its cells from the start
+        if !self.settings.tape.grow_right {
+            return;
+        }
+        let len = position + 1;
+        if self.settings.track_memory && len > self.touched.len() {
+            self.touched.resize(len, false);
+        }
+        if self.settings.track_uninit && len > self.written.len() {
+            self.written.resize(len, false);
+        }
+        if self.settings.track_access && len > self.reads.len() {
+            self.reads.resize(len, 0);
+            self.writes.resize(len, 0);
+        }
+    }
+
+    /// Get the position of the cell at `offset` relative to the data pointer, wrapping it around
+    /// or growing the tape if the settings allow it
+    #[inline]
+    fn offset_dp(&mut self, offset: isize, loc: usize) -> Result<usize, InterpreterError> {
+        let dp = self.memory.dp();
+        let target = self.memory.position(offset).map_err(|e| {
+            let (attempted, len) = (dp as isize + offset, self.memory.len());
+            match e {
+                MemoryError::Overflow => InterpreterError::TapeOverflow {
+                    loc,
+                    attempted,
+                    len,
+                },
+                MemoryError::Underflow => InterpreterError::TapeUnderflow {
+                    loc,
+                    attempted,
+                    len,
+                },
+            }
+        })?;
+        self.grow_to(target);
+        Ok(target)
+    }
+
+    /// Move the data pointer by `offset` cells, wrapping it around or growing the tape if the
+    /// settings allow it
+    fn shift(&mut self, offset: isize, loc: usize) -> Result<(), InterpreterError> {
+        let dp = self.offset_dp(offset, loc)?;
+        self.memory.set_dp(dp);
+        Ok(())
+    }
+}
+
+/// A buffer that is passed to the interpreter as its input or output and can still be read
+/// after the run
+///
+/// Clones share the buffer, so a clone is passed to the interpreter. A `SharedBuffer<VecDeque<u8>>`
+/// is read from like a queue and a `SharedBuffer<Vec<u8>>` is written to.
+///
+/// # Example
+/// ```
+/// use rbfc::interpreter::{Interpreter, InterpreterSettings, SharedBuffer};
+///
+/// let output = SharedBuffer::new(Vec::new());
+/// let mut interpreter = Interpreter::new(">-[-----<+>]<-.", InterpreterSettings::default()).unwrap();
+/// interpreter.set_output(Box::new(output.clone()));
+/// interpreter.interpret().unwrap();
+/// assert_eq!(output.take_contents(), b"2");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer<T>(Rc<RefCell<T>>);
+
+impl<T> SharedBuffer<T> {
+    /// Create a buffer holding the value
+    pub fn new(value: T) -> SharedBuffer<T> {
+        SharedBuffer(Rc::new(RefCell::new(value)))
+    }
+
+    /// Take the value out of the buffer, leaving the default value in its place
+    pub fn take_contents(&self) -> T
+    where
+        T: Default,
+    {
+        self.0.take()
+    }
+}
+
+impl Read for SharedBuffer<VecDeque<u8>> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+impl Write for SharedBuffer<Vec<u8>> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::optimizer;
+
+    #[test]
+    fn test_interpreter() {
+        let input = String::from("++[->+<]");
+        let settings: InterpreterSettings = Default::default();
+        let mut interpreter = Interpreter::new(input, settings).unwrap();
+        interpreter.interpret().unwrap();
+    }
+
+    #[test]
+    fn test_memory() {
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().wrap(true).tape_size(4),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new("+<++<+++>>>>[-<+>]<", settings).unwrap();
+        interpreter.interpret().unwrap();
+
+        let mut memory = Memory::new(Tape::Fixed(4), true, true);
+        for (shift, value) in [(0, 1), (-1, 2), (-1, 3), (4, 0)] {
+            memory.shift(shift).unwrap();
+            memory.set(memory.get() + value);
+        }
+        let moved = memory.get();
+        memory.set(0);
+        memory.shift(-1).unwrap();
+        memory.set(memory.get() + moved);
+        assert_eq!(interpreter.memory(), &memory);
+        assert_eq!(interpreter.render_tape(4), " 01 [03] 00  02");
+    }
+
+    #[test]
+    fn test_from_ops() {
+        let token = |token_type, size, loc| Token {
+            token_type,
+            size,
+            loc,
+            leading_trivia: None,
+        };
+        let ops = vec![
+            token(TokenType::Plus, Some(2), 0),
+            token(TokenType::OpenBracket, Some(7), 2),
+            token(TokenType::Minus, Some(1), 3),
+            token(TokenType::ShiftRight, Some(1), 4),
+            token(TokenType::Plus, Some(3), 5),
+            token(TokenType::ShiftLeft, Some(1), 8),
+            token(TokenType::CloseBracket, Some(2), 9),
+            token(TokenType::Eof, None, 10),
+        ];
+        let mut from_ops = Interpreter::from_ops(ops, InterpreterSettings::default());
+        from_ops.interpret().unwrap();
+
+        let input = String::from("++[->+++<]");
+        let mut new = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+        new.interpret().unwrap();
+
+        assert_eq!(from_ops.memory.cells(), new.memory.cells());
+        assert_eq!(from_ops.memory.dp(), new.memory.dp());
+        assert_eq!(from_ops.memory.cells()[1], 6);
+    }
+
+    #[test]
+    fn test_detect_overflow() {
+        let input = "+".repeat(256);
+        let settings = InterpreterSettings {
+            detect_overflow: true,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(input.clone(), settings.clone()).unwrap();
+        assert_eq!(
+            interpreter.interpret(),
+            Err(InterpreterError::CellOverflow(0))
+        );
+
+        let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[0], 0);
+
+        // The ops of the optimizer overflow like the code they replace
+        for (input, opt_level) in [
+            ("++++++++++++++++[->++++++++++++++++<]", 2),
+            (">-<", 3),
+            ("[-]-", 1),
+        ] {
+            let ops = optimizer::optimize(Parser::new(input).parse().unwrap(), opt_level);
+            let mut interpreter = Interpreter::from_ops(ops.clone(), settings.clone());
+            assert!(
+                matches!(
+                    interpreter.interpret(),
+                    Err(InterpreterError::CellOverflow(_))
+                ),
+                "{input}"
+            );
+            let mut interpreter = Interpreter::from_ops(ops, InterpreterSettings::default());
+            interpreter.interpret().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_max_steps() {
+        let settings = InterpreterSettings {
+            max_steps: Some(100),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from("+[]"), settings).unwrap();
+        assert!(matches!(
+            interpreter.interpret(),
+            Err(InterpreterError::StepLimitExceeded(100))
+        ));
+
+        // Shifting further than the tape is long wraps around more than once
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().wrap(true).tape_size(3),
+            max_steps: Some(100),
+            ..Default::default()
+        };
+        let mut interpreter =
+            Interpreter::new(String::from("+>>>>>>>+<<<<<<<<+"), settings).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells(), [1, 1, 1]);
+    }
+
+    #[test]
+    fn test_wrap_one_direction() {
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().wrap_right(true).tape_size(3),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(">>>+<<", settings).unwrap();
+        assert_eq!(
+            interpreter.interpret(),
+            Err(InterpreterError::TapeUnderflow {
+                loc: 4,
+                attempted: -2,
+                len: 3
+            })
+        );
+        assert_eq!(interpreter.memory.cells(), [1, 0, 0]);
+
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().wrap_left(true).tape_size(3),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new("<+>>", settings).unwrap();
+        assert_eq!(
+            interpreter.interpret(),
+            Err(InterpreterError::TapeOverflow {
+                loc: 2,
+                attempted: 4,
+                len: 3
+            })
+        );
+        assert_eq!(interpreter.memory.cells(), [0, 0, 1]);
+    }
+
+    #[test]
+    fn test_tape_overflow_target() {
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().tape_size(30000),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(">".repeat(40000), settings).unwrap();
+        let error = interpreter.interpret().unwrap_err();
+        assert_eq!(
+            error,
+            InterpreterError::TapeOverflow {
+                loc: 0,
+                attempted: 40000,
+                len: 30000
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Tape overflow at 0, moving to cell 40000 of a tape of 30000 cells"
+        );
+    }
+
+    #[test]
+    fn test_initial_dp() {
+        let settings = InterpreterSettings {
+            initial_dp: 100,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new("<".repeat(10) + "+", settings.clone()).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.dp(), 90);
+        assert_eq!(interpreter.memory.cells()[90], 1);
+        interpreter.reset();
+        assert_eq!(interpreter.memory.dp(), 100);
+
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().tape_size(100),
+            ..settings
+        };
+        assert!(matches!(
+            Interpreter::new("<", settings),
+            Err(InterpreterError::InvalidInitialDp { dp: 100, len: 100 })
+        ));
+    }
+
+    #[test]
+    fn test_timeout() {
+        let settings = InterpreterSettings {
+            timeout: Some(Duration::from_millis(1)),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from("+[]"), settings).unwrap();
+        assert_eq!(
+            interpreter.interpret(),
+            Err(InterpreterError::Timeout(Duration::from_millis(1)))
+        );
+    }
+
+    #[test]
+    fn test_interpret_cancellable() {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&cancel);
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let mut interpreter = Interpreter::new("+[]", InterpreterSettings::default()).unwrap();
+        assert_eq!(
+            interpreter.interpret_cancellable(cancel),
+            Err(InterpreterError::Cancelled)
+        );
+        stopper.join().unwrap();
+
+        let mut interpreter = Interpreter::new("+++", InterpreterSettings::default()).unwrap();
+        interpreter
+            .interpret_cancellable(Arc::new(AtomicBool::new(false)))
+            .unwrap();
+        assert_eq!(interpreter.memory.get(), 3);
+    }
+
+    #[test]
+    fn test_access_heatmap() {
+        let settings = InterpreterSettings {
+            track_access: true,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from("++"), settings).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.access_heatmap(), vec![(0, 1, 1)]);
+        interpreter.reset();
+        assert!(interpreter.access_heatmap().is_empty());
+
+        let mut interpreter =
+            Interpreter::new(String::from("++"), InterpreterSettings::default()).unwrap();
+        interpreter.interpret().unwrap();
+        assert!(interpreter.access_heatmap().is_empty());
+    }
+
+    #[test]
+    fn test_run_to_output() {
+        let mut interpreter =
+            Interpreter::new(String::from("+.+."), InterpreterSettings::default()).unwrap();
+        assert_eq!(interpreter.run_to_output().unwrap(), Some(1));
+        assert_eq!(interpreter.run_to_output().unwrap(), Some(2));
+        assert_eq!(interpreter.run_to_output().unwrap(), None);
+        assert_eq!(interpreter.run_to_output().unwrap(), None);
+
+        // A run of dots yields every byte before continuing
+        let mut interpreter =
+            Interpreter::new(String::from("+..+."), InterpreterSettings::default()).unwrap();
+        let mut output = Vec::new();
+        while let Some(byte) = interpreter.run_to_output().unwrap() {
+            output.push(byte);
+        }
+        assert_eq!(output, [1, 1, 2]);
+    }
+
+    #[test]
+    fn test_current_depth() {
+        let settings = InterpreterSettings {
+            max_steps: Some(3),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new("+[[+]]", settings).unwrap();
+        assert!(interpreter.interpret().is_err());
+        assert_eq!(interpreter.current_depth(), 2);
+
+        let state = interpreter.save_state();
+        interpreter.reset();
+        assert_eq!(interpreter.current_depth(), 0);
+        interpreter.restore_state(state);
+        assert_eq!(interpreter.current_depth(), 2);
+
+        let depths = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new("+[[-]]", InterpreterSettings::default()).unwrap();
+        let recorded = std::rc::Rc::clone(&depths);
+        interpreter.set_trace_hook(Box::new(move |event| {
+            recorded.borrow_mut().push(event.depth)
+        }));
+        interpreter.interpret().unwrap();
+        assert_eq!(depths.take(), [0, 0, 1, 2, 2, 1, 0]);
+        assert_eq!(interpreter.current_depth(), 0);
+    }
+
+    #[test]
+    fn test_memory_report() {
+        let settings = InterpreterSettings {
+            track_memory: true,
+            ..Default::default()
+        };
+        let input = format!("{}+", ">".repeat(99));
+        let mut interpreter = Interpreter::new(input.clone(), settings.clone()).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(
+            interpreter.memory_report(),
+            MemoryReport {
+                peak_offset: 99,
+                cells_touched: 2
+            }
+        );
+        interpreter.reset();
+        assert_eq!(
+            interpreter.memory_report(),
+            MemoryReport {
+                peak_offset: 0,
+                cells_touched: 0
+            }
+        );
+
+        // Cells changed by optimized ops are reached without moving the data pointer
+        let ops =
+            crate::optimizer::optimize(Parser::new(String::from("+>>>+<<<")).parse().unwrap(), 3);
+        let mut interpreter = Interpreter::from_ops(ops, settings);
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory_report().peak_offset, 3);
+
+        let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory_report().cells_touched, 0);
+    }
+
+    #[test]
+    fn test_new_from_str() {
+        let mut interpreter = Interpreter::new("+++", InterpreterSettings::default()).unwrap();
+        interpreter.interpret().unwrap();
+        interpreter.load(">++").unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[..2], [3, 2]);
+    }
+
+    #[test]
+    fn test_run_with_input_queue() {
+        let mut interpreter = Interpreter::new(",.", InterpreterSettings::default()).unwrap();
+        let (output, leftover) = interpreter
+            .run_with_input_queue(VecDeque::from([b'a', b'b']))
+            .unwrap();
+        assert_eq!(output, b"a");
+        assert_eq!(leftover, [b'b']);
+
+        let settings = InterpreterSettings {
+            line_buffered_input: true,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(",", settings).unwrap();
+        let (_, leftover) = interpreter
+            .run_with_input_queue(VecDeque::from(*b"ab\ncd"))
+            .unwrap();
+        assert_eq!(leftover, *b"b\ncd");
+
+        let mut interpreter = Interpreter::new(",,", InterpreterSettings::default()).unwrap();
+        assert_eq!(
+            interpreter.run_with_input_queue(VecDeque::from([1])),
+            Err(InterpreterError::InputError)
+        );
+    }
+
+    #[test]
+    fn test_parser_error_from() {
+        let parse = || -> Result<Vec<Token>, InterpreterError> { Ok(Parser::new("+]").parse()?) };
+        assert_eq!(
+            parse(),
+            Err(InterpreterError::ParserError(
+                ParserError::UnmatchedBracket(1)
+            ))
+        );
+        assert_eq!(
+            Interpreter::new("+]", InterpreterSettings::default()).err(),
+            parse().err()
+        );
+    }
+
+    #[test]
+    fn test_load_and_reset() {
+        let mut interpreter =
+            Interpreter::new(String::from("+++>"), InterpreterSettings::default()).unwrap();
+        interpreter.interpret().unwrap();
+        assert!(matches!(
+            interpreter.load(String::from("]")),
+            Err(InterpreterError::ParserError(_))
+        ));
+        interpreter.load(String::from("+[-<+>]")).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[..2], [4, 0]);
+        assert_eq!(interpreter.memory.dp(), 1);
+
+        interpreter.reset();
+        assert_eq!(interpreter.memory.cells()[..2], [0, 0]);
+        assert_eq!((interpreter.memory.dp(), interpreter.pc), (0, 0));
+    }
+
+    #[test]
+    fn test_render_tape() {
+        let input = String::from("+++>++");
+        let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.render_tape(4), " 03 [02]");
+
+        let input = String::from("+>>>>+<");
+        let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.render_tape(4), " 01  00  00 [00]\n 01");
+    }
+
+    #[test]
+    fn test_no_io() {
+        let input = String::from("+++.,.>+,");
+        let settings = InterpreterSettings {
+            no_io: true,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(input, settings).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[0], 0);
+        assert_eq!(interpreter.memory.cells()[1], 0);
+    }
+
+    #[test]
+    fn test_seed_tape() {
+        let input = String::from("[->+<]");
+        let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+        interpreter.set_cell(0, 5);
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[..2], [0, 5]);
+
+        let settings = InterpreterSettings {
+            track_uninit: true,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from("[>]."), settings).unwrap();
+        interpreter.load_tape(&[1, 2, 0]);
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.dp(), 2);
+    }
+
+    /// An input that records the output that was written when it is read from
+    struct PromptedInput {
+        output: SharedBuffer<Vec<u8>>,
+        prompts: std::rc::Rc<std::cell::RefCell<Vec<Vec<u8>>>>,
+    }
+
+    impl Read for PromptedInput {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.prompts
+                .borrow_mut()
+                .push(self.output.0.borrow().clone());
+            buf[0] = b'y';
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_output_encoding() {
+        let run = |code: &str, encoding| {
+            let output = SharedBuffer::new(Vec::new());
+            let settings = InterpreterSettings {
+                encoding,
+                ..Default::default()
+            };
+            let mut interpreter = Interpreter::new(code, settings).unwrap();
+            interpreter.set_output(Box::new(output.clone()));
+            interpreter.interpret().unwrap();
+            String::from_utf8(output.take_contents()).unwrap()
+        };
+
+        // 195 169 is é in UTF-8, which is two characters in Latin-1
+        let code = "++++++++++++[>++++++++++++++++<-]>+++.<++[>-------------<-]>.";
+        assert_eq!(run(code, OutputEncoding::Utf8), "é");
+        assert_eq!(run(code, OutputEncoding::Latin1), "Ã©");
+        assert_eq!(run(code, OutputEncoding::Cp437), "├⌐");
+        assert_eq!(run("-.+++++++++++.", OutputEncoding::Utf8), "\u{fffd}\n");
+        // The byte that breaks a sequence is not lost, and an incomplete character at the end is
+        // replaced as well
+        let code = "++++++++++++++[>++++++++++++++<-]>-.<+++++++++++++[>----------<-]>.<+++++++++++++[>++++++++++<-]>.";
+        assert_eq!(run(code, OutputEncoding::Utf8), "\u{fffd}A\u{fffd}");
+        assert_eq!(CP437_HIGH.chars().count(), 128);
+    }
+
+    #[test]
+    fn test_loop_sentinel() {
+        let settings = InterpreterSettings {
+            loop_sentinel: 255,
+            ..Default::default()
+        };
+        let output = SharedBuffer::new(Vec::new());
+        let mut interpreter = Interpreter::new("-[.]>[-]", settings.clone()).unwrap();
+        interpreter.set_output(Box::new(output.clone()));
+        interpreter.interpret().unwrap();
+        assert!(output.take_contents().is_empty());
+        assert_eq!(interpreter.memory.cells()[..2], [255, 255]);
+
+        let ops = optimizer::optimize(Parser::new("+>->+<<[>]").parse().unwrap(), 1);
+        let mut interpreter = Interpreter::from_ops(ops, settings);
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.dp(), 1);
+    }
+
+    #[test]
+    fn test_empty_program() {
+        for code in ["", "just a comment"] {
+            let output = SharedBuffer::new(Vec::new());
+            let mut interpreter = Interpreter::new(code, InterpreterSettings::default()).unwrap();
+            interpreter.set_output(Box::new(output.clone()));
+            interpreter.interpret().unwrap();
+            assert!(output.take_contents().is_empty());
+            assert_eq!(interpreter.memory.dp(), 0);
+            assert!(interpreter.memory.cells().iter().all(|&cell| cell == 0));
+            assert_eq!(interpreter.run_to_output(), Ok(None));
+        }
+
+        let mut interpreter = Interpreter::from_ops(Vec::new(), InterpreterSettings::default());
+        interpreter.interpret().unwrap();
+    }
+
+    #[test]
+    fn test_run_region() {
+        let mut interpreter = Interpreter::new("[->+<]", InterpreterSettings::default()).unwrap();
+        interpreter.load_tape(&[4]);
+        interpreter.run_region(1, 5).unwrap();
+        assert_eq!(interpreter.memory.cells()[..2], [3, 1]);
+        assert_eq!(interpreter.memory.dp(), 0);
+        interpreter.run_region(0, 6).unwrap();
+        assert_eq!(interpreter.memory.cells()[..2], [0, 4]);
+
+        for (start, end) in [(0, 3), (3, 6), (2, 8), (4, 2)] {
+            assert_eq!(
+                interpreter.run_region(start, end),
+                Err(InterpreterError::InvalidRegion { start, end })
+            );
+        }
+    }
+
+    #[test]
+    fn test_echo_input() {
+        let run = |echo_input| {
+            let output = SharedBuffer::new(Vec::new());
+            let settings = InterpreterSettings {
+                echo_input,
+                ..Default::default()
+            };
+            let mut interpreter = Interpreter::new(",.", settings).unwrap();
+            interpreter.set_input(Box::new("a".as_bytes()));
+            interpreter.set_output(Box::new(output.clone()));
+            interpreter.interpret().unwrap();
+            output.take_contents()
+        };
+        assert_eq!(run(true), b"aa");
+        assert_eq!(run(false), b"a");
+    }
+
+    #[test]
+    fn test_trace_sink() {
+        let run = |trace_format| {
+            let sink = SharedBuffer::new(Vec::new());
+            let settings = InterpreterSettings {
+                trace_format,
+                ..Default::default()
+            };
+            let mut interpreter = Interpreter::new("++", settings)
+                .unwrap()
+                .with_trace_sink(Box::new(sink.clone()));
+            interpreter.interpret().unwrap();
+            sink.take_contents()
+        };
+
+        let csv = String::from_utf8(run(TraceFormat::Csv)).unwrap();
+        assert_eq!(csv.lines().collect::<Vec<_>>(), ["Plus,0,0,0", "Eof,1,0,2"]);
+
+        let binary = run(TraceFormat::Binary);
+        assert_eq!(binary.len(), 2 * 21);
+        assert_eq!(binary[0], 3);
+        assert_eq!(
+            binary[21..],
+            [
+                [0, 1, 0, 0, 0, 0, 0, 0, 0].as_slice(),
+                &[0; 8],
+                &[2, 0, 0, 0]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_flush_before_input() {
+        let output = SharedBuffer::new(Vec::new());
+        let prompts = std::rc::Rc::default();
+        let settings = InterpreterSettings {
+            buffer_output: true,
+            ..Default::default()
+        };
+        let mut interpreter =
+            Interpreter::new(String::from("+++[>+++<-]>[.-],."), settings).unwrap();
+        interpreter.set_output(Box::new(output.clone()));
+        interpreter.set_input(Box::new(PromptedInput {
+            output: output.clone(),
+            prompts: std::rc::Rc::clone(&prompts),
+        }));
+        interpreter.interpret().unwrap();
+        assert_eq!(prompts.take(), [(1..=9).rev().collect::<Vec<u8>>()]);
+        assert_eq!(output.0.borrow().last(), Some(&b'y'));
+    }
+
+    #[test]
+    fn test_buffer_output() {
+        let input = String::from("++++++++[>++++++++<-]>+[.+]");
+        let run = |buffer_output| {
+            let output = SharedBuffer::new(Vec::new());
+            let settings = InterpreterSettings {
+                buffer_output,
+                ..Default::default()
+            };
+            let mut interpreter = Interpreter::new(input.clone(), settings).unwrap();
+            interpreter.set_output(Box::new(output.clone()));
+            interpreter.interpret().unwrap();
+            output.take_contents()
+        };
+
+        let buffered = run(true);
+        assert_eq!(buffered, run(false));
+        assert!(buffered.starts_with(b"ABC"));
+    }
+
+    #[test]
+    fn test_flush_every() {
+        /// An output recording how many bytes were written at every flush
+        #[derive(Clone, Default)]
+        struct FlushRecorder(std::rc::Rc<std::cell::RefCell<(usize, Vec<usize>)>>);
+
+        impl Write for FlushRecorder {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().0 += buf.len();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                let mut recorded = self.0.borrow_mut();
+                let written = recorded.0;
+                recorded.1.push(written);
+                Ok(())
+            }
+        }
+
+        let run = |flush_every| {
+            let output = FlushRecorder::default();
+            let settings = InterpreterSettings {
+                buffer_output: true,
+                flush_every,
+                ..Default::default()
+            };
+            let mut interpreter = Interpreter::new("+++.>++..", settings).unwrap();
+            interpreter.set_output(Box::new(output.clone()));
+            interpreter.interpret().unwrap();
+            output.0.take().1
+        };
+        // The output is flushed once more at the end of `interpret`
+        assert_eq!(run(Some(1)), [1, 2, 3, 3]);
+        assert_eq!(run(Some(2)), [2, 3]);
+        assert_eq!(run(None), [3]);
+    }
+
+    #[test]
+    fn test_eof_policy() {
+        let run = |eof_policy, line_buffered_input| {
+            let settings = InterpreterSettings {
+                eof_policy,
+                line_buffered_input,
+                ..Default::default()
+            };
+            let mut interpreter = Interpreter::new("+++>,>+++,,", settings).unwrap();
+            interpreter.set_input(Box::new("a".as_bytes()));
+            interpreter
+                .interpret()
+                .map(|()| interpreter.memory.cells()[..3].to_vec())
+        };
+        assert_eq!(run(EofPolicy::Value(42), false), Ok(vec![3, 97, 42]));
+        assert_eq!(run(EofPolicy::Value(42), true), Ok(vec![3, 97, 42]));
+        assert_eq!(run(EofPolicy::Unchanged, false), Ok(vec![3, 97, 3]));
+        assert_eq!(
+            run(EofPolicy::Error, false),
+            Err(InterpreterError::InputError)
+        );
+    }
+
+    #[test]
+    fn test_set_input() {
+        let output = SharedBuffer::new(Vec::new());
+        let mut interpreter =
+            Interpreter::new(String::from(",+.,+."), InterpreterSettings::default()).unwrap();
+        interpreter.set_input(Box::new("ab".as_bytes()));
+        interpreter.set_output(Box::new(output.clone()));
+        interpreter.interpret().unwrap();
+        assert_eq!(output.take_contents(), b"bc");
+
+        let mut interpreter =
+            Interpreter::new(String::from(",,"), InterpreterSettings::default()).unwrap();
+        interpreter.set_input(Box::new("a".as_bytes()));
+        assert!(matches!(
+            interpreter.interpret(),
+            Err(InterpreterError::InputError)
+        ));
+    }
+
+    #[test]
+    fn test_line_buffered_input() {
+        let settings = InterpreterSettings {
+            line_buffered_input: true,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from(",>,>,>,"), settings).unwrap();
+        interpreter.set_input(Box::new("abc\n".as_bytes()));
+        interpreter.set_output(Box::new(std::io::sink()));
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[..4], [97, 98, 99, 10]);
+    }
+
+    #[test]
+    fn test_clone_without_io() {
+        let settings = InterpreterSettings {
+            track_access: true,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new("++++[>++++++++<-]>[.-]", settings).unwrap();
+        interpreter.set_output(Box::new(std::io::sink()));
+        interpreter.set_trace_hook(Box::new(|_| {}));
+        while interpreter.current_depth() == 0 || interpreter.memory.get() < 2 {
+            interpreter.step_op().unwrap();
+        }
+
+        let mut clone = interpreter.clone_without_io();
+        assert!(clone.trace_hook.is_none());
+        assert_eq!(clone.save_state(), interpreter.save_state());
+        assert_eq!(clone.current_depth(), 1);
+
+        let output = SharedBuffer::new(Vec::new());
+        clone.set_output(Box::new(output.clone()));
+        clone.interpret().unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(clone.save_state(), interpreter.save_state());
+        assert_eq!(clone.access_heatmap(), interpreter.access_heatmap());
+        assert_eq!(output.take_contents(), (1..=32).rev().collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_save_and_restore_state() {
+        let ops = Parser::new(String::from("++++++++[>++++++++<-]>+.+.+.>++++++++++."))
+            .parse()
+            .unwrap();
+
+        let full_output = SharedBuffer::new(Vec::new());
+        let mut full = Interpreter::from_ops(ops.clone(), InterpreterSettings::default());
+        full.set_output(Box::new(full_output.clone()));
+        full.interpret().unwrap();
+
+        let output = SharedBuffer::new(Vec::new());
+        let mut first = Interpreter::from_ops(ops.clone(), InterpreterSettings::default());
+        first.set_output(Box::new(output.clone()));
+        while first.pc < 10 {
+            first.step_op().unwrap();
+        }
+        let state = first.save_state();
+        assert_eq!(state.pc, 10);
+        assert_eq!(state.tape[..2], [0, 65]);
+
+        let mut second = Interpreter::from_ops(ops, InterpreterSettings::default());
+        second.set_output(Box::new(output.clone()));
+        second.restore_state(state);
+        second.interpret().unwrap();
+
+        assert_eq!(second.save_state(), full.save_state());
+        assert_eq!(output.take_contents(), full_output.take_contents());
+    }
+
+    #[test]
+    fn test_cell_width() {
+        let input = "+".repeat(256);
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().cell_width(CellWidth::U16),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(input.clone(), settings).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[0], 256);
+        assert_eq!(interpreter.render_tape(4), "[0100]");
+
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().cell_width(CellWidth::U16),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from("-"), settings).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[0], 0xffff);
+
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().cell_width(CellWidth::U32),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from("-[->+<]"), settings).unwrap();
+        interpreter.set_cell(0, 3);
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[..2], [0, 2]);
+    }
+
+    #[test]
+    fn test_grow_right() {
+        let token = |token_type, size, loc| Token {
+            token_type,
+            size,
+            loc,
+            leading_trivia: None,
+        };
+        let ops = vec![
+            token(TokenType::ShiftRight, Some(50000), 0),
+            token(TokenType::Plus, Some(1), 50000),
+            token(TokenType::ShiftRight, Some(1), 50001),
+            token(TokenType::Plus, Some(1), 50002),
+            token(TokenType::Eof, None, 50003),
+        ];
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().grow_right(true),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::from_ops(ops.clone(), settings);
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.dp(), 50001);
+        assert_eq!(interpreter.memory.cells()[50000..], [1, 1]);
+
+        let mut interpreter = Interpreter::from_ops(ops, InterpreterSettings::default());
+        assert_eq!(
+            interpreter.interpret(),
+            Err(InterpreterError::TapeOverflow {
+                loc: 0,
+                attempted: 50000,
+                len: 30000
+            })
+        );
+
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().wrap(true).grow_right(true),
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from("><<"), settings).unwrap();
+        assert_eq!(
+            interpreter.interpret(),
+            Err(InterpreterError::TapeUnderflow {
+                loc: 1,
+                attempted: -1,
+                len: 30000
+            })
+        );
+    }
+
+    #[test]
+    fn test_track_uninit() {
+        let settings = InterpreterSettings {
+            track_uninit: true,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from(">."), settings).unwrap();
+        assert_eq!(
+            interpreter.interpret(),
+            Err(InterpreterError::UninitRead(1))
+        );
+
+        let settings = InterpreterSettings {
+            track_uninit: true,
+            ..Default::default()
+        };
+        let mut interpreter =
+            Interpreter::new(String::from("+[->+<]>[-]"), settings.clone()).unwrap();
+        interpreter.interpret().unwrap();
+
+        // A clear loop reads the cell, also when the optimizer replaces it
+        for input in [">[-]", ">[-]+"] {
+            for opt_level in [0, 1] {
+                let ops = optimizer::optimize(Parser::new(input).parse().unwrap(), opt_level);
+                let mut interpreter = Interpreter::from_ops(ops, settings.clone());
+                assert_eq!(
+                    interpreter.interpret(),
+                    Err(InterpreterError::UninitRead(1)),
+                    "{input} at opt level {opt_level}"
+                );
+            }
+        }
+
+        // A snapshot doesn't mark its zero cells as written
+        for (tape, result) in [
+            (vec![1, 0, 0], Err(InterpreterError::UninitRead(2))),
+            (vec![0, 0, 5], Ok(())),
+        ] {
+            let mut interpreter = Interpreter::new(">>.", settings.clone()).unwrap();
+            interpreter.set_output(Box::new(std::io::sink()));
+            interpreter.restore_state(InterpreterState { tape, pc: 0, dp: 0 });
+            assert_eq!(interpreter.interpret(), result);
+        }
+
+        // A multiply loop that isn't entered neither moves off the tape nor writes its target
+        for opt_level in [0, 2] {
+            let ops = optimizer::optimize(Parser::new(",[-<+>]").parse().unwrap(), opt_level);
+            let mut interpreter = Interpreter::from_ops(ops, settings.clone());
+            assert!(interpreter
+                .run_with_input_queue(VecDeque::from([0]))
+                .is_ok());
+
+            let ops = optimizer::optimize(Parser::new(",[->+<]>.").parse().unwrap(), opt_level);
+            let mut interpreter = Interpreter::from_ops(ops, settings.clone());
+            assert_eq!(
+                interpreter.run_with_input_queue(VecDeque::from([0])),
+                Err(InterpreterError::UninitRead(8)),
+                "opt level {opt_level}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_trace_hook() {
+        let events = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let collected = events.clone();
+        let mut interpreter =
+            Interpreter::new(String::from("+>+"), InterpreterSettings::default()).unwrap();
+        interpreter.set_trace_hook(Box::new(move |event| {
+            collected
+                .borrow_mut()
+                .push((event.op.token_type, event.pc, event.dp, event.cell))
+        }));
+        interpreter.interpret().unwrap();
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                (TokenType::Plus, 0, 0, 0),
+                (TokenType::ShiftRight, 1, 0, 1),
+                (TokenType::Plus, 2, 1, 0),
+                (TokenType::Eof, 3, 1, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_close_bracket_target() {
+        let executed = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let collected = executed.clone();
+        let mut interpreter =
+            Interpreter::new(String::from("++[-]"), InterpreterSettings::default()).unwrap();
+        interpreter.set_trace_hook(Box::new(move |event| {
+            collected.borrow_mut().push(event.op.token_type)
+        }));
+        interpreter.interpret().unwrap();
+        // The second iteration starts at the body, the opening bracket is only run once
+        assert_eq!(
+            *executed.borrow(),
+            vec![
+                TokenType::Plus,
+                TokenType::OpenBracket,
+                TokenType::Minus,
+                TokenType::CloseBracket,
+                TokenType::Minus,
+                TokenType::CloseBracket,
+                TokenType::Eof,
+            ]
+        );
+        assert_eq!(interpreter.current_depth(), 0);
+    }
+
+    #[test]
+    fn test_scan() {
+        let input = ">+>+>+<<[>]+<[<]>>+[<<]";
+        let ops = optimizer::optimize(Parser::new(input).parse().unwrap(), 1);
+        assert!(ops
+            .iter()
+            .any(|op| op.token_type == TokenType::Scan { step: -2 }));
+        let mut scanned = Interpreter::from_ops(ops, InterpreterSettings::default());
+        let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+        scanned.interpret().unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(scanned.memory.cells()[..5], [0, 1, 2, 1, 1]);
+        assert_eq!(scanned.memory.cells(), interpreter.memory.cells());
+
+        let ops = optimizer::optimize(Parser::new("+[<]").parse().unwrap(), 1);
+        let mut interpreter = Interpreter::from_ops(ops, InterpreterSettings::default());
+        assert_eq!(
+            interpreter.interpret(),
+            Err(InterpreterError::TapeUnderflow {
+                loc: 1,
+                attempted: -1,
+                len: 30000
+            })
+        );
+
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().tape_size(5).wrap(true),
+            ..Default::default()
+        };
+        let ops = optimizer::optimize(Parser::new("+>+>>+>+[>]").parse().unwrap(), 1);
+        let mut interpreter = Interpreter::from_ops(ops, settings);
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.dp(), 2);
+    }
+
+    #[test]
+    fn test_set_value() {
+        let ops = optimizer::optimize(Parser::new("[-]+++++").parse().unwrap(), 1);
+        assert_eq!(ops[0].token_type, TokenType::SetValue { value: 5 });
+        assert_eq!(ops.len(), 2);
+        let mut interpreter = Interpreter::from_ops(ops, InterpreterSettings::default());
+        interpreter.set_cell(0, 7);
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[0], 5);
+
+        let ops = optimizer::optimize(Parser::new("+[-]--").parse().unwrap(), 1);
+        let mut interpreter = Interpreter::from_ops(ops, InterpreterSettings::default());
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[0], 254);
+    }
+
+    #[test]
+    fn test_halt_command() {
+        let settings = InterpreterSettings {
+            halt_command: true,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from("+++!+++"), settings).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[0], 3);
+
+        let mut interpreter =
+            Interpreter::new(String::from("+++!+++"), InterpreterSettings::default()).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.memory.cells()[0], 6);
+    }
+
+    #[test]
+    fn test_debug_commands() {
+        let dumps = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let collected = dumps.clone();
+        let settings = InterpreterSettings {
+            debug_commands: true,
+            ..Default::default()
+        };
+        let mut interpreter = Interpreter::new(String::from("+++#>+#"), settings).unwrap();
+        interpreter.set_trace_hook(Box::new(move |event| {
+            if let Some(tape) = event.tape {
+                collected.borrow_mut().push((event.dp, tape[..2].to_vec()));
+            }
+        }));
+        interpreter.interpret().unwrap();
+        assert_eq!(*dumps.borrow(), vec![(0, vec![3, 0]), (1, vec![3, 1])]);
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn test_jit_run() {
+        let input = String::from("++++++++[>++++++++[>++<-]<-]>>[->+>++<<]<<<");
+        let ops = crate::optimizer::optimize(Parser::new(input.clone()).parse().unwrap(), 3);
+        let mut jit = Interpreter::from_ops(ops, InterpreterSettings::default());
+        let mut interpreter = Interpreter::new(input, InterpreterSettings::default()).unwrap();
+        let underflow = InterpreterError::TapeUnderflow {
+            loc: 40,
+            attempted: -1,
+            len: 30000,
+        };
+        assert_eq!(jit.jit_run(), Err(underflow));
+        assert_eq!(
+            interpreter.interpret(),
+            Err(InterpreterError::TapeUnderflow {
+                loc: 40,
+                attempted: -1,
+                len: 30000
+            })
+        );
+        assert_eq!(jit.memory.cells(), interpreter.memory.cells());
+        assert_eq!(jit.memory.cells()[3], 128);
+        assert_eq!(jit.memory.cells()[4], 0);
+
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().wrap(true),
+            ..Default::default()
+        };
+        let mut jit = Interpreter::new(String::from("<+++[->>++<<]"), settings).unwrap();
+        jit.jit_run().unwrap();
+        assert_eq!(jit.memory.dp(), 29999);
+        assert_eq!(jit.memory.cells()[1], 6);
+
+        let ops = crate::optimizer::optimize(Parser::new(">+>+>+<<[>]<[<]").parse().unwrap(), 1);
+        let mut jit = Interpreter::from_ops(ops, InterpreterSettings::default());
+        jit.jit_run().unwrap();
+        assert_eq!(jit.memory.dp(), 0);
+        assert_eq!(jit.memory.cells()[..5], [0, 1, 1, 1, 0]);
+
+        let settings = InterpreterSettings {
+            loop_sentinel: 255,
+            ..Default::default()
+        };
+        let mut jit = Interpreter::new(String::from("-[+]>[-]"), settings).unwrap();
+        jit.jit_run().unwrap();
+        assert_eq!(jit.memory.cells()[..2], [255, 255]);
+        assert_eq!(jit.memory.dp(), 1);
+
+        // Moves of more than the tape length wrap around as often as needed
+        for (input, dp) in [(">>>>>>>>>+", 1), ("<<<<<<<<<+", 3)] {
+            let settings = InterpreterSettings {
+                tape: TapeConfig::default().wrap(true).tape_size(4),
+                ..Default::default()
+            };
+            let ops = crate::optimizer::optimize(Parser::new(input).parse().unwrap(), 1);
+            let mut jit = Interpreter::from_ops(ops, settings.clone());
+            let mut interpreter = Interpreter::new(String::from(input), settings).unwrap();
+            jit.jit_run().unwrap();
+            interpreter.interpret().unwrap();
+            assert_eq!(jit.memory.dp(), dp);
+            assert_eq!(jit.memory.cells(), interpreter.memory.cells());
+            assert_eq!(interpreter.memory.dp(), dp);
+        }
+
+        // IO goes through the interpreter with its IO settings
+        let settings = InterpreterSettings {
+            echo_input: true,
+            eof_policy: EofPolicy::Value(33),
+            ..Default::default()
+        };
+        let output = SharedBuffer::new(Vec::new());
+        let mut jit = Interpreter::new(String::from(",+.,.,."), settings).unwrap();
+        jit.set_input(Box::new("ab".as_bytes()));
+        jit.set_output(Box::new(output.clone()));
+        jit.jit_run().unwrap();
+        assert_eq!(output.take_contents(), b"abbb!");
+
+        let mut jit = Interpreter::new(String::from(","), InterpreterSettings::default()).unwrap();
+        jit.set_input(Box::new(std::io::empty()));
+        assert_eq!(jit.jit_run(), Err(InterpreterError::InputError));
+
+        let settings = InterpreterSettings {
+            tape: TapeConfig::default().grow_right(true),
+            ..Default::default()
+        };
+        let mut jit = Interpreter::new(String::from("+"), settings).unwrap();
+        assert!(matches!(jit.jit_run(), Err(InterpreterError::JitError(_))));
+
+        let settings = InterpreterSettings {
+            track_memory: true,
+            ..Default::default()
+        };
+        let mut jit = Interpreter::new(String::from("+"), settings).unwrap();
+        assert!(matches!(jit.jit_run(), Err(InterpreterError::JitError(_))));
+
+        // Ops that don't come from the parser may have unmatched brackets
+        let ops = vec![
+            Token {
+                token_type: TokenType::CloseBracket,
+                size: Some(0),
+                loc: 0,
+                leading_trivia: None,
+            },
+            Token {
+                token_type: TokenType::Eof,
+                size: None,
+                loc: 1,
+                leading_trivia: None,
+            },
+        ];
+        let mut jit = Interpreter::from_ops(ops, InterpreterSettings::default());
+        assert!(matches!(jit.jit_run(), Err(InterpreterError::JitError(_))));
     }
 }
@@ -0,0 +1,469 @@
+//! The jit module compiles parsed operations to native code with cranelift and runs them
+//! in-process. It is only available with the `jit` feature, see `Interpreter::jit_run`.
+
+use crate::interpreter::{Interpreter, InterpreterError, InterpreterSettings};
+use crate::lexer::{Token, TokenType};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, FuncRef, Inst, InstBuilder, MemFlagsData, UserFuncName, Value,
+};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+
+/// The status codes returned by the compiled code. On errors the location of the failing op is
+/// shifted into the bits above the status code.
+const STATUS_OK: i64 = 0;
+const STATUS_OVERFLOW: i64 = 1;
+const STATUS_UNDERFLOW: i64 = 2;
+const STATUS_IO_ERROR: i64 = 3;
+const STATUS_BITS: i64 = 2;
+
+/// The signature of the compiled code, it takes the tape, a pointer to the data pointer, which
+/// is read on entry and written back on exit, a pointer the target of a move past the ends of
+/// the tape is written to and the IO context passed on to the IO callbacks
+type JitFunction = unsafe extern "C" fn(*mut u8, *mut i64, *mut i64, *mut IoContext) -> i64;
+
+/// The state of the IO callbacks, the interpreter doing the IO and the error that stopped it
+struct IoContext<'a> {
+    interpreter: &'a mut Interpreter,
+    error: Option<InterpreterError>,
+}
+
+/// Write a byte through the interpreter, returning 0 on success and 1 if the compiled code has to
+/// stop with the error stored in the context
+extern "C" fn jit_output(context: *mut IoContext, byte: u8) -> i8 {
+    // Safety: the compiled code only passes on the context it was called with, which outlives it
+    let context = unsafe { &mut *context };
+    match context.interpreter.write_output(byte) {
+        Ok(()) => 0,
+        Err(e) => {
+            context.error = Some(e);
+            1
+        }
+    }
+}
+
+/// Read a byte through the interpreter into the cell, returning 0 on success and 1 if the
+/// compiled code has to stop with the error stored in the context
+extern "C" fn jit_input(context: *mut IoContext, cell: *mut u8) -> i8 {
+    // Safety: the compiled code only passes on the context it was called with, which outlives
+    // it, and the address of the current cell on the tape
+    let (context, cell) = unsafe { (&mut *context, &mut *cell) };
+    let result = context
+        .interpreter
+        .write_buffer()
+        .and_then(|_| context.interpreter.input_cell(*cell as u32));
+    match result {
+        Ok(value) => {
+            *cell = value as u8;
+            0
+        }
+        Err(e) => {
+            context.error = Some(e);
+            1
+        }
+    }
+}
+
+/// Compile the ops to native code and run them on the tape
+///
+/// # Arguments
+/// * `ops` - The operations to run, they have to be valid output of the parser or optimizer
+/// * `tape` - The tape to run the operations on
+/// * `dp` - The data pointer, updated to its final position
+/// * `settings` - The settings of the interpreter, the wrap settings, `no_io` and
+///   `loop_sentinel` are compiled in
+/// * `interpreter` - The interpreter doing the IO, with its input, output and IO settings
+pub(crate) fn run(
+    ops: &[Token],
+    tape: &mut [u8],
+    dp: &mut usize,
+    settings: &InterpreterSettings,
+    interpreter: &mut Interpreter,
+) -> Result<(), InterpreterError> {
+    let jit_error = |e: &dyn std::fmt::Display| InterpreterError::JitError(e.to_string());
+
+    let mut flag_builder = settings::builder();
+    flag_builder
+        .set("use_colocated_libcalls", "false")
+        .map_err(|e| jit_error(&e))?;
+    flag_builder
+        .set("is_pic", "false")
+        .map_err(|e| jit_error(&e))?;
+    let isa = cranelift_native::builder()
+        .map_err(|e| jit_error(&e))?
+        .finish(settings::Flags::new(flag_builder))
+        .map_err(|e| jit_error(&e))?;
+
+    let mut jit_builder = JITBuilder::with_isa(isa, default_libcall_names());
+    jit_builder.symbol("rbfc_output", jit_output as *const u8);
+    jit_builder.symbol("rbfc_input", jit_input as *const u8);
+    let mut module = JITModule::new(jit_builder);
+
+    let pointer = module.target_config().pointer_type();
+    let mut output_signature = module.make_signature();
+    output_signature.params.push(AbiParam::new(pointer));
+    output_signature.params.push(AbiParam::new(types::I8));
+    output_signature.returns.push(AbiParam::new(types::I8));
+    let output = module
+        .declare_function("rbfc_output", Linkage::Import, &output_signature)
+        .map_err(|e| jit_error(&e))?;
+    let mut input_signature = module.make_signature();
+    input_signature.params.push(AbiParam::new(pointer));
+    input_signature.params.push(AbiParam::new(pointer));
+    input_signature.returns.push(AbiParam::new(types::I8));
+    let input = module
+        .declare_function("rbfc_input", Linkage::Import, &input_signature)
+        .map_err(|e| jit_error(&e))?;
+
+    let mut ctx = module.make_context();
+    for _ in 0..4 {
+        ctx.func.signature.params.push(AbiParam::new(pointer));
+    }
+    ctx.func.signature.returns.push(AbiParam::new(types::I64));
+    let main = module
+        .declare_function("main", Linkage::Local, &ctx.func.signature)
+        .map_err(|e| jit_error(&e))?;
+    ctx.func.name = UserFuncName::user(0, main.as_u32());
+
+    let mut function_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ctx.func, &mut function_context);
+    let output = module.declare_func_in_func(output, builder.func);
+    let input = module.declare_func_in_func(input, builder.func);
+
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    let tape_ptr = builder.block_params(entry)[0];
+    let dp_ptr = builder.block_params(entry)[1];
+    let attempted_ptr = builder.block_params(entry)[2];
+    let context_ptr = builder.block_params(entry)[3];
+    let dp_var = builder.declare_var(types::I64);
+    let initial_dp = builder
+        .ins()
+        .load(types::I64, MemFlagsData::new(), dp_ptr, 0);
+    builder.def_var(dp_var, initial_dp);
+
+    let mut emitter = Emitter {
+        builder,
+        tape_ptr,
+        dp_ptr,
+        attempted_ptr,
+        context_ptr,
+        dp_var,
+        output,
+        input,
+        len: tape.len() as i64,
+        wrap_left: settings.tape.wrap_left,
+        wrap_right: settings.tape.wrap_right,
+        no_io: settings.no_io,
+        loop_sentinel: settings.loop_sentinel as i64,
+    };
+    emitter.emit(ops)?;
+    emitter.builder.seal_all_blocks();
+    emitter.builder.finalize(module.target_config());
+
+    module
+        .define_function(main, &mut ctx)
+        .map_err(|e| jit_error(&e))?;
+    module.clear_context(&mut ctx);
+    module.finalize_definitions().map_err(|e| jit_error(&e))?;
+
+    let code = module.get_finalized_function(main);
+    let mut jit_dp = *dp as i64;
+    let mut attempted = 0i64;
+    let mut context = IoContext {
+        interpreter,
+        error: None,
+    };
+    // Safety: the code was compiled with the signature of `JitFunction` and only accesses the
+    // tape within its bounds, as every pointer movement is either checked against its length or
+    // reduced modulo it on a wrapping tape
+    let status = unsafe {
+        let function = std::mem::transmute::<*const u8, JitFunction>(code);
+        function(tape.as_mut_ptr(), &mut jit_dp, &mut attempted, &mut context)
+    };
+    *dp = jit_dp as usize;
+
+    // Safety: the code is not running anymore and no pointers into it are kept
+    unsafe { module.free_memory() };
+
+    let loc = (status >> STATUS_BITS) as usize;
+    let (attempted, len) = (attempted as isize, tape.len());
+    match status & ((1 << STATUS_BITS) - 1) {
+        STATUS_OK => Ok(()),
+        STATUS_OVERFLOW => Err(InterpreterError::TapeOverflow {
+            loc,
+            attempted,
+            len,
+        }),
+        STATUS_UNDERFLOW => Err(InterpreterError::TapeUnderflow {
+            loc,
+            attempted,
+            len,
+        }),
+        _ => Err(context.error.unwrap_or(InterpreterError::InputError)),
+    }
+}
+
+/// Emits the cranelift IR for the ops into the function
+struct Emitter<'a> {
+    builder: FunctionBuilder<'a>,
+    tape_ptr: Value,
+    dp_ptr: Value,
+    attempted_ptr: Value,
+    context_ptr: Value,
+    dp_var: Variable,
+    output: FuncRef,
+    input: FuncRef,
+    len: i64,
+    wrap_left: bool,
+    wrap_right: bool,
+    no_io: bool,
+    loop_sentinel: i64,
+}
+
+impl Emitter<'_> {
+    fn emit(&mut self, ops: &[Token]) -> Result<(), InterpreterError> {
+        let mut loops = Vec::new();
+
+        for op in ops {
+            let size = op.size.unwrap_or_default() as i64;
+            match op.token_type {
+                TokenType::Eof => break,
+                TokenType::Plus => self.add_at(0, size, op.loc),
+                TokenType::Minus => self.add_at(0, -size, op.loc),
+                TokenType::ShiftRight => {
+                    let dp = self.offset_dp(size, op.loc);
+                    self.builder.def_var(self.dp_var, dp);
+                }
+                TokenType::ShiftLeft => {
+                    let dp = self.offset_dp(-size, op.loc);
+                    self.builder.def_var(self.dp_var, dp);
+                }
+                TokenType::Dot if self.no_io => {}
+                TokenType::Comma if self.no_io => {
+                    let address = self.cell_address(None);
+                    let zero = self.builder.ins().iconst(types::I8, 0);
+                    self.builder
+                        .ins()
+                        .store(MemFlagsData::new(), zero, address, 0);
+                }
+                TokenType::Dot => {
+                    let address = self.cell_address(None);
+                    let value = self
+                        .builder
+                        .ins()
+                        .load(types::I8, MemFlagsData::new(), address, 0);
+                    for _ in 0..size {
+                        let call = self
+                            .builder
+                            .ins()
+                            .call(self.output, &[self.context_ptr, value]);
+                        self.exit_on_io_error(call, op.loc);
+                    }
+                }
+                TokenType::Comma => {
+                    for _ in 0..size {
+                        let address = self.cell_address(None);
+                        let call = self
+                            .builder
+                            .ins()
+                            .call(self.input, &[self.context_ptr, address]);
+                        self.exit_on_io_error(call, op.loc);
+                    }
+                }
+                TokenType::OpenBracket => {
+                    let body = self.builder.create_block();
+                    let after = self.builder.create_block();
+                    let value = self.loops();
+                    self.builder.ins().brif(value, body, &[], after, &[]);
+                    self.builder.switch_to_block(body);
+                    loops.push((body, after));
+                }
+                TokenType::CloseBracket => {
+                    let (body, after) = loops.pop().ok_or_else(|| {
+                        InterpreterError::JitError(format!("Unmatched bracket at {}", op.loc))
+                    })?;
+                    let value = self.loops();
+                    self.builder.ins().brif(value, body, &[], after, &[]);
+                    self.builder.switch_to_block(after);
+                }
+                TokenType::Clear => {
+                    let address = self.cell_address(None);
+                    let zero = self.builder.ins().iconst(types::I8, 0);
+                    self.builder
+                        .ins()
+                        .store(MemFlagsData::new(), zero, address, 0);
+                }
+                TokenType::SetValue { value } => {
+                    let address = self.cell_address(None);
+                    let value = self.builder.ins().iconst(types::I8, value as u8 as i64);
+                    self.builder
+                        .ins()
+                        .store(MemFlagsData::new(), value, address, 0);
+                }
+                TokenType::MulAdd { offset, factor } => {
+                    let body = self.builder.create_block();
+                    let after = self.builder.create_block();
+                    let value = self.current_cell();
+                    self.builder.ins().brif(value, body, &[], after, &[]);
+                    self.builder.switch_to_block(body);
+                    let target = self.offset_dp(offset as i64, op.loc);
+                    let address = self.cell_address(Some(target));
+                    let product = self.builder.ins().imul_imm_s(value, factor as i64);
+                    let old = self
+                        .builder
+                        .ins()
+                        .load(types::I8, MemFlagsData::new(), address, 0);
+                    let new = self.builder.ins().iadd(old, product);
+                    self.builder
+                        .ins()
+                        .store(MemFlagsData::new(), new, address, 0);
+                    self.builder.ins().jump(after, &[]);
+                    self.builder.switch_to_block(after);
+                }
+                TokenType::AddAt { offset, delta } => {
+                    self.add_at(offset as i64, delta as i64, op.loc)
+                }
+                TokenType::Scan { step } => {
+                    let header = self.builder.create_block();
+                    let body = self.builder.create_block();
+                    let after = self.builder.create_block();
+                    self.builder.ins().jump(header, &[]);
+                    self.builder.switch_to_block(header);
+                    let value = self.loops();
+                    self.builder.ins().brif(value, body, &[], after, &[]);
+                    self.builder.switch_to_block(body);
+                    let dp = self.offset_dp(step as i64, op.loc);
+                    self.builder.def_var(self.dp_var, dp);
+                    self.builder.ins().jump(header, &[]);
+                    self.builder.switch_to_block(after);
+                }
+                TokenType::Debug => {}
+                TokenType::Halt => {
+                    // The ops after the halt are emitted into a block that is never reached
+                    self.exit(STATUS_OK);
+                    let after = self.builder.create_block();
+                    self.builder.switch_to_block(after);
+                }
+            }
+        }
+
+        self.exit(STATUS_OK);
+        Ok(())
+    }
+
+    /// The address of the cell at the given position, or of the current cell
+    fn cell_address(&mut self, position: Option<Value>) -> Value {
+        let position = position.unwrap_or_else(|| self.builder.use_var(self.dp_var));
+        self.builder.ins().iadd(self.tape_ptr, position)
+    }
+
+    /// Whether the current cell is not the loop sentinel, which keeps a loop running
+    fn loops(&mut self) -> Value {
+        let value = self.current_cell();
+        let sentinel = self.builder.ins().iconst(types::I8, self.loop_sentinel);
+        self.builder.ins().icmp(IntCC::NotEqual, value, sentinel)
+    }
+
+    fn current_cell(&mut self) -> Value {
+        let address = self.cell_address(None);
+        self.builder
+            .ins()
+            .load(types::I8, MemFlagsData::new(), address, 0)
+    }
+
+    /// Add `delta` to the cell at `offset`
+    fn add_at(&mut self, offset: i64, delta: i64, loc: usize) {
+        let position = if offset == 0 {
+            self.builder.use_var(self.dp_var)
+        } else {
+            self.offset_dp(offset, loc)
+        };
+        let address = self.cell_address(Some(position));
+        let value = self
+            .builder
+            .ins()
+            .load(types::I8, MemFlagsData::new(), address, 0);
+        let value = self.builder.ins().iadd_imm_s(value, delta);
+        self.builder
+            .ins()
+            .store(MemFlagsData::new(), value, address, 0);
+    }
+
+    /// Compute the position of the cell at `offset`, wrapping it around the tape or exiting with
+    /// an error if it is out of bounds
+    ///
+    /// A wrapping offset is reduced modulo the tape length first, so a single correction by the
+    /// length brings any target back onto the tape, like the `rem_euclid` of `Memory::position`
+    fn offset_dp(&mut self, offset: i64, loc: usize) -> Value {
+        let dp = self.builder.use_var(self.dp_var);
+        let wraps = if offset >= 0 {
+            self.wrap_right
+        } else {
+            self.wrap_left
+        };
+        let offset = if wraps { offset % self.len } else { offset };
+        let target = self.builder.ins().iadd_imm_s(dp, offset);
+        let (out_of_bounds, correction, status, wrap) = if offset >= 0 {
+            let overflow =
+                self.builder
+                    .ins()
+                    .icmp_imm_s(IntCC::SignedGreaterThanOrEqual, target, self.len);
+            (overflow, -self.len, STATUS_OVERFLOW, self.wrap_right)
+        } else {
+            let underflow = self
+                .builder
+                .ins()
+                .icmp_imm_s(IntCC::SignedLessThan, target, 0);
+            (underflow, self.len, STATUS_UNDERFLOW, self.wrap_left)
+        };
+
+        if wrap {
+            let wrapped = self.builder.ins().iadd_imm_s(target, correction);
+            self.builder.ins().select(out_of_bounds, wrapped, target)
+        } else {
+            let error = self.builder.create_block();
+            let after = self.builder.create_block();
+            self.builder
+                .ins()
+                .brif(out_of_bounds, error, &[], after, &[]);
+            self.builder.switch_to_block(error);
+            self.builder
+                .ins()
+                .store(MemFlagsData::new(), target, self.attempted_ptr, 0);
+            self.exit(((loc as i64) << STATUS_BITS) | status);
+            self.builder.switch_to_block(after);
+            target
+        }
+    }
+
+    /// Exit with an IO error if the IO callback returned a failure
+    fn exit_on_io_error(&mut self, call: Inst, loc: usize) {
+        let failed = self.builder.inst_results(call)[0];
+        self.exit_if(failed, STATUS_IO_ERROR, loc);
+    }
+
+    /// Exit with the status if the condition is set
+    fn exit_if(&mut self, condition: Value, status: i64, loc: usize) {
+        let error = self.builder.create_block();
+        let after = self.builder.create_block();
+        self.builder.ins().brif(condition, error, &[], after, &[]);
+        self.builder.switch_to_block(error);
+        self.exit(((loc as i64) << STATUS_BITS) | status);
+        self.builder.switch_to_block(after);
+    }
+
+    /// Write the data pointer back and return the status
+    fn exit(&mut self, status: i64) {
+        let dp = self.builder.use_var(self.dp_var);
+        self.builder
+            .ins()
+            .store(MemFlagsData::new(), dp, self.dp_ptr, 0);
+        let status = self.builder.ins().iconst(types::I64, status);
+        self.builder.ins().return_(&[status]);
+    }
+}
@@ -1,18 +1,38 @@
-/// The lexer module is responsible for tokenizing the input string
-/// into a sequence of tokens.
+//! The lexer module is responsible for tokenizing the input string
+//! into a sequence of tokens.
+//!
+//! Every character that is not a command is a comment and skipped, including tabs, carriage
+//! returns and Unicode whitespace. The locations of the tokens are byte offsets into the input,
+//! `line_col` turns them into a line and column for diagnostics.
+//!
+//! # Example
+//! ```
+//! use rbfc::lexer::{Lexer, Token, TokenType};
+//! let input = String::from("+++[->+<]...,,,");
+//! let mut lexer = Lexer::new(input);
+//! let token = lexer.next_token();
+//! assert_eq!(token, Token {
+//!     token_type: TokenType::Plus,
+//!     size: Some(3),
+//!     loc: 0,
+//!     leading_trivia: None,
+//! });
+//! ```
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Error type for the lexer
 ///
-/// # Example
-/// ```
-/// use rbfc::lexer::{Lexer, Token, TokenType};
-/// let input = String::from("+++[->+<]...,,,");
-/// let mut lexer = Lexer::new(input);
-/// let token = lexer.next_token();
-/// assert_eq!(token, Token {
-///     token_type: TokenType::Plus,
-///     size: Some(3),
-///     loc: 0
-/// });
-/// ```
+/// This error type is used to represent the errors that can occur during lexing in strict mode
+#[derive(Debug, Error, PartialEq)]
+pub enum LexerError {
+    #[error("Unexpected character {0:?} at position {1}")]
+    UnexpectedChar(char, usize),
+}
 
 /// The TokenType enum represents the different types of tokens
 /// that the lexer can produce.
@@ -23,7 +43,8 @@
 /// assert_eq!(TokenType::Eof, TokenType::Eof);
 /// ```
 ///
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum TokenType {
     Eof,
     ShiftLeft,
@@ -34,6 +55,37 @@ pub enum TokenType {
     Comma,
     OpenBracket,
     CloseBracket,
+    /// Sets the current cell to zero. Never produced by the lexer, only by the
+    /// optimizer from loops like `[-]`
+    Clear,
+    /// Adds the current cell multiplied by `factor` to the cell at `offset` relative to the
+    /// data pointer. Never produced by the lexer, only by the optimizer from loops like `[->++<]`
+    MulAdd {
+        offset: isize,
+        factor: isize,
+    },
+    /// Adds `delta` to the cell at `offset` relative to the data pointer. Never produced by the
+    /// lexer, only by the optimizer from straight-line code like `>+<`
+    AddAt {
+        offset: isize,
+        delta: isize,
+    },
+    /// Sets the current cell to `value`. Never produced by the lexer, only by the optimizer from
+    /// a `Clear` followed by `+` or `-`, like `[-]+++`
+    SetValue {
+        value: isize,
+    },
+    /// Dumps the tape for debugging. Only produced by the lexer from `#` if `debug_commands` is
+    /// enabled, since standard Brainfuck ignores it
+    Debug,
+    /// Ends the program right away. Only produced by the lexer from `!` if `halt_command` is
+    /// enabled, since standard Brainfuck ignores it
+    Halt,
+    /// Moves the data pointer by `step` cells until the current cell is zero. Never produced by
+    /// the lexer, only by the optimizer from loops like `[>]` and `[<<]`
+    Scan {
+        step: isize,
+    },
 }
 
 /// The Token struct represents a single token produced by the lexer.
@@ -41,14 +93,26 @@ pub enum TokenType {
 /// The size is the number of consecutive tokens of the same type.
 /// For example, the token "+++" would have a size of 3.
 /// The size is None for tokens that are not repeated.
-#[derive(Debug, PartialEq)]
+///
+/// With the `serde` feature enabled tokens can be serialized, with the names of their fields as
+/// keys.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Token {
     /// The type of the token
     pub token_type: TokenType,
     /// The size of the token
     pub size: Option<usize>,
-    /// The location of the token in the input string
+    /// The location of the token in the input string, as a byte offset. Characters before the
+    /// token that take up more than one byte in UTF-8 are counted by their length
     pub loc: usize,
+    /// The characters skipped before the token, like comments and whitespace. Only kept if
+    /// `keep_trivia` is enabled and the token has any, see `to_source`
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub leading_trivia: Option<String>,
 }
 
 impl Token {
@@ -65,14 +129,77 @@ impl Token {
             _ => None,
         }
     }
+
+    /// The jump target of a bracket, which the parser stores in its `size`
+    ///
+    /// An `OpenBracket` jumps past its `CloseBracket` when the cell is zero, a `CloseBracket`
+    /// jumps back to the first op of the body, right after its `OpenBracket`, when the cell is
+    /// not zero. Both are indices into the parsed operations. Other tokens don't jump and return
+    /// `None`.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::parser::Parser;
+    ///
+    /// let ops = Parser::new("+[-]").parse().unwrap();
+    /// assert_eq!(ops[1].jump_target(), Some(4));
+    /// assert_eq!(ops[3].jump_target(), Some(2));
+    /// assert_eq!(ops[0].jump_target(), None);
+    /// ```
+    pub fn jump_target(&self) -> Option<usize> {
+        match self.token_type {
+            TokenType::OpenBracket | TokenType::CloseBracket => self.size,
+            _ => None,
+        }
+    }
+}
+
+/// The settings for the lexer
+///
+/// This struct is used to represent the settings for the lexer.
+///
+/// # Fields
+/// * `comment_char` - A character that starts a comment running to the end of the line, in which
+///   operators are ignored. This allows for shebang lines like `#!/usr/bin/env rbfc`
+/// * `strict` - Whether characters that are neither operators nor whitespace are rejected
+///   instead of ignored, see `Lexer::try_next_token`
+/// * `debug_commands` - Whether `#` is lexed as `TokenType::Debug` instead of being ignored. A
+///   `comment_char` of `#` takes precedence
+/// * `halt_command` - Whether `!` is lexed as `TokenType::Halt` instead of being ignored
+/// * `keep_trivia` - Whether the characters skipped before a token are kept in its
+///   `leading_trivia`, so the source can be restored with `to_source`. Skipped characters end a
+///   run, so `+ +` is lexed as two tokens
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::LexerSettings;
+/// let settings = LexerSettings {
+///     comment_char: Some('#'),
+///     strict: false,
+///     debug_commands: false,
+///     halt_command: false,
+///     keep_trivia: false,
+/// };
+/// ```
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LexerSettings {
+    pub comment_char: Option<char>,
+    pub strict: bool,
+    pub debug_commands: bool,
+    pub halt_command: bool,
+    pub keep_trivia: bool,
 }
 
 /// The Lexer struct is responsible for tokenizing the input string
 /// into a sequence of tokens.
-#[derive(Debug)]
+///
+/// The position in the input is kept as a byte offset, so every character is read in constant
+/// time and the locations of the tokens are byte offsets.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Lexer {
     input: String,
     position: usize,
+    settings: LexerSettings,
 }
 
 impl Lexer {
@@ -88,18 +215,52 @@ impl Lexer {
     /// let input = String::from("+++[->+<]...,,,");
     /// let mut lexer = Lexer::new(input);
     /// ```
-    pub fn new(input: String) -> Lexer {
-        Lexer { input, position: 0 }
+    pub fn new(input: impl Into<String>) -> Lexer {
+        Lexer::with_settings(input, LexerSettings::default())
+    }
+
+    /// Create a new lexer from a string with the given settings
+    ///
+    /// # Arguments
+    /// * `input` - A string to be tokenized
+    /// * `settings` - The settings for the lexer
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::lexer::{Lexer, LexerSettings, TokenType};
+    ///
+    /// let settings = LexerSettings { comment_char: Some('#'), ..Default::default() };
+    /// let mut lexer = Lexer::with_settings(String::from("# +++\n-"), settings);
+    /// assert_eq!(lexer.next_token().token_type, TokenType::Minus);
+    /// ```
+    pub fn with_settings(input: impl Into<String>, settings: LexerSettings) -> Lexer {
+        Lexer {
+            input: input.into(),
+            position: 0,
+            settings,
+        }
     }
 
+    /// Read the character at the position and move past it
     fn next_char(&mut self) -> Option<char> {
-        let c = self.input.chars().nth(self.position);
-        self.position += 1;
-        c
+        let c = self.input[self.position..].chars().next()?;
+        self.position += c.len_utf8();
+        // A comment is skipped up to the newline and returned as a single non operator
+        if Some(c) == self.settings.comment_char {
+            self.position = match self.input[self.position..].find('\n') {
+                Some(newline) => self.position + newline,
+                None => self.input.len(),
+            };
+            return Some(' ');
+        }
+        Some(c)
     }
 
     /// Get the next token from the input
     ///
+    /// Characters rejected in strict mode are skipped, use `try_next_token` to get an error for
+    /// them instead.
+    ///
     /// # Example
     /// ```
     /// use rbfc::lexer::{Lexer, Token, TokenType};
@@ -110,27 +271,55 @@ impl Lexer {
     ///    Token {
     ///    token_type: TokenType::Plus,
     ///    size: Some(3),
-    ///    loc: 0
+    ///    loc: 0,
+    ///    leading_trivia: None,
     /// });
     /// ```
     pub fn next_token(&mut self) -> Token {
-        let mut c = char::default();
-        let loc = self.position;
+        loop {
+            if let Ok(token) = self.try_next_token() {
+                return token;
+            }
+        }
+    }
 
-        while Token::is_token(&c).is_none() {
-            c = match self.next_char() {
+    /// Get the next token from the input, rejecting unexpected characters in strict mode
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::lexer::{Lexer, LexerError, LexerSettings, TokenType};
+    ///
+    /// let settings = LexerSettings { strict: true, ..Default::default() };
+    /// let mut lexer = Lexer::with_settings(String::from("+ a"), settings);
+    /// assert_eq!(lexer.try_next_token().unwrap().token_type, TokenType::Plus);
+    /// assert_eq!(lexer.try_next_token(), Err(LexerError::UnexpectedChar('a', 2)));
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error in strict mode if the input contains a character that is neither an
+    /// operator nor whitespace
+    pub fn try_next_token(&mut self) -> Result<Token, LexerError> {
+        let start = self.position;
+        let (token_type, loc) = loop {
+            let loc = self.position;
+            let c = match self.next_char() {
                 Some(c) => c,
                 None => {
-                    return Token {
+                    return Ok(Token {
                         token_type: TokenType::Eof,
                         size: None,
                         loc,
-                    }
+                        leading_trivia: self.trivia(start, loc),
+                    })
                 }
             };
-        }
-
-        let token_type = Token::is_token(&c).expect("Should be some token_type");
+            if self.is_rejected(&c) {
+                return Err(LexerError::UnexpectedChar(c, loc));
+            }
+            if let Some(token_type) = self.token_type(&c) {
+                break (token_type, loc);
+            }
+        };
 
         match token_type {
             TokenType::Dot
@@ -141,37 +330,218 @@ impl Lexer {
             | TokenType::ShiftRight => {
                 let mut size = 1;
 
-                while let Some(next_char) = self.next_char() {
-                    if let Some(next_token_type) = Token::is_token(&next_char) {
-                        if next_token_type == token_type {
-                            size += 1;
-                        } else {
+                // The character ending the run is left for the next token
+                loop {
+                    let position = self.position;
+                    let Some(next_char) = self.next_char() else {
+                        break;
+                    };
+                    match self.token_type(&next_char) {
+                        Some(next_token_type) if next_token_type == token_type => size += 1,
+                        None if !self.is_rejected(&next_char) && !self.settings.keep_trivia => {}
+                        _ => {
+                            self.position = position;
                             break;
                         }
                     }
                 }
 
-                self.position -= 1;
-
-                Token {
+                Ok(Token {
                     token_type,
                     size: Some(size),
                     loc,
-                }
+                    leading_trivia: self.trivia(start, loc),
+                })
             }
-            _ => Token {
+            _ => Ok(Token {
                 token_type,
                 size: None,
                 loc,
-            },
+                leading_trivia: self.trivia(start, loc),
+            }),
+        }
+    }
+
+    /// The characters skipped between `start` and the token at `loc`, if they are kept
+    fn trivia(&self, start: usize, loc: usize) -> Option<String> {
+        (self.settings.keep_trivia && start < loc).then(|| self.input[start..loc].to_string())
+    }
+
+    /// The type of the token the character starts, taking the debug and halt commands into account
+    fn token_type(&self, c: &char) -> Option<TokenType> {
+        match c {
+            '#' if self.settings.debug_commands => Some(TokenType::Debug),
+            '!' if self.settings.halt_command => Some(TokenType::Halt),
+            _ => Token::is_token(c),
+        }
+    }
+
+    /// Whether the character is rejected in strict mode
+    fn is_rejected(&self, c: &char) -> bool {
+        self.settings.strict && self.token_type(c).is_none() && !c.is_whitespace()
+    }
+}
+
+impl From<&str> for Lexer {
+    fn from(input: &str) -> Self {
+        Lexer::new(input)
+    }
+}
+
+/// Create a lexer with the default settings, which never fails
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::{Lexer, TokenType};
+/// use std::str::FromStr;
+///
+/// let mut lexer = Lexer::from_str("+++").unwrap();
+/// assert_eq!(lexer.next_token().token_type, TokenType::Plus);
+/// ```
+impl FromStr for Lexer {
+    type Err = Infallible;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Ok(Lexer::new(input))
+    }
+}
+
+/// Get the line and column of a location in the input, both counted from 1
+///
+/// Lines end at `\n`, like the comments started by `comment_char`, so a file with Windows line
+/// endings has the same lines as with Unix ones and a lone `\r` doesn't end a line. The column
+/// counts characters, not bytes.
+///
+/// # Arguments
+/// * `input` - The input the location points into
+/// * `loc` - The byte offset of a character, as in the `loc` of a token. An offset inside of a
+///   character points at that character and an offset past the input at its end
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::line_col;
+///
+/// let input = "+\r\n\t-\n.";
+/// assert_eq!(line_col(input, 0), (1, 1));
+/// assert_eq!(line_col(input, 4), (2, 2));
+/// assert_eq!(line_col(input, 6), (3, 1));
+/// ```
+pub fn line_col(input: &str, loc: usize) -> (usize, usize) {
+    let mut end = loc.min(input.len());
+    while !input.is_char_boundary(end) {
+        end -= 1;
+    }
+    let (mut line, mut column) = (1, 1);
+    for c in input[..end].chars() {
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
         }
     }
+    (line, column)
+}
+
+/// Turn the tokens back into source code, including their `leading_trivia`
+///
+/// Lexed with `keep_trivia` enabled, this restores the original source. Brackets are written
+/// once, as their `size` is the jump target after parsing.
+///
+/// # Arguments
+/// * `tokens` - The tokens to be turned into source code
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::{to_source, LexerSettings};
+/// use rbfc::parser::Parser;
+///
+/// let code = "+[- a comment ]\n";
+/// let settings = LexerSettings { keep_trivia: true, ..Default::default() };
+/// let ops = Parser::with_settings(code, settings).parse().unwrap();
+/// assert_eq!(to_source(&ops), code);
+/// ```
+pub fn to_source(tokens: &[Token]) -> String {
+    let mut source = String::new();
+    for token in tokens {
+        if let Some(trivia) = &token.leading_trivia {
+            source.push_str(trivia);
+        }
+        let (c, size) = match token.token_type {
+            TokenType::ShiftLeft => ('<', token.size.unwrap_or(1)),
+            TokenType::ShiftRight => ('>', token.size.unwrap_or(1)),
+            TokenType::Plus => ('+', token.size.unwrap_or(1)),
+            TokenType::Minus => ('-', token.size.unwrap_or(1)),
+            TokenType::Dot => ('.', token.size.unwrap_or(1)),
+            TokenType::Comma => (',', token.size.unwrap_or(1)),
+            TokenType::OpenBracket => ('[', 1),
+            TokenType::CloseBracket => (']', 1),
+            TokenType::Debug => ('#', 1),
+            TokenType::Halt => ('!', 1),
+            // Ops produced by the optimizer have no source of their own
+            _ => continue,
+        };
+        source.extend(std::iter::repeat_n(c, size));
+    }
+    source
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_from_str() {
+        let mut lexer = Lexer::from("+-");
+        assert_eq!(lexer.next_token().token_type, TokenType::Plus);
+        let mut lexer: Lexer = "+-".parse().unwrap();
+        assert_eq!(lexer.next_token().token_type, TokenType::Plus);
+        let mut lexer = Lexer::with_settings("-", LexerSettings::default());
+        assert_eq!(lexer.next_token().token_type, TokenType::Minus);
+    }
+
+    #[test]
+    fn test_whitespace_ignored() {
+        let mut lexer = Lexer::new("+\t+\r\n+\u{a0}+\u{2003}-");
+        assert_eq!(lexer.next_token().size, Some(4));
+        assert_eq!(lexer.next_token().token_type, TokenType::Minus);
+        assert_eq!(lexer.next_token().token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn test_line_col_crlf() {
+        let crlf = "+++\r\n[->\r\n\t+<]\r\n";
+        let unix = crlf.replace("\r\n", "\n");
+        let locs = |input: &str| {
+            let mut lexer = Lexer::new(input);
+            let mut positions = Vec::new();
+            loop {
+                let token = lexer.next_token();
+                positions.push(line_col(input, token.loc));
+                if token.token_type == TokenType::Eof {
+                    break positions;
+                }
+            }
+        };
+        assert_eq!(locs(crlf), locs(&unix));
+        assert_eq!(locs(crlf)[4], (3, 2));
+        assert_eq!(locs(crlf).last(), Some(&(4, 1)));
+        // A lone carriage return doesn't end a line, like it doesn't end a comment
+        assert_eq!(line_col("+\r-", 2), (1, 3));
+        assert_eq!(line_col("+\r\n-", 3), (2, 1));
+        let settings = LexerSettings {
+            comment_char: Some('#'),
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_settings("# +\r-\n.", settings);
+        let token = lexer.next_token();
+        assert_eq!(token.token_type, TokenType::Dot);
+        assert_eq!(line_col("# +\r-\n.", token.loc), (2, 1));
+        // An offset inside of a character points at the character
+        assert_eq!(line_col("\né+", 2), (2, 1));
+        assert_eq!(line_col("\né+", 3), (2, 2));
+    }
+
     #[test]
     fn test_next_token() {
         let mut lexer = Lexer::new(String::from("+++"));
@@ -180,7 +550,8 @@ mod test {
             Token {
                 token_type: TokenType::Plus,
                 size: Some(3),
-                loc: 0
+                loc: 0,
+                leading_trivia: None,
             }
         );
 
@@ -190,7 +561,8 @@ mod test {
             Token {
                 token_type: TokenType::Plus,
                 size: Some(2),
-                loc: 0
+                loc: 0,
+                leading_trivia: None,
             }
         );
         assert_eq!(
@@ -198,7 +570,8 @@ mod test {
             Token {
                 token_type: TokenType::ShiftRight,
                 size: Some(1),
-                loc: 2
+                loc: 2,
+                leading_trivia: None,
             }
         );
     }
@@ -211,7 +584,8 @@ mod test {
             Token {
                 token_type: TokenType::OpenBracket,
                 size: None,
-                loc: 0
+                loc: 0,
+                leading_trivia: None,
             }
         );
 
@@ -221,8 +595,201 @@ mod test {
             Token {
                 token_type: TokenType::CloseBracket,
                 size: None,
-                loc: 0
+                loc: 0,
+                leading_trivia: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_token_loc() {
+        // A token is located at its first character, not where the lexer started looking for it
+        let mut lexer = Lexer::new(String::from("a +  -"));
+        assert_eq!(lexer.next_token().loc, 2);
+        assert_eq!(lexer.next_token().loc, 5);
+    }
+
+    #[test]
+    fn test_comment_char() {
+        let settings = LexerSettings {
+            comment_char: Some('#'),
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_settings(String::from("#+++ a comment"), settings);
+        assert_eq!(
+            lexer.next_token(),
+            Token {
+                token_type: TokenType::Eof,
+                size: None,
+                loc: 14,
+                leading_trivia: None,
+            }
+        );
+
+        let settings = LexerSettings {
+            comment_char: Some('#'),
+            ..Default::default()
+        };
+        let mut lexer =
+            Lexer::with_settings(String::from("#!/usr/bin/env rbfc\n+ # +\n+"), settings);
+        assert_eq!(
+            lexer.next_token(),
+            Token {
+                token_type: TokenType::Plus,
+                size: Some(2),
+                loc: 20,
+                leading_trivia: None,
             }
         );
     }
+
+    #[test]
+    fn test_multibyte_chars() {
+        // `é` and `→` take up two and three bytes
+        let mut lexer = Lexer::new(String::from("é+→-+"));
+        assert_eq!(
+            lexer.next_token(),
+            Token {
+                token_type: TokenType::Plus,
+                size: Some(1),
+                loc: 2,
+                leading_trivia: None,
+            }
+        );
+        assert_eq!(lexer.next_token().loc, 6);
+        assert_eq!(lexer.next_token().loc, 7);
+        assert_eq!(lexer.next_token().loc, 8);
+
+        let settings = LexerSettings {
+            comment_char: Some('§'),
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_settings(String::from("§ ñ +\n+"), settings);
+        assert_eq!(
+            lexer.next_token(),
+            Token {
+                token_type: TokenType::Plus,
+                size: Some(1),
+                loc: 8,
+                leading_trivia: None,
+            }
+        );
+
+        let settings = LexerSettings {
+            strict: true,
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_settings(String::from("+é"), settings);
+        assert_eq!(lexer.try_next_token().unwrap().size, Some(1));
+        assert_eq!(
+            lexer.try_next_token(),
+            Err(LexerError::UnexpectedChar('é', 1))
+        );
+    }
+
+    #[test]
+    fn test_long_input() {
+        // Lexing has to take linear time, a quadratic lexer takes minutes on this, also when
+        // skipping comments
+        let settings = LexerSettings {
+            comment_char: Some('#'),
+            ..Default::default()
+        };
+        for input in ["+>".repeat(50000), "# +>\n+>".repeat(50000)] {
+            let start = std::time::Instant::now();
+            let mut lexer = Lexer::with_settings(input, settings.clone());
+            let mut tokens = 0;
+            while lexer.next_token().token_type != TokenType::Eof {
+                tokens += 1;
+            }
+            assert_eq!(tokens, 100000);
+            assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn test_debug_commands() {
+        let settings = LexerSettings {
+            debug_commands: true,
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_settings(String::from("+++##"), settings);
+        assert_eq!(lexer.next_token().token_type, TokenType::Plus);
+        for loc in [3, 4] {
+            assert_eq!(
+                lexer.next_token(),
+                Token {
+                    token_type: TokenType::Debug,
+                    size: None,
+                    loc,
+                    leading_trivia: None,
+                }
+            );
+        }
+
+        let mut lexer = Lexer::new(String::from("+++#"));
+        assert_eq!(lexer.next_token().token_type, TokenType::Plus);
+        assert_eq!(lexer.next_token().token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn test_halt_command() {
+        let settings = LexerSettings {
+            halt_command: true,
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_settings(String::from("+!+"), settings);
+        assert_eq!(lexer.next_token().token_type, TokenType::Plus);
+        assert_eq!(
+            lexer.next_token(),
+            Token {
+                token_type: TokenType::Halt,
+                size: None,
+                loc: 1,
+                leading_trivia: None,
+            }
+        );
+        assert_eq!(lexer.next_token().token_type, TokenType::Plus);
+
+        let mut lexer = Lexer::new(String::from("+!+"));
+        assert_eq!(lexer.next_token().size, Some(2));
+    }
+
+    #[test]
+    fn test_keep_trivia() {
+        let settings = LexerSettings {
+            keep_trivia: true,
+            ..Default::default()
+        };
+        let mut lexer = Lexer::with_settings("++ foo ++", settings.clone());
+        assert_eq!(lexer.next_token().leading_trivia, None);
+        assert_eq!(
+            lexer.next_token(),
+            Token {
+                token_type: TokenType::Plus,
+                size: Some(2),
+                loc: 7,
+                leading_trivia: Some(String::from(" foo ")),
+            }
+        );
+
+        for code in ["++ foo ++", "# shebang\n[->+<] done\n", ""] {
+            let ops = crate::parser::Parser::with_settings(code, settings.clone())
+                .parse()
+                .unwrap();
+            assert_eq!(to_source(&ops), code);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let ops = crate::parser::Parser::new(String::from("+[->+<].,"))
+            .parse()
+            .unwrap();
+        let json = serde_json::to_string(&ops).unwrap();
+        assert!(json.starts_with(r#"[{"token_type":"Plus","size":1,"loc":0}"#));
+        let deserialized: Vec<Token> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, ops);
+    }
 }
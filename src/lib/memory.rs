@@ -0,0 +1,381 @@
+use crate::interpreter::{CellWidth, Tape};
+use thiserror::Error;
+
+/// Error type for the memory
+///
+/// This error type is used to represent moving past the ends of a tape that can't wrap around or
+/// grow
+#[derive(Debug, Error, PartialEq)]
+pub enum MemoryError {
+    #[error("Tape overflow")]
+    Overflow,
+    #[error("Tape underflow")]
+    Underflow,
+}
+
+/// The memory of a program, a tape of cells and the data pointer into it
+///
+/// The memory only stores the values of the cells, wrapping them around at the cell width is
+/// left to the interpreter. Moving past the ends of the tape wraps around, grows the tape or
+/// returns an error, depending on the kind of tape.
+///
+/// # Fields
+/// * `cells` - The cells of the tape
+/// * `dp` - The data pointer, the position of the current cell
+/// * `tape` - The size of the tape, see `Tape`
+/// * `wrap_left` - Whether moving left of the first cell wraps around to the last one
+/// * `wrap_right` - Whether moving right of the last cell of a fixed size tape wraps around to
+///   the first one
+///
+/// # Example
+/// ```
+/// use rbfc::interpreter::Tape;
+/// use rbfc::memory::Memory;
+///
+/// let mut memory = Memory::new(Tape::Fixed(4), true, true);
+/// memory.set(3);
+/// memory.shift(-1).unwrap();
+/// assert_eq!(memory.dp(), 3);
+/// memory.shift(1).unwrap();
+/// assert_eq!(memory.get(), 3);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Memory {
+    cells: Vec<u32>,
+    dp: usize,
+    tape: Tape,
+    wrap_left: bool,
+    wrap_right: bool,
+}
+
+impl Memory {
+    /// Create a new zeroed memory with the data pointer on the first cell
+    ///
+    /// # Arguments
+    /// * `tape` - The size of the tape
+    /// * `wrap_left` - Whether moving left of the first cell wraps around
+    /// * `wrap_right` - Whether moving right of the last cell of a fixed size tape wraps around
+    pub fn new(tape: Tape, wrap_left: bool, wrap_right: bool) -> Memory {
+        Memory {
+            cells: vec![0; tape.initial_len()],
+            dp: 0,
+            tape,
+            wrap_left,
+            wrap_right,
+        }
+    }
+
+    /// The value of the current cell
+    #[inline]
+    pub fn get(&self) -> u32 {
+        self.cells[self.dp]
+    }
+
+    /// Set the value of the current cell
+    ///
+    /// # Arguments
+    /// * `value` - The new value of the cell
+    #[inline]
+    pub fn set(&mut self, value: u32) {
+        self.cells[self.dp] = value;
+    }
+
+    /// The value of the cell at the given position
+    ///
+    /// # Arguments
+    /// * `position` - The position of the cell
+    ///
+    /// # Panics
+    /// Panics if the position is not on the tape
+    #[inline]
+    pub fn get_at(&self, position: usize) -> u32 {
+        self.cells[position]
+    }
+
+    /// Set the value of the cell at the given position, growing a `GrowRight` tape to it
+    ///
+    /// # Arguments
+    /// * `position` - The position of the cell
+    /// * `value` - The new value of the cell
+    ///
+    /// # Panics
+    /// Panics if the position is outside of a fixed size tape
+    #[inline]
+    pub fn set_at(&mut self, position: usize, value: u32) {
+        if self.tape == Tape::GrowRight {
+            self.grow_to(position);
+        }
+        self.cells[position] = value;
+    }
+
+    /// Move the data pointer by `offset` cells
+    ///
+    /// # Arguments
+    /// * `offset` - The number of cells to move, to the left if negative
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::interpreter::Tape;
+    /// use rbfc::memory::{Memory, MemoryError};
+    ///
+    /// let mut memory = Memory::new(Tape::Fixed(4), false, false);
+    /// assert_eq!(memory.shift(-1), Err(MemoryError::Underflow));
+    /// assert_eq!(memory.shift(4), Err(MemoryError::Overflow));
+    /// ```
+    ///
+    /// # Errors
+    /// Returns an error if the data pointer would leave the tape, see `position`
+    #[inline]
+    pub fn shift(&mut self, offset: isize) -> Result<(), MemoryError> {
+        self.dp = self.position(offset)?;
+        Ok(())
+    }
+
+    /// Get the position of the cell at `offset` relative to the data pointer, wrapping it around
+    /// or growing the tape if it allows it
+    ///
+    /// # Arguments
+    /// * `offset` - The offset of the cell, to the left if negative
+    ///
+    /// # Errors
+    /// Returns `MemoryError::Overflow` for a position right of a fixed size tape that doesn't
+    /// wrap to the right, and `MemoryError::Underflow` for a position left of a tape that doesn't
+    /// wrap to the left. A `GrowRight` tape never wraps
+    #[inline]
+    pub fn position(&mut self, offset: isize) -> Result<usize, MemoryError> {
+        // A position left of the tape wraps around to a large one, so this only takes positions
+        // on the tape
+        let target = self.dp.wrapping_add_signed(offset);
+        if target < self.cells.len() {
+            return Ok(target);
+        }
+        self.position_off_tape(offset)
+    }
+
+    /// Get the position of the cell at `offset` for a position that is not on the tape, see
+    /// `position`
+    #[cold]
+    fn position_off_tape(&mut self, offset: isize) -> Result<usize, MemoryError> {
+        let len = self.cells.len() as isize;
+        let target = self.dp as isize + offset;
+        if target >= len && self.tape == Tape::GrowRight {
+            self.grow_to(target as usize);
+            Ok(target as usize)
+        } else if target >= len {
+            if !self.wrap_right {
+                return Err(MemoryError::Overflow);
+            }
+            Ok(target.rem_euclid(len) as usize)
+        } else if target < 0 {
+            if !self.wrap_left || self.tape == Tape::GrowRight {
+                return Err(MemoryError::Underflow);
+            }
+            Ok(target.rem_euclid(len) as usize)
+        } else {
+            Ok(target as usize)
+        }
+    }
+
+    /// The data pointer, the position of the current cell
+    #[inline]
+    pub fn dp(&self) -> usize {
+        self.dp
+    }
+
+    /// Move the data pointer to the given position
+    ///
+    /// # Arguments
+    /// * `dp` - The new position of the data pointer
+    #[inline]
+    pub fn set_dp(&mut self, dp: usize) {
+        self.dp = dp;
+    }
+
+    /// The cells of the tape
+    pub fn cells(&self) -> &[u32] {
+        &self.cells
+    }
+
+    /// The cells of the tape, to change many of them at once
+    pub fn cells_mut(&mut self) -> &mut [u32] {
+        &mut self.cells
+    }
+
+    /// The number of cells on the tape
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Whether the tape has no cells
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+
+    /// Set every cell to zero and move the data pointer to the first cell
+    pub fn clear(&mut self) {
+        self.cells.fill(0);
+        self.dp = 0;
+    }
+
+    /// Grow the tape so that the given position is on it
+    fn grow_to(&mut self, position: usize) {
+        if position >= self.cells.len() {
+            self.cells.resize(position + 1, 0);
+        }
+    }
+}
+
+/// The tape shared by the interpreter and the compiler, the `tape` field of both settings
+///
+/// Both `InterpreterSettings` and `CompilerSettings` can also be created from a config, with
+/// their other fields at their defaults.
+///
+/// # Fields
+/// * `wrap_left` - Whether moving left of the first cell wraps around to the last one
+/// * `wrap_right` - Whether moving right of the last cell wraps around to the first one
+/// * `tape_size` - The number of cells on the tape
+/// * `grow_right` - Whether the tape of the interpreter grows when moving right of its last cell
+///   instead of having `tape_size` cells, see `Tape::GrowRight`. The compiler ignores this
+/// * `cell_width` - The number of bits of a cell, see `CellWidth`
+///
+/// # Example
+/// ```
+/// use rbfc::compiler::CompilerSettings;
+/// use rbfc::interpreter::{InterpreterSettings, Tape};
+/// use rbfc::memory::TapeConfig;
+///
+/// let config = TapeConfig::default().wrap(true).tape_size(1024);
+/// let interpreter = InterpreterSettings::from(&config);
+/// assert_eq!(interpreter.tape.kind(), Tape::Fixed(1024));
+/// let compiler = CompilerSettings { opt_level: 1, ..CompilerSettings::from(&config) };
+/// assert!(compiler.tape.wrap_left && compiler.tape.wrap_right);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TapeConfig {
+    pub wrap_left: bool,
+    pub wrap_right: bool,
+    pub tape_size: usize,
+    pub grow_right: bool,
+    pub cell_width: CellWidth,
+}
+
+impl Default for TapeConfig {
+    fn default() -> Self {
+        TapeConfig {
+            wrap_left: false,
+            wrap_right: false,
+            tape_size: 30000,
+            grow_right: false,
+            cell_width: CellWidth::default(),
+        }
+    }
+}
+
+impl TapeConfig {
+    /// Set whether the tape wraps around in both directions
+    pub fn wrap(self, wrap: bool) -> TapeConfig {
+        TapeConfig {
+            wrap_left: wrap,
+            wrap_right: wrap,
+            ..self
+        }
+    }
+
+    /// Set whether moving left of the first cell wraps around
+    pub fn wrap_left(self, wrap_left: bool) -> TapeConfig {
+        TapeConfig { wrap_left, ..self }
+    }
+
+    /// Set whether moving right of the last cell wraps around
+    pub fn wrap_right(self, wrap_right: bool) -> TapeConfig {
+        TapeConfig { wrap_right, ..self }
+    }
+
+    /// Set the number of cells on the tape
+    pub fn tape_size(self, tape_size: usize) -> TapeConfig {
+        TapeConfig { tape_size, ..self }
+    }
+
+    /// Set whether the tape of the interpreter grows when moving right of the last cell
+    pub fn grow_right(self, grow_right: bool) -> TapeConfig {
+        TapeConfig { grow_right, ..self }
+    }
+
+    /// Set the number of bits of a cell
+    pub fn cell_width(self, cell_width: CellWidth) -> TapeConfig {
+        TapeConfig { cell_width, ..self }
+    }
+
+    /// The tape the interpreter runs on
+    pub fn kind(&self) -> Tape {
+        if self.grow_right {
+            Tape::GrowRight
+        } else {
+            Tape::Fixed(self.tape_size)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::CompilerSettings;
+    use crate::interpreter::InterpreterSettings;
+
+    #[test]
+    fn test_tape_config() {
+        let config = TapeConfig::default()
+            .wrap(true)
+            .wrap_right(false)
+            .tape_size(1024)
+            .cell_width(CellWidth::U16);
+
+        let interpreter = InterpreterSettings::from(&config);
+        assert_eq!(interpreter.tape, config);
+        assert_eq!(interpreter.tape.kind(), Tape::Fixed(1024));
+        assert_eq!(interpreter.max_steps, None);
+        assert_eq!(config.grow_right(true).kind(), Tape::GrowRight);
+
+        let compiler = CompilerSettings::from(&config);
+        assert_eq!(compiler.tape, config);
+        assert!(compiler.tape.wrap_left && !compiler.tape.wrap_right);
+        assert_eq!(compiler.opt_level, 0);
+    }
+
+    #[test]
+    fn test_shift_get_set() {
+        let mut memory = Memory::new(Tape::Fixed(3), true, true);
+        memory.set(1);
+        memory.shift(2).unwrap();
+        memory.set(2);
+        memory.shift(2).unwrap();
+        assert_eq!((memory.dp(), memory.get()), (1, 0));
+        memory.shift(-1).unwrap();
+        assert_eq!(memory.get(), 1);
+        memory.shift(-4).unwrap();
+        assert_eq!(memory.get(), 2);
+        assert_eq!(memory.cells(), [1, 0, 2]);
+
+        let mut memory = Memory::new(Tape::GrowRight, true, true);
+        memory.shift(30001).unwrap();
+        memory.set(5);
+        assert_eq!(memory.len(), 30002);
+        assert_eq!(memory.get_at(30001), 5);
+        assert_eq!(memory.shift(-30002), Err(MemoryError::Underflow));
+        memory.clear();
+        assert_eq!((memory.dp(), memory.get_at(30001)), (0, 0));
+    }
+
+    #[test]
+    fn test_wrap_one_direction() {
+        let mut memory = Memory::new(Tape::Fixed(3), false, true);
+        memory.shift(4).unwrap();
+        assert_eq!(memory.dp(), 1);
+        assert_eq!(memory.shift(-2), Err(MemoryError::Underflow));
+
+        let mut memory = Memory::new(Tape::Fixed(3), true, false);
+        memory.shift(-1).unwrap();
+        assert_eq!(memory.dp(), 2);
+        assert_eq!(memory.shift(1), Err(MemoryError::Overflow));
+    }
+}
@@ -26,7 +26,8 @@
 //!    Token {
 //!    token_type: TokenType::Plus,
 //!    size: Some(3),
-//!    loc: 0
+//!    loc: 0,
+//!    leading_trivia: None,
 //! });
 //! ```
 //!
@@ -48,7 +49,94 @@
 //! let result = compiler.compile_code();
 //! ```
 
+use interpreter::{Interpreter, InterpreterError, InterpreterSettings};
+use lexer::Token;
+use parser::{Parser, ParserError};
+
+pub mod analyze;
+pub mod codegen;
 pub mod compiler;
+pub mod fuzz;
 pub mod interpreter;
+#[cfg(feature = "jit")]
+mod jit;
 pub mod lexer;
+pub mod memory;
+pub mod optimizer;
 pub mod parser;
+
+/// Parse the code into a sequence of operations with the default settings
+///
+/// This is a shorthand for `Parser::new(code).parse()`.
+///
+/// # Arguments
+/// * `code` - The code to be parsed
+///
+/// # Example
+/// ```
+/// let ops = rbfc::parse("+++[->+<]").unwrap();
+/// assert_eq!(ops.len(), 8);
+/// ```
+///
+/// # Errors
+/// Returns the same errors as `Parser::parse`
+pub fn parse(code: &str) -> Result<Vec<Token>, ParserError> {
+    Parser::new(code).parse()
+}
+
+/// Run the program on the input and return its output and the final tape
+///
+/// The tape ends at its last nonzero cell and every cell is truncated to its lowest byte. Reading
+/// past the end of the input returns `InterpreterError::InputError`.
+///
+/// # Arguments
+/// * `code` - The code of the program
+/// * `input` - The input of the program
+/// * `settings` - The settings of the interpreter
+///
+/// # Example
+/// ```
+/// use rbfc::interpreter::InterpreterSettings;
+///
+/// let (output, tape) = rbfc::run(",+.>++", &[65], InterpreterSettings::default()).unwrap();
+/// assert_eq!(output, b"B");
+/// assert_eq!(tape, [66, 2]);
+/// ```
+///
+/// # Errors
+/// Returns the same errors as `Interpreter::new` and `Interpreter::interpret`
+pub fn run(
+    code: &str,
+    input: &[u8],
+    settings: InterpreterSettings,
+) -> Result<(Vec<u8>, Vec<u8>), InterpreterError> {
+    let mut interpreter = Interpreter::new(code, settings)?;
+    let (output, _) = interpreter.run_with_input_queue(input.iter().copied().collect())?;
+
+    let cells = interpreter.memory().cells();
+    let end = cells
+        .iter()
+        .rposition(|&cell| cell != 0)
+        .map_or(0, |last| last + 1);
+    let tape = cells[..end].iter().map(|&cell| cell as u8).collect();
+    Ok((output, tape))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let (output, tape) = run(",+.", &[65], InterpreterSettings::default()).unwrap();
+        assert_eq!(output, [66]);
+        assert_eq!(tape, [66]);
+
+        let (output, tape) = run(">>", &[], InterpreterSettings::default()).unwrap();
+        assert!(output.is_empty() && tape.is_empty());
+        assert!(matches!(
+            run(",", &[], InterpreterSettings::default()),
+            Err(InterpreterError::InputError)
+        ));
+    }
+}
@@ -0,0 +1,782 @@
+//! The optimizer module contains passes that rewrite a parsed sequence of tokens into an
+//! equivalent but cheaper sequence of tokens.
+//!
+//! Runs of identical operators are already coalesced by the lexer, so the passes here work on
+//! whole loops and on straight-line runs of mixed operators.
+//!
+//! # Example
+//! ```
+//! use rbfc::lexer::TokenType;
+//! use rbfc::optimizer;
+//! use rbfc::parser::Parser;
+//!
+//! let ops = Parser::new(String::from("+[-]")).parse().unwrap();
+//! let ops = optimizer::optimize(ops, 1);
+//! assert_eq!(ops[1].token_type, TokenType::Clear);
+//! ```
+
+use crate::lexer::{Token, TokenType};
+use std::collections::BTreeMap;
+
+/// Apply the optimizer passes enabled by the given optimization level
+///
+/// # Levels
+/// * `0` - No optimizations, the ops are returned unchanged
+/// * `1` - Runs of `+` and `-` are folded into their net change, see `fold_arith`, clear loops
+///   like `[-]` are folded into a single `Clear`, a `Clear` followed by `+` or `-` into a
+///   `SetValue` and scan loops like `[>]` into a `Scan`
+/// * `2` - Additionally multiply loops like `[->++<]` are lowered into `MulAdd` ops
+///   followed by a `Clear`
+/// * `3` - Additionally straight-line runs of `+`, `-`, `<` and `>` are coalesced into their
+///   net effect, see `coalesce_block`
+///
+/// Levels above 3 behave like level 3.
+///
+/// # Example
+/// ```
+/// use rbfc::optimizer;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new(String::from("[->+<]")).parse().unwrap();
+/// assert_eq!(optimizer::optimize(ops.clone(), 0), ops);
+/// ```
+pub fn optimize(ops: Vec<Token>, level: u8) -> Vec<Token> {
+    let ops = fold_scan_loops(match level {
+        0 => return ops,
+        1 => fold_clear_loops(fold_arith(ops)),
+        2 => fold_multiply_loops(fold_arith(ops)),
+        _ => coalesce_blocks(fold_multiply_loops(fold_arith(ops))),
+    });
+    relink(fold_set_values(ops))
+}
+
+/// Fold every run of consecutive `+` and `-` into a single op with their net change
+///
+/// The lexer only coalesces identical operators, so `+-+` is three ops. Any other op ends the
+/// run, as does a shift, since it changes the cell the following ops apply to. The folded op
+/// keeps the location of the first op of the run, a run that cancels out produces no op.
+///
+/// The jump targets of the brackets are not updated, use `optimize` to get a runnable sequence
+/// of ops.
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::TokenType;
+/// use rbfc::optimizer;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new("+-+.+-").parse().unwrap();
+/// let ops = optimizer::fold_arith(ops);
+/// assert_eq!(ops[0].token_type, TokenType::Plus);
+/// assert_eq!(ops[1].token_type, TokenType::Dot);
+/// assert_eq!(ops[2].token_type, TokenType::Eof);
+/// ```
+pub fn fold_arith(ops: Vec<Token>) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::with_capacity(ops.len());
+    // The net change of the current run and the location of its first op
+    let mut run: Option<(isize, usize)> = None;
+
+    for op in ops {
+        let size = op.size.unwrap_or_default() as isize;
+        let delta = match op.token_type {
+            TokenType::Plus => size,
+            TokenType::Minus => -size,
+            _ => {
+                if let Some((delta, loc)) = run.take() {
+                    result.extend(net_arith(delta, loc));
+                }
+                result.push(op);
+                continue;
+            }
+        };
+        run.get_or_insert((0, op.loc)).0 += delta;
+    }
+    if let Some((delta, loc)) = run {
+        result.extend(net_arith(delta, loc));
+    }
+
+    result
+}
+
+/// The `Plus` or `Minus` for a net change of the current cell, or nothing if it is zero
+fn net_arith(delta: isize, loc: usize) -> Option<Token> {
+    let token_type = match delta {
+        0 => return None,
+        delta if delta > 0 => TokenType::Plus,
+        _ => TokenType::Minus,
+    };
+    Some(Token {
+        token_type,
+        size: Some(delta.unsigned_abs()),
+        loc,
+        leading_trivia: None,
+    })
+}
+
+/// Fold every run of consecutive `<` and `>` into a single shift by their net offset
+///
+/// The lexer only coalesces identical operators, so `>><` is two ops, each of which is bounds
+/// checked by the compiled code of a wrapping tape. The folded op keeps the location of the first
+/// op of the run, a run that cancels out produces no op. Moving by the net offset only matches
+/// moving step by step if the tape wraps in both directions, so the compiler applies this pass
+/// only then.
+///
+/// Unlike the other passes this one updates the jump targets of the brackets.
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::TokenType;
+/// use rbfc::optimizer;
+/// use rbfc::parser::Parser;
+///
+/// let ops = optimizer::fold_shifts(Parser::new(">><.<>").parse().unwrap());
+/// assert_eq!(ops[0].token_type, TokenType::ShiftRight);
+/// assert_eq!(ops[0].size, Some(1));
+/// assert_eq!(ops[1].token_type, TokenType::Dot);
+/// assert_eq!(ops[2].token_type, TokenType::Eof);
+/// ```
+pub fn fold_shifts(ops: Vec<Token>) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::with_capacity(ops.len());
+    // The net offset of the current run and the location of its first op
+    let mut run: Option<(isize, usize)> = None;
+
+    for op in ops {
+        let size = op.size.unwrap_or_default() as isize;
+        let offset = match op.token_type {
+            TokenType::ShiftRight => size,
+            TokenType::ShiftLeft => -size,
+            _ => {
+                if let Some((offset, loc)) = run.take() {
+                    result.extend(net_shift(offset, loc));
+                }
+                result.push(op);
+                continue;
+            }
+        };
+        run.get_or_insert((0, op.loc)).0 += offset;
+    }
+    if let Some((offset, loc)) = run {
+        result.extend(net_shift(offset, loc));
+    }
+
+    relink(result)
+}
+
+/// The `ShiftRight` or `ShiftLeft` for a net offset, or nothing if it is zero
+fn net_shift(offset: isize, loc: usize) -> Option<Token> {
+    let token_type = match offset {
+        0 => return None,
+        offset if offset > 0 => TokenType::ShiftRight,
+        _ => TokenType::ShiftLeft,
+    };
+    Some(Token {
+        token_type,
+        size: Some(offset.unsigned_abs()),
+        loc,
+        leading_trivia: None,
+    })
+}
+
+/// Fold loops of the form `[-]` and `[+]` into a single `Clear`
+///
+/// The jump targets of the remaining brackets are not updated, use `optimize` to get a runnable
+/// sequence of ops.
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::TokenType;
+/// use rbfc::optimizer;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new(String::from("[-]")).parse().unwrap();
+/// let ops = optimizer::fold_clear_loops(ops);
+/// assert_eq!(ops[0].token_type, TokenType::Clear);
+/// ```
+pub fn fold_clear_loops(ops: Vec<Token>) -> Vec<Token> {
+    let mut result = Vec::with_capacity(ops.len());
+    let mut i = 0;
+
+    while i < ops.len() {
+        if let [open, body, close, ..] = &ops[i..] {
+            if open.token_type == TokenType::OpenBracket
+                && matches!(body.token_type, TokenType::Plus | TokenType::Minus)
+                && body.size == Some(1)
+                && close.token_type == TokenType::CloseBracket
+            {
+                result.push(Token {
+                    token_type: TokenType::Clear,
+                    size: None,
+                    loc: open.loc,
+                    leading_trivia: None,
+                });
+                i += 3;
+                continue;
+            }
+        }
+        result.push(ops[i].clone());
+        i += 1;
+    }
+
+    result
+}
+
+/// Fold loops only moving the data pointer, like `[>]` and `[<<]`, into a single `Scan`
+///
+/// The `Scan` keeps the location of the opening bracket. Its step is the size of the shift,
+/// negated for `<`.
+///
+/// The jump targets of the remaining brackets are not updated, use `optimize` to get a runnable
+/// sequence of ops.
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::TokenType;
+/// use rbfc::optimizer;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new("[<<]").parse().unwrap();
+/// let ops = optimizer::fold_scan_loops(ops);
+/// assert_eq!(ops[0].token_type, TokenType::Scan { step: -2 });
+/// ```
+pub fn fold_scan_loops(ops: Vec<Token>) -> Vec<Token> {
+    let mut result = Vec::with_capacity(ops.len());
+    let mut i = 0;
+
+    while i < ops.len() {
+        if let [open, body, close, ..] = &ops[i..] {
+            let size = body.size.unwrap_or_default() as isize;
+            let step = match body.token_type {
+                TokenType::ShiftRight => Some(size),
+                TokenType::ShiftLeft => Some(-size),
+                _ => None,
+            };
+            if let Some(step) = step.filter(|_| {
+                open.token_type == TokenType::OpenBracket
+                    && close.token_type == TokenType::CloseBracket
+            }) {
+                result.push(Token {
+                    token_type: TokenType::Scan { step },
+                    size: None,
+                    loc: open.loc,
+                    leading_trivia: None,
+                });
+                i += 3;
+                continue;
+            }
+        }
+        result.push(ops[i].clone());
+        i += 1;
+    }
+
+    result
+}
+
+/// Fold a `Clear` immediately followed by a `+` or `-` into a single `SetValue`
+///
+/// The value is the size of the `+`, or the negated size of the `-`, so it wraps around at the
+/// cell width like the ops it replaces. The folded op keeps the location of the `Clear`.
+///
+/// The jump targets of the brackets are not updated, use `optimize` to get a runnable sequence
+/// of ops.
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::TokenType;
+/// use rbfc::optimizer;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new("[-]+++").parse().unwrap();
+/// let ops = optimizer::fold_set_values(optimizer::fold_clear_loops(ops));
+/// assert_eq!(ops[0].token_type, TokenType::SetValue { value: 3 });
+/// ```
+pub fn fold_set_values(ops: Vec<Token>) -> Vec<Token> {
+    let mut result: Vec<Token> = Vec::with_capacity(ops.len());
+
+    for op in ops {
+        let size = op.size.unwrap_or_default() as isize;
+        let value = match op.token_type {
+            TokenType::Plus => size,
+            TokenType::Minus => -size,
+            _ => {
+                result.push(op);
+                continue;
+            }
+        };
+        match result.last_mut() {
+            Some(last) if last.token_type == TokenType::Clear => {
+                last.token_type = TokenType::SetValue { value };
+            }
+            _ => result.push(op),
+        }
+    }
+
+    result
+}
+
+/// Lower loops that only move value from the current cell into other cells, like `[->++<]`,
+/// into a `MulAdd` for every target cell followed by a `Clear`
+///
+/// A loop qualifies if its body contains only `+`, `-`, `<` and `>`, returns the data pointer to
+/// where it started and decrements the current cell by exactly one. Clear loops qualify as well,
+/// they are lowered into a lone `Clear`.
+///
+/// The jump targets of the remaining brackets are not updated, use `optimize` to get a runnable
+/// sequence of ops.
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::TokenType;
+/// use rbfc::optimizer;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new(String::from("[->++<]")).parse().unwrap();
+/// let ops = optimizer::fold_multiply_loops(ops);
+/// assert_eq!(ops[0].token_type, TokenType::MulAdd { offset: 1, factor: 2 });
+/// assert_eq!(ops[1].token_type, TokenType::Clear);
+/// ```
+pub fn fold_multiply_loops(ops: Vec<Token>) -> Vec<Token> {
+    let mut result = Vec::with_capacity(ops.len());
+    let mut i = 0;
+
+    while i < ops.len() {
+        if ops[i].token_type == TokenType::OpenBracket {
+            if let Some((deltas, end)) = multiply_loop(&ops, i) {
+                let loc = ops[i].loc;
+                for (offset, factor) in deltas {
+                    result.push(Token {
+                        token_type: TokenType::MulAdd { offset, factor },
+                        size: None,
+                        loc,
+                        leading_trivia: None,
+                    });
+                }
+                result.push(Token {
+                    token_type: TokenType::Clear,
+                    size: None,
+                    loc,
+                    leading_trivia: None,
+                });
+                i = end + 1;
+                continue;
+            }
+        }
+        result.push(ops[i].clone());
+        i += 1;
+    }
+
+    result
+}
+
+/// Check whether the loop opening at `start` is a multiply loop and return the non zero cell
+/// deltas of the other cells together with the index of the closing bracket
+fn multiply_loop(ops: &[Token], start: usize) -> Option<(BTreeMap<isize, isize>, usize)> {
+    let mut deltas = BTreeMap::new();
+    let mut offset: isize = 0;
+
+    for (i, op) in ops.iter().enumerate().skip(start + 1) {
+        let size = op.size.unwrap_or_default() as isize;
+        match op.token_type {
+            TokenType::Plus => *deltas.entry(offset).or_insert(0) += size,
+            TokenType::Minus => *deltas.entry(offset).or_insert(0) -= size,
+            TokenType::ShiftRight => offset += size,
+            TokenType::ShiftLeft => offset -= size,
+            TokenType::CloseBracket => {
+                if offset != 0 || deltas.remove(&0) != Some(-1) {
+                    return None;
+                }
+                deltas.retain(|_, factor| *factor != 0);
+                return Some((deltas, i));
+            }
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+/// Apply `coalesce_block` to every maximal run of `+`, `-`, `<` and `>` in the ops
+///
+/// The jump targets of the brackets are not updated, use `optimize` to get a runnable sequence
+/// of ops.
+pub fn coalesce_blocks(ops: Vec<Token>) -> Vec<Token> {
+    let mut result = Vec::with_capacity(ops.len());
+    let mut start = 0;
+
+    for (i, op) in ops.iter().enumerate() {
+        if !is_straight_line(&op.token_type) {
+            result.extend(coalesce_block(&ops[start..i]));
+            result.push(op.clone());
+            start = i + 1;
+        }
+    }
+    result.extend(coalesce_block(&ops[start..]));
+
+    result
+}
+
+fn is_straight_line(token_type: &TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::Plus | TokenType::Minus | TokenType::ShiftLeft | TokenType::ShiftRight
+    )
+}
+
+/// Coalesce a straight-line run of `+`, `-`, `<` and `>` into its net effect
+///
+/// The net change of every touched cell is accumulated per offset from the starting data
+/// pointer, as well as the net movement of the data pointer. The result contains a `Plus` or
+/// `Minus` for the starting cell, an `AddAt` for every other changed cell in ascending order of
+/// offset and a single shift for the net movement. Cells and movements that cancel out produce
+/// no op.
+///
+/// Tokens of any other type in the run are kept as they are, in front of the coalesced ops.
+///
+/// # Example
+/// ```
+/// use rbfc::lexer::TokenType;
+/// use rbfc::optimizer;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new(String::from(">+<")).parse().unwrap();
+/// let ops = optimizer::coalesce_block(&ops[..3]);
+/// assert_eq!(ops.len(), 1);
+/// assert_eq!(ops[0].token_type, TokenType::AddAt { offset: 1, delta: 1 });
+/// ```
+pub fn coalesce_block(ops: &[Token]) -> Vec<Token> {
+    let mut result = Vec::new();
+    let mut deltas: BTreeMap<isize, (isize, usize)> = BTreeMap::new();
+    let mut offset: isize = 0;
+    let mut shift_loc = None;
+
+    for op in ops {
+        let size = op.size.unwrap_or_default() as isize;
+        match op.token_type {
+            TokenType::Plus => deltas.entry(offset).or_insert((0, op.loc)).0 += size,
+            TokenType::Minus => deltas.entry(offset).or_insert((0, op.loc)).0 -= size,
+            TokenType::ShiftRight => {
+                offset += size;
+                shift_loc.get_or_insert(op.loc);
+            }
+            TokenType::ShiftLeft => {
+                offset -= size;
+                shift_loc.get_or_insert(op.loc);
+            }
+            _ => result.push(op.clone()),
+        }
+    }
+
+    for (cell, (delta, loc)) in deltas {
+        let (token_type, size) = match (cell, delta) {
+            (_, 0) => continue,
+            (0, delta) if delta > 0 => (TokenType::Plus, Some(delta as usize)),
+            (0, delta) => (TokenType::Minus, Some(delta.unsigned_abs())),
+            (offset, delta) => (TokenType::AddAt { offset, delta }, None),
+        };
+        result.push(Token {
+            token_type,
+            size,
+            loc,
+            leading_trivia: None,
+        });
+    }
+
+    if let Some(loc) = shift_loc {
+        if offset != 0 {
+            let token_type = if offset > 0 {
+                TokenType::ShiftRight
+            } else {
+                TokenType::ShiftLeft
+            };
+            result.push(Token {
+                token_type,
+                size: Some(offset.unsigned_abs()),
+                loc,
+                leading_trivia: None,
+            });
+        }
+    }
+
+    result
+}
+
+/// Evaluate the leading part of a program that does not depend on loops or input
+///
+/// The ops are executed on a tape of 8 bit cells starting at zero until the first op that is not
+/// `+`, `-`, `<`, `>`, `Clear`, `SetValue` or `AddAt`, which includes every loop, `,` and `.`, or
+/// the first move left of the starting cell. The returned tape reaches up to the rightmost cell
+/// that was reached, together with the data pointer at that point. A backend can start with this
+/// tape instead of executing the prefix.
+///
+/// # Arguments
+/// * `ops` - The operations to evaluate
+///
+/// # Example
+/// ```
+/// use rbfc::optimizer::eval_const_prefix;
+/// use rbfc::parser::Parser;
+///
+/// let ops = Parser::new(">+++[-]").parse().unwrap();
+/// assert_eq!(eval_const_prefix(&ops), (vec![0, 3], 1));
+/// ```
+pub fn eval_const_prefix(ops: &[Token]) -> (Vec<u8>, usize) {
+    let mut tape = vec![0u8];
+    let mut dp: usize = 0;
+
+    for op in ops {
+        let size = op.size.unwrap_or_default();
+        let (target, delta) = match op.token_type {
+            TokenType::Plus => (Some(dp), size as isize),
+            TokenType::Minus => (Some(dp), -(size as isize)),
+            TokenType::AddAt { offset, delta } => (dp.checked_add_signed(offset), delta),
+            TokenType::Clear => {
+                tape[dp] = 0;
+                continue;
+            }
+            TokenType::SetValue { value } => {
+                tape[dp] = value as u8;
+                continue;
+            }
+            TokenType::ShiftRight => {
+                dp += size;
+                (Some(dp), 0)
+            }
+            TokenType::ShiftLeft => match dp.checked_sub(size) {
+                Some(left) => {
+                    dp = left;
+                    continue;
+                }
+                None => break,
+            },
+            _ => break,
+        };
+        let Some(target) = target else {
+            break;
+        };
+        if target >= tape.len() {
+            tape.resize(target + 1, 0);
+        }
+        tape[target] = tape[target].wrapping_add(delta as u8);
+    }
+
+    (tape, dp)
+}
+
+/// Recompute the jump targets stored in the `size` of the brackets after ops have been removed
+/// or inserted
+///
+/// The targets follow the same convention as the parser: an `OpenBracket` points past its
+/// `CloseBracket` and a `CloseBracket` points at the op after its `OpenBracket`. Unbalanced
+/// brackets are left as they are.
+fn relink(mut ops: Vec<Token>) -> Vec<Token> {
+    let mut jump_stack = Vec::new();
+
+    for i in 0..ops.len() {
+        match ops[i].token_type {
+            TokenType::OpenBracket => jump_stack.push(i),
+            TokenType::CloseBracket => {
+                if let Some(open) = jump_stack.pop() {
+                    ops[open].size = Some(i + 1);
+                    ops[i].size = Some(open + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    ops
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn parse(code: &str) -> Vec<Token> {
+        Parser::new(code.to_string()).parse().unwrap()
+    }
+
+    #[test]
+    fn test_clear_loops() {
+        let ops = optimize(parse("+[-]>[+]"), 1);
+        let types: Vec<_> = ops.iter().map(|op| &op.token_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                &TokenType::Plus,
+                &TokenType::Clear,
+                &TokenType::ShiftRight,
+                &TokenType::Clear,
+                &TokenType::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_loops() {
+        let ops = optimize(parse("+[>]<[<<][>+]"), 1);
+        let types: Vec<_> = ops.iter().map(|op| &op.token_type).collect();
+        assert_eq!(
+            types[..4],
+            [
+                &TokenType::Plus,
+                &TokenType::Scan { step: 1 },
+                &TokenType::ShiftLeft,
+                &TokenType::Scan { step: -2 },
+            ]
+        );
+        assert_eq!(ops[1].loc, 1);
+        assert_eq!(ops[4].token_type, TokenType::OpenBracket);
+        assert_eq!(ops[4].size, Some(8));
+        assert_eq!(
+            optimize(parse("[>]"), 0)[0].token_type,
+            TokenType::OpenBracket
+        );
+    }
+
+    #[test]
+    fn test_multiply_loops() {
+        let ops = optimize(parse("[->+>---<<][[->+<]]"), 2);
+        let types: Vec<_> = ops.iter().map(|op| &op.token_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                &TokenType::MulAdd {
+                    offset: 1,
+                    factor: 1
+                },
+                &TokenType::MulAdd {
+                    offset: 2,
+                    factor: -3
+                },
+                &TokenType::Clear,
+                &TokenType::OpenBracket,
+                &TokenType::MulAdd {
+                    offset: 1,
+                    factor: 1
+                },
+                &TokenType::Clear,
+                &TokenType::CloseBracket,
+                &TokenType::Eof
+            ]
+        );
+        assert_eq!(ops[3].size, Some(7));
+        assert_eq!(ops[6].size, Some(4));
+    }
+
+    #[test]
+    fn test_fold_arith() {
+        assert_eq!(
+            fold_arith(parse("++---")),
+            vec![
+                Token {
+                    token_type: TokenType::Minus,
+                    size: Some(1),
+                    loc: 0,
+                    leading_trivia: None,
+                },
+                Token {
+                    token_type: TokenType::Eof,
+                    size: None,
+                    loc: 5,
+                    leading_trivia: None,
+                },
+            ]
+        );
+        assert_eq!(fold_arith(parse("+-")).len(), 1);
+        let types: Vec<_> = fold_arith(parse("+->+-<-+.+"))
+            .into_iter()
+            .map(|op| (op.token_type, op.size))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                (TokenType::ShiftRight, Some(1)),
+                (TokenType::ShiftLeft, Some(1)),
+                (TokenType::Dot, Some(1)),
+                (TokenType::Plus, Some(1)),
+                (TokenType::Eof, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fold_shifts() {
+        let types: Vec<_> = fold_shifts(parse("[><]>><<<+<>>"))
+            .into_iter()
+            .map(|op| (op.token_type, op.size, op.loc))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                (TokenType::OpenBracket, Some(2), 0),
+                (TokenType::CloseBracket, Some(1), 3),
+                (TokenType::ShiftLeft, Some(1), 4),
+                (TokenType::Plus, Some(1), 9),
+                (TokenType::ShiftRight, Some(1), 10),
+                (TokenType::Eof, None, 13),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_eval_const_prefix() {
+        assert_eq!(eval_const_prefix(&parse("+++>++")), (vec![3, 2], 1));
+        assert_eq!(eval_const_prefix(&parse("->>+<,+")), (vec![255, 0, 1], 1));
+        assert_eq!(eval_const_prefix(&parse("+<+")), (vec![1], 0));
+        let ops = optimize(parse(">>+<<[-]>+<-"), 3);
+        assert_eq!(eval_const_prefix(&ops), (vec![255, 1, 1], 0));
+    }
+
+    #[test]
+    fn test_coalesce_block() {
+        let ops = parse(">+>-<<");
+        let ops = coalesce_block(&ops[..ops.len() - 1]);
+        assert_eq!(
+            ops,
+            vec![
+                Token {
+                    token_type: TokenType::AddAt {
+                        offset: 1,
+                        delta: 1
+                    },
+                    size: None,
+                    loc: 1,
+                    leading_trivia: None,
+                },
+                Token {
+                    token_type: TokenType::AddAt {
+                        offset: 2,
+                        delta: -1
+                    },
+                    size: None,
+                    loc: 3,
+                    leading_trivia: None,
+                },
+            ]
+        );
+
+        let ops = parse("+>>-<+-");
+        let ops = coalesce_block(&ops[..ops.len() - 1]);
+        let types: Vec<_> = ops.iter().map(|op| (&op.token_type, op.size)).collect();
+        assert_eq!(
+            types,
+            vec![
+                (&TokenType::Plus, Some(1)),
+                (
+                    &TokenType::AddAt {
+                        offset: 2,
+                        delta: -1
+                    },
+                    None
+                ),
+                (&TokenType::ShiftRight, Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_body_is_kept() {
+        let ops = parse("[->+]");
+        assert_eq!(optimize(ops.clone(), 2), ops);
+    }
+}
@@ -1,12 +1,87 @@
 use crate::lexer;
+use crate::optimizer;
+use std::convert::Infallible;
+use std::str::FromStr;
 use thiserror::Error;
 
+/// Error type for the parser
+///
+/// Positions are indices into the parsed sequence of operations, except for the positions of
+/// lexer errors, which are byte offsets into the input string.
 #[derive(Debug, Error, PartialEq)]
 pub enum ParserError {
+    /// A closing bracket at the given position without an opening bracket
     #[error("Unmatched bracket at position {0}")]
     UnmatchedBracket(usize),
-    #[error("Unexpected end of file at position {0}, expected closing bracket at position {1}")]
-    UnexpectedEof(usize, usize),
+    /// The input ended at position `eof` while the opening bracket at position `open` was not
+    /// closed yet
+    #[error(
+        "Unexpected end of file at position {eof}, bracket at position {open} is never closed"
+    )]
+    UnexpectedEof { eof: usize, open: usize },
+    /// The lexer rejected a character
+    #[error("Lexing error: {0}")]
+    LexerError(lexer::LexerError),
+    /// The opening bracket at the given position exceeds the maximum nesting depth, reaching the
+    /// given depth
+    #[error("Nesting too deep at position {0}, reached depth {1}")]
+    NestingTooDeep(usize, usize),
+}
+
+impl ParserError {
+    /// The position the error points at
+    ///
+    /// This is the stray closing bracket for `UnmatchedBracket`, the unclosed opening bracket for
+    /// `UnexpectedEof`, the byte offset of the rejected character for `LexerError` and the opening
+    /// bracket that is too deep for `NestingTooDeep`.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::parser::Parser;
+    ///
+    /// let result = Parser::new(String::from("+[-[")).parse();
+    /// assert_eq!(result.unwrap_err().position(), 3);
+    /// ```
+    pub fn position(&self) -> usize {
+        match self {
+            ParserError::UnmatchedBracket(position) => *position,
+            ParserError::UnexpectedEof { open, .. } => *open,
+            ParserError::LexerError(lexer::LexerError::UnexpectedChar(_, position)) => *position,
+            ParserError::NestingTooDeep(position, _) => *position,
+        }
+    }
+
+    /// The line and column the error points at in the input, see `lexer::line_col`
+    ///
+    /// # Arguments
+    /// * `input` - The input that failed to parse
+    /// * `settings` - The settings of the lexer the input was parsed with, which decide the ops
+    ///   the position of the error counts
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::lexer::LexerSettings;
+    /// use rbfc::parser::Parser;
+    ///
+    /// let input = "+\n+[-[";
+    /// let error = Parser::new(input).parse().unwrap_err();
+    /// assert_eq!(error.line_col(input, LexerSettings::default()), (2, 4));
+    /// ```
+    pub fn line_col(&self, input: &str, settings: lexer::LexerSettings) -> (usize, usize) {
+        let loc = match self {
+            ParserError::LexerError(lexer::LexerError::UnexpectedChar(_, loc)) => *loc,
+            // The other positions are indices into the ops, which are the tokens of the lexer
+            _ => {
+                let mut lexer = lexer::Lexer::with_settings(input, settings);
+                let mut token = lexer.next_token();
+                for _ in 0..self.position() {
+                    token = lexer.next_token();
+                }
+                token.loc
+            }
+        };
+        lexer::line_col(input, loc)
+    }
 }
 
 /// The Parser struct is responsible for parsing a sequence of tokens
@@ -20,9 +95,10 @@ pub enum ParserError {
 /// let mut parser = Parser::new(input);
 /// let mut ops = parser.parse().unwrap();
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Parser {
     lexer: lexer::Lexer,
+    max_depth: Option<usize>,
 }
 
 impl Parser {
@@ -38,12 +114,52 @@ impl Parser {
     /// let input = String::from("+++[->+<]...,,,");
     /// let mut parser = Parser::new(input);
     /// ```
-    pub fn new(input: String) -> Parser {
+    pub fn new(input: impl Into<String>) -> Parser {
+        Parser::with_settings(input, lexer::LexerSettings::default())
+    }
+
+    /// Create a new parser from a string with the given settings for the lexer
+    ///
+    /// # Arguments
+    /// * `input` - A string to be parsed
+    /// * `settings` - The settings for the lexer
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::lexer::{LexerError, LexerSettings};
+    /// use rbfc::parser::{Parser, ParserError};
+    ///
+    /// let settings = LexerSettings { strict: true, ..Default::default() };
+    /// let mut parser = Parser::with_settings(String::from("+a+"), settings);
+    /// assert_eq!(
+    ///     parser.parse(),
+    ///     Err(ParserError::LexerError(LexerError::UnexpectedChar('a', 1)))
+    /// );
+    /// ```
+    pub fn with_settings(input: impl Into<String>, settings: lexer::LexerSettings) -> Parser {
         Parser {
-            lexer: lexer::Lexer::new(input),
+            lexer: lexer::Lexer::with_settings(input, settings),
+            max_depth: None,
         }
     }
 
+    /// Limit the nesting depth of brackets the parser accepts
+    ///
+    /// # Arguments
+    /// * `max_depth` - The maximum number of brackets that may be open at the same time
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::parser::{Parser, ParserError};
+    ///
+    /// let mut parser = Parser::new(String::from("[[-]]")).with_max_depth(1);
+    /// assert_eq!(parser.parse(), Err(ParserError::NestingTooDeep(1, 2)));
+    /// ```
+    pub fn with_max_depth(mut self, max_depth: usize) -> Parser {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
     /// Parse the input string into a sequence of operations
     ///
     /// # Example
@@ -56,8 +172,9 @@ impl Parser {
     /// ```
     ///
     /// # Errors
-    /// Returns an error if the input string contains unmatched brackets
-    /// or if the input string ends unexpectedly
+    /// Returns an error if the input string contains unmatched brackets,
+    /// if the input string ends unexpectedly, if the lexer rejects a character
+    /// or if the brackets are nested deeper than the maximum depth
     /// ```
     /// use rbfc::parser::Parser;
     /// use rbfc::parser::ParserError;
@@ -65,7 +182,7 @@ impl Parser {
     /// let input = String::from("+++[->+<");
     /// let mut parser = Parser::new(input);
     /// let result = parser.parse();
-    /// assert_eq!(result, Err(ParserError::UnexpectedEof(6, 1)));
+    /// assert_eq!(result, Err(ParserError::UnexpectedEof { eof: 6, open: 1 }));
     /// ```
     pub fn parse(&mut self) -> Result<Vec<lexer::Token>, ParserError> {
         let mut jump_stack = Vec::new();
@@ -73,7 +190,10 @@ impl Parser {
         let mut loc = 0;
 
         loop {
-            let mut token = self.lexer.next_token();
+            let mut token = self
+                .lexer
+                .try_next_token()
+                .map_err(ParserError::LexerError)?;
             match token.token_type {
                 lexer::TokenType::Eof => {
                     ops.push(token);
@@ -81,11 +201,14 @@ impl Parser {
                 }
                 lexer::TokenType::OpenBracket => {
                     jump_stack.push(loc);
+                    if self.max_depth.is_some_and(|max| jump_stack.len() > max) {
+                        return Err(ParserError::NestingTooDeep(loc, jump_stack.len()));
+                    }
                     ops.push(token);
                 }
                 lexer::TokenType::CloseBracket => {
                     let jump = jump_stack.pop().ok_or(ParserError::UnmatchedBracket(loc))?;
-                    token.size = Some(jump);
+                    token.size = Some(jump + 1);
                     ops[jump].size = Some(loc + 1);
                     ops.push(token);
                 }
@@ -96,21 +219,444 @@ impl Parser {
             loc += 1;
         }
 
-        if !jump_stack.is_empty() {
-            return Err(ParserError::UnexpectedEof(
-                loc,
-                jump_stack.pop().expect("Should be some location"),
-            ));
+        if let Some(open) = jump_stack.pop() {
+            return Err(ParserError::UnexpectedEof { eof: loc, open });
         }
 
         Ok(ops)
     }
+
+    /// Parse the input string into an iterator over the operations, without collecting them
+    ///
+    /// The input is lexed twice. The first pass only records the jump target of every bracket,
+    /// in the order the brackets appear, and reports the same errors as `parse`. The second pass
+    /// yields the operations with their jump targets filled in, ending with the `Eof`, so only
+    /// the targets are kept in memory instead of every operation.
+    ///
+    /// # Example
+    /// ```
+    /// use rbfc::lexer::TokenType;
+    /// use rbfc::parser::Parser;
+    ///
+    /// let mut parser = Parser::new("+[-]");
+    /// let ops: Vec<_> = parser.iter_resolved().unwrap().collect();
+    /// assert_eq!(ops[1].token_type, TokenType::OpenBracket);
+    /// assert_eq!(ops[1].size, Some(4));
+    /// ```
+    ///
+    /// # Errors
+    /// Returns the same errors as `parse`, before any operation is yielded
+    pub fn iter_resolved(&mut self) -> Result<ResolvedOps<'_>, ParserError> {
+        let mut lexer = self.lexer.clone();
+        let mut jump_stack = Vec::new();
+        let mut targets = Vec::new();
+        let mut loc = 0;
+
+        loop {
+            let token = lexer.try_next_token().map_err(ParserError::LexerError)?;
+            match token.token_type {
+                lexer::TokenType::Eof => break,
+                lexer::TokenType::OpenBracket => {
+                    jump_stack.push((targets.len(), loc));
+                    if self.max_depth.is_some_and(|max| jump_stack.len() > max) {
+                        return Err(ParserError::NestingTooDeep(loc, jump_stack.len()));
+                    }
+                    targets.push(0);
+                }
+                lexer::TokenType::CloseBracket => {
+                    let (index, open) =
+                        jump_stack.pop().ok_or(ParserError::UnmatchedBracket(loc))?;
+                    targets[index] = loc + 1;
+                    targets.push(open + 1);
+                }
+                _ => {}
+            }
+            loc += 1;
+        }
+
+        if let Some((_, open)) = jump_stack.pop() {
+            return Err(ParserError::UnexpectedEof { eof: loc, open });
+        }
+
+        Ok(ResolvedOps {
+            lexer: &mut self.lexer,
+            targets: targets.into_iter(),
+            done: false,
+        })
+    }
+}
+
+/// An iterator over the operations of a program with resolved jump targets, see
+/// `Parser::iter_resolved`
+#[derive(Debug)]
+pub struct ResolvedOps<'a> {
+    lexer: &'a mut lexer::Lexer,
+    targets: std::vec::IntoIter<usize>,
+    done: bool,
+}
+
+impl Iterator for ResolvedOps<'_> {
+    type Item = lexer::Token;
+
+    fn next(&mut self) -> Option<lexer::Token> {
+        if self.done {
+            return None;
+        }
+        // The first pass already lexed the same input without an error
+        let mut token = self.lexer.try_next_token().ok()?;
+        match token.token_type {
+            lexer::TokenType::Eof => self.done = true,
+            lexer::TokenType::OpenBracket | lexer::TokenType::CloseBracket => {
+                token.size = self.targets.next();
+            }
+            _ => {}
+        }
+        Some(token)
+    }
+}
+
+/// Check that the code is a well-formed program without keeping the parsed operations
+///
+/// This reports the same errors as `Parser::parse`, but only keeps track of the open brackets
+/// instead of collecting every operation.
+///
+/// # Arguments
+/// * `code` - The code to be validated
+///
+/// # Example
+/// ```
+/// use rbfc::parser::{validate, ParserError};
+///
+/// assert_eq!(validate("+[-]"), Ok(()));
+/// assert_eq!(validate("+[-"), Err(ParserError::UnexpectedEof { eof: 3, open: 1 }));
+/// ```
+///
+/// # Errors
+/// Returns an error if the code contains unmatched brackets or ends unexpectedly
+pub fn validate(code: &str) -> Result<(), ParserError> {
+    let mut lexer = lexer::Lexer::new(code.to_string());
+    let mut jump_stack = Vec::new();
+    let mut loc = 0;
+
+    loop {
+        match lexer.next_token().token_type {
+            lexer::TokenType::Eof => break,
+            lexer::TokenType::OpenBracket => jump_stack.push(loc),
+            lexer::TokenType::CloseBracket => {
+                jump_stack.pop().ok_or(ParserError::UnmatchedBracket(loc))?;
+            }
+            _ => {}
+        }
+        loc += 1;
+    }
+
+    match jump_stack.pop() {
+        Some(open) => Err(ParserError::UnexpectedEof { eof: loc, open }),
+        None => Ok(()),
+    }
+}
+
+/// Check that the code is a well-formed program, reporting every bracket error
+///
+/// Unlike `validate` this does not stop at the first error. Every closing bracket without an
+/// opening bracket is reported in order, followed by every opening bracket that is never closed.
+///
+/// # Arguments
+/// * `code` - The code to be checked
+///
+/// # Example
+/// ```
+/// use rbfc::parser::{check_all, ParserError};
+///
+/// assert_eq!(check_all("+[-]"), vec![]);
+/// assert_eq!(
+///     check_all("]+]"),
+///     vec![ParserError::UnmatchedBracket(0), ParserError::UnmatchedBracket(2)]
+/// );
+/// ```
+pub fn check_all(code: &str) -> Vec<ParserError> {
+    let mut lexer = lexer::Lexer::new(code);
+    let mut jump_stack = Vec::new();
+    let mut errors = Vec::new();
+    let mut loc = 0;
+
+    loop {
+        match lexer.next_token().token_type {
+            lexer::TokenType::Eof => break,
+            lexer::TokenType::OpenBracket => jump_stack.push(loc),
+            lexer::TokenType::CloseBracket => match jump_stack.pop() {
+                Some(_) => {}
+                None => errors.push(ParserError::UnmatchedBracket(loc)),
+            },
+            _ => {}
+        }
+        loc += 1;
+    }
+
+    errors.extend(
+        jump_stack
+            .into_iter()
+            .map(|open| ParserError::UnexpectedEof { eof: loc, open }),
+    );
+    errors
+}
+
+/// Get the locations of all matching brackets in parsed operations
+///
+/// The pairs are taken from the jump targets the parser computed and are ordered by the location
+/// of the opening bracket.
+///
+/// # Arguments
+/// * `ops` - The operations as returned by `Parser::parse`
+///
+/// # Example
+/// ```
+/// use rbfc::parser::{bracket_pairs, Parser};
+///
+/// let ops = Parser::new(String::from("+[-]")).parse().unwrap();
+/// assert_eq!(bracket_pairs(&ops), vec![(1, 3)]);
+/// ```
+pub fn bracket_pairs(ops: &[lexer::Token]) -> Vec<(usize, usize)> {
+    ops.iter()
+        .filter(|op| op.token_type == lexer::TokenType::OpenBracket)
+        .filter_map(|op| {
+            let close = ops.get(op.size? - 1)?;
+            Some((op.loc, close.loc))
+        })
+        .collect()
+}
+
+/// An overview of a program, see `stats`
+///
+/// # Fields
+/// * `ops` - The number of ops the program is parsed into, where a run of the same command is a
+///   single op
+/// * `loops` - The number of loops
+/// * `max_depth` - The number of loops the most deeply nested op is in
+/// * `commands` - The number of command characters in the code
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramStats {
+    pub ops: usize,
+    pub loops: usize,
+    pub max_depth: usize,
+    pub commands: usize,
+}
+
+/// Get an overview of a program without running it
+///
+/// # Arguments
+/// * `code` - The code to get the overview of
+///
+/// # Example
+/// ```
+/// use rbfc::parser::{stats, ProgramStats};
+///
+/// let stats = stats("+++[->[-]<]").unwrap();
+/// assert_eq!(
+///     stats,
+///     ProgramStats { ops: 9, loops: 2, max_depth: 2, commands: 11 }
+/// );
+/// ```
+///
+/// # Errors
+/// Returns an error if the code contains unmatched brackets or ends unexpectedly
+pub fn stats(code: &str) -> Result<ProgramStats, ParserError> {
+    let ops = Parser::new(code).parse()?;
+    let mut stats = ProgramStats::default();
+    let mut depth = 0;
+
+    for op in &ops {
+        match op.token_type {
+            lexer::TokenType::Eof => continue,
+            lexer::TokenType::OpenBracket => {
+                stats.loops += 1;
+                depth += 1;
+                stats.max_depth = stats.max_depth.max(depth);
+            }
+            lexer::TokenType::CloseBracket => depth -= 1,
+            _ => {}
+        }
+        stats.ops += 1;
+    }
+    // The size of a bracket is its jump target, so the commands are counted from the source
+    stats.commands = lexer::to_source(&ops).chars().count();
+    Ok(stats)
+}
+
+/// Strip everything but the operators from the code
+///
+/// Unlike `minify` the operators are kept as they are, so the result behaves exactly like the
+/// code, including programs that fail on the tape.
+///
+/// # Arguments
+/// * `code` - The code to be stripped
+///
+/// # Example
+/// ```
+/// use rbfc::parser::strip_comments;
+///
+/// assert_eq!(strip_comments("+ + + [ - ] a comment, really"), "+++[-],");
+/// ```
+pub fn strip_comments(code: &str) -> String {
+    code.chars()
+        .filter(|c| lexer::Token::is_token(c).is_some())
+        .collect()
+}
+
+/// Minify the code into the shortest equivalent source
+///
+/// All characters that are not operators are stripped and runs of `+` and `-` are folded into
+/// their net change, see `optimizer::fold_arith`, so `+-` is removed. Moves like `><` are kept,
+/// as moving off the tape fails even if the move is undone right away.
+///
+/// # Arguments
+/// * `code` - The code to be minified
+///
+/// # Example
+/// ```
+/// use rbfc::parser::minify;
+///
+/// assert_eq!(minify("+ + + [ - ] a comment"), "+++[-]");
+/// assert_eq!(minify("++>-<+-<>"), "++>-<<>");
+/// ```
+pub fn minify(code: &str) -> String {
+    let mut lexer = lexer::Lexer::new(code.to_string());
+    let mut ops = Vec::new();
+    loop {
+        let token = lexer.next_token();
+        if token.token_type == lexer::TokenType::Eof {
+            break;
+        }
+        ops.push(token);
+    }
+
+    optimizer::fold_arith(ops)
+        .iter()
+        .filter_map(|op| {
+            let c = match op.token_type {
+                lexer::TokenType::ShiftLeft => '<',
+                lexer::TokenType::ShiftRight => '>',
+                lexer::TokenType::Plus => '+',
+                lexer::TokenType::Minus => '-',
+                lexer::TokenType::Dot => '.',
+                lexer::TokenType::Comma => ',',
+                lexer::TokenType::OpenBracket => '[',
+                lexer::TokenType::CloseBracket => ']',
+                // The lexer emits no other ops without debug or halt commands
+                _ => return None,
+            };
+            Some(c.to_string().repeat(op.size.unwrap_or(1)))
+        })
+        .collect()
+}
+
+/// Print the operations one per line, indenting the ops inside of loops
+///
+/// Every line shows the type of the op, its size if it has one and its location. The body of a
+/// loop is indented by four spaces more than its brackets, which makes the output of the
+/// optimizer easier to read than printing the ops with `{:?}`.
+///
+/// # Arguments
+/// * `ops` - The operations to be printed
+///
+/// # Example
+/// ```
+/// use rbfc::parser::{pretty, Parser};
+///
+/// let ops = Parser::new("+[-]").parse().unwrap();
+/// let lines: Vec<_> = pretty(&ops).lines().map(String::from).collect();
+/// assert_eq!(lines[1], "OpenBracket size=4 loc=1");
+/// assert_eq!(lines[2], "    Minus size=1 loc=2");
+/// ```
+pub fn pretty(ops: &[lexer::Token]) -> String {
+    let mut output = String::new();
+    let mut depth: usize = 0;
+    for op in ops {
+        if op.token_type == lexer::TokenType::CloseBracket {
+            depth = depth.saturating_sub(1);
+        }
+        output.push_str(&"    ".repeat(depth));
+        output.push_str(&format!("{:?}", op.token_type));
+        if let Some(size) = op.size {
+            output.push_str(&format!(" size={size}"));
+        }
+        output.push_str(&format!(" loc={}\n", op.loc));
+        if op.token_type == lexer::TokenType::OpenBracket {
+            depth += 1;
+        }
+    }
+    output
+}
+
+impl From<&str> for Parser {
+    fn from(input: &str) -> Self {
+        Parser::new(input)
+    }
+}
+
+/// Create a parser with the default settings, which never fails
+///
+/// # Example
+/// ```
+/// use rbfc::parser::Parser;
+/// use std::str::FromStr;
+///
+/// let ops = Parser::from_str("+[-]").unwrap().parse().unwrap();
+/// assert_eq!(ops.len(), 5);
+/// ```
+impl FromStr for Parser {
+    type Err = Infallible;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Ok(Parser::new(input))
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_jump_target() {
+        let ops = Parser::new("[+]").parse().unwrap();
+        assert_eq!(ops[0].jump_target(), Some(3));
+        assert_eq!(ops[2].jump_target(), Some(1));
+        assert_eq!(ops[1].jump_target(), None);
+        assert_eq!(ops[ops[0].jump_target().unwrap() - 1], ops[2]);
+    }
+
+    #[test]
+    fn test_stats() {
+        let overview = stats("++[>+<-]").unwrap();
+        assert_eq!(overview.ops, 7);
+        assert_eq!(overview.loops, 1);
+        assert_eq!(overview.max_depth, 1);
+        assert_eq!(overview.commands, 8);
+        assert_eq!(stats("+]"), Err(ParserError::UnmatchedBracket(1)));
+    }
+
+    #[test]
+    fn test_check_all() {
+        assert_eq!(
+            check_all("][+["),
+            vec![
+                ParserError::UnmatchedBracket(0),
+                ParserError::UnexpectedEof { eof: 4, open: 1 },
+                ParserError::UnexpectedEof { eof: 4, open: 3 },
+            ]
+        );
+        assert_eq!(check_all("[[]]"), vec![]);
+    }
+
+    #[test]
+    fn test_from_str() {
+        let ops = Parser::new("+[-]").parse().unwrap();
+        assert_eq!(Parser::from("+[-]").parse().unwrap(), ops);
+        assert_eq!("+[-]".parse::<Parser>().unwrap().parse().unwrap(), ops);
+        assert_eq!(crate::parse("+[-]").unwrap(), ops);
+        assert_eq!(crate::parse("+]"), Err(ParserError::UnmatchedBracket(1)));
+    }
+
     #[test]
     fn test_parser() {
         let input = String::from("++[->+<]");
@@ -122,42 +668,50 @@ mod test {
                 lexer::Token {
                     token_type: lexer::TokenType::Plus,
                     size: Some(2),
-                    loc: 0
+                    loc: 0,
+                    leading_trivia: None,
                 },
                 lexer::Token {
                     token_type: lexer::TokenType::OpenBracket,
                     size: Some(7),
-                    loc: 2
+                    loc: 2,
+                    leading_trivia: None,
                 },
                 lexer::Token {
                     token_type: lexer::TokenType::Minus,
                     size: Some(1),
-                    loc: 3
+                    loc: 3,
+                    leading_trivia: None,
                 },
                 lexer::Token {
                     token_type: lexer::TokenType::ShiftRight,
                     size: Some(1),
-                    loc: 4
+                    loc: 4,
+                    leading_trivia: None,
                 },
                 lexer::Token {
                     token_type: lexer::TokenType::Plus,
                     size: Some(1),
-                    loc: 5
+                    loc: 5,
+                    leading_trivia: None,
                 },
                 lexer::Token {
                     token_type: lexer::TokenType::ShiftLeft,
                     size: Some(1),
-                    loc: 6
+                    loc: 6,
+                    leading_trivia: None,
                 },
                 lexer::Token {
                     token_type: lexer::TokenType::CloseBracket,
-                    size: Some(1),
-                    loc: 7
+                    size: Some(2),
+                    loc: 7,
+                    leading_trivia: None,
                 },
                 lexer::Token {
                     token_type: lexer::TokenType::Eof,
                     size: None,
-                    loc: 8
+                    loc: 8,
+                    leading_trivia: None,
                 }
             ]
         );
@@ -168,7 +722,7 @@ mod test {
         let input = String::from("++[->+<");
         let mut parser = Parser::new(input);
         let result = parser.parse();
-        assert_eq!(result, Err(ParserError::UnexpectedEof(6, 1)));
+        assert_eq!(result, Err(ParserError::UnexpectedEof { eof: 6, open: 1 }));
     }
 
     #[test]
@@ -178,4 +732,155 @@ mod test {
         let result = parser.parse();
         assert_eq!(result, Err(ParserError::UnmatchedBracket(5)));
     }
+
+    #[test]
+    fn test_parser_strict() {
+        let settings = lexer::LexerSettings {
+            strict: true,
+            ..Default::default()
+        };
+        let mut parser = Parser::with_settings(String::from("+a+"), settings);
+        assert_eq!(
+            parser.parse(),
+            Err(ParserError::LexerError(lexer::LexerError::UnexpectedChar(
+                'a', 1
+            )))
+        );
+
+        let mut parser = Parser::new(String::from("+a+"));
+        assert_eq!(parser.parse().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_parser_max_depth() {
+        let input = String::from("+[[[[[-]]]]]");
+        let mut parser = Parser::new(input).with_max_depth(3);
+        assert_eq!(parser.parse(), Err(ParserError::NestingTooDeep(4, 4)));
+
+        let input = String::from("+[[[[[-]]]]]");
+        let mut parser = Parser::new(input).with_max_depth(5);
+        assert!(parser.parse().is_ok());
+    }
+
+    #[test]
+    fn test_parser_error_position() {
+        let result = Parser::new(String::from("[+[-]")).parse();
+        assert_eq!(result, Err(ParserError::UnexpectedEof { eof: 5, open: 0 }));
+        assert_eq!(result.unwrap_err().position(), 0);
+
+        let result = Parser::new(String::from("+[-]]")).parse();
+        assert_eq!(result, Err(ParserError::UnmatchedBracket(4)));
+        assert_eq!(result.unwrap_err().position(), 4);
+    }
+
+    #[test]
+    fn test_parser_error_line_col() {
+        let input = "+++\r\n[-]\r\n  ]";
+        let error = Parser::new(input).parse().unwrap_err();
+        assert_eq!(error, ParserError::UnmatchedBracket(4));
+        assert_eq!(error.line_col(input, Default::default()), (3, 3));
+
+        let settings = lexer::LexerSettings {
+            strict: true,
+            ..Default::default()
+        };
+        let input = "+\né a";
+        let error = Parser::with_settings(input, settings.clone())
+            .parse()
+            .unwrap_err();
+        assert_eq!(error.line_col(input, settings), (2, 1));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate("+[-]"), Ok(()));
+        assert_eq!(
+            validate("+[-"),
+            Err(ParserError::UnexpectedEof { eof: 3, open: 1 })
+        );
+        assert_eq!(
+            validate("++->+<]"),
+            Parser::new(String::from("++->+<]")).parse().map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_pretty() {
+        let ops = Parser::new("+[>+<-]").parse().unwrap();
+        assert_eq!(
+            pretty(&ops),
+            indoc::indoc! {"
+                Plus size=1 loc=0
+                OpenBracket size=7 loc=1
+                    ShiftRight size=1 loc=2
+                    Plus size=1 loc=3
+                    ShiftLeft size=1 loc=4
+                    Minus size=1 loc=5
+                CloseBracket size=2 loc=6
+                Eof loc=7
+            "}
+        );
+
+        let ops = Parser::new("[[-]]").parse().unwrap();
+        assert!(pretty(&ops).contains("\n        Minus size=1 loc=2\n"));
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let code = indoc::indoc! {"
+            Print the letter A
+
+            ++++++++ set the counter to eight
+            [ loop eight times
+                >++++++++ add eight to the next cell
+                <- count down
+            ]
+            >+ one more for sixty five
+            . print it
+        "};
+        let stripped = strip_comments(code);
+        assert_eq!(stripped, "++++++++[>++++++++<-]>+.");
+        assert!(stripped.chars().all(|c| "<>+-.,[]".contains(c)));
+        assert_eq!(strip_comments("+-><"), "+-><");
+    }
+
+    #[test]
+    fn test_minify() {
+        assert_eq!(minify("+ + + [ - ]  "), "+++[-]");
+        assert_eq!(minify("+-"), "");
+        assert_eq!(minify("+>-+<-[.,]"), "+><-[.,]");
+        // Moves are kept, `<>` fails on the first cell of a tape that doesn't wrap
+        assert_eq!(minify("<>+>+<>-<."), "<>+>+<>-<.");
+        assert_eq!(minify("[][]"), "[][]");
+    }
+
+    #[test]
+    fn test_bracket_pairs() {
+        let ops = Parser::new(String::from("[[]]")).parse().unwrap();
+        assert_eq!(bracket_pairs(&ops), vec![(0, 3), (1, 2)]);
+
+        let ops = Parser::new(String::from("+ [>[-] <]")).parse().unwrap();
+        assert_eq!(bracket_pairs(&ops), vec![(2, 9), (4, 6)]);
+    }
+
+    #[test]
+    fn test_iter_resolved() {
+        for code in ["[+]", "+[>[-]<[.]]-", ""] {
+            let streamed: Vec<_> = Parser::new(code).iter_resolved().unwrap().collect();
+            assert_eq!(streamed, Parser::new(code).parse().unwrap());
+        }
+
+        assert_eq!(
+            Parser::new("+]").iter_resolved().err(),
+            Some(ParserError::UnmatchedBracket(1))
+        );
+        assert_eq!(
+            Parser::new("[[").iter_resolved().err(),
+            Some(ParserError::UnexpectedEof { eof: 2, open: 1 })
+        );
+        assert_eq!(
+            Parser::new("[[]]").with_max_depth(1).iter_resolved().err(),
+            Some(ParserError::NestingTooDeep(1, 2))
+        );
+    }
 }
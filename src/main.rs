@@ -1,9 +1,18 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use rbfc::{
-    compiler::{Compiler, CompilerError, CompilerSettings},
-    interpreter::{Interpreter, InterpreterError, InterpreterSettings},
+    analyze,
+    compiler::{Compiler, CompilerError, CompilerSettings, Target},
+    interpreter::{
+        CellWidth, EofPolicy, Interpreter, InterpreterError, InterpreterSettings, OutputEncoding,
+    },
+    lexer::{LexerSettings, Token},
+    memory::TapeConfig,
+    parser::{self, ParserError},
 };
-use std::path::PathBuf;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
 use thiserror::Error;
 extern crate log;
 extern crate pretty_env_logger;
@@ -12,11 +21,16 @@ extern crate rbfc;
 
 /// The arguments for the program
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    /// The file to interpret
-    file_path: PathBuf,
+    #[command(subcommand)]
+    command: Option<Commands>,
 
-    /// The output folder
+    /// The file to interpret, or - to read the program from stdin
+    #[arg(required_unless_present = "repl")]
+    file_path: Option<PathBuf>,
+
+    /// The output folder, or - to write the compiled code to stdout
     #[arg(short, long)]
     output: Option<String>,
 
@@ -24,9 +38,199 @@ struct Args {
     #[arg(short, long)]
     interpret: bool,
 
+    /// Whether to print the time the interpreter took and the ops it executed per second to
+    /// stderr
+    #[arg(long)]
+    time: bool,
+
     /// Whether to wrap the tape
     #[arg(short, long)]
     wrap: bool,
+
+    /// Whether to wrap the tape only when moving left of the first cell
+    #[arg(long)]
+    wrap_left: bool,
+
+    /// Whether to wrap the tape only when moving right of the last cell
+    #[arg(long)]
+    wrap_right: bool,
+
+    /// The optimization level for the compiler
+    #[arg(short = 'O', long, default_value_t = 0)]
+    opt_level: u8,
+
+    /// The target to compile to (fasm, llvm-ir, pe-win64, arm64, gas-intel)
+    #[arg(short, long, default_value = "fasm")]
+    target: Target,
+
+    /// The number of cells on the tape
+    #[arg(long, default_value_t = 30000, value_parser = parse_tape_size)]
+    tape_size: usize,
+
+    /// The width of a cell in bits (8, 16, 32)
+    #[arg(long, default_value = "8")]
+    cell_width: CellWidth,
+
+    /// How the output bytes are turned into characters when interpreting (latin1, utf8, cp437)
+    #[arg(long, default_value = "latin1")]
+    encoding: OutputEncoding,
+
+    /// What `,` does at the end of the input when interpreting (error, unchanged, or a value
+    /// from 0 to 255 the cell is set to)
+    #[arg(long, default_value = "error")]
+    eof_policy: EofPolicy,
+
+    /// Whether to compare the output of the interpreter with the output of the compiled program,
+    /// both reading the same input from stdin
+    #[arg(long)]
+    verify: bool,
+
+    /// Whether `#` dumps the tape when interpreting, it is ignored otherwise
+    #[arg(long)]
+    debug_commands: bool,
+
+    /// Whether `!` ends the program, it is ignored otherwise
+    #[arg(long)]
+    halt_command: bool,
+
+    /// The value of the current cell at which `[` skips a loop and `]` ends it
+    #[arg(long, default_value_t = 0)]
+    loop_sentinel: u8,
+
+    /// Whether every byte of input is written to the output as well when interpreting
+    #[arg(long)]
+    echo_input: bool,
+
+    /// Whether the output is buffered instead of written byte by byte
+    #[arg(long)]
+    buffer_output: bool,
+
+    /// Flush the output of the interpreter after every N bytes, to show the progress of programs
+    /// that write their output over a long time
+    #[arg(long, value_name = "N")]
+    flush_every: Option<usize>,
+
+    /// Whether to compile to a function `bf_main` taking the tape, to link into other programs
+    #[arg(long)]
+    emit_as_function: bool,
+
+    /// The name of the entry point of the compiled executable (fasm and pe-win64 targets)
+    #[arg(long, default_value = "main")]
+    entry_symbol: String,
+
+    /// The register the data pointer is kept in (fasm and pe-win64 targets), one of rbx, rbp,
+    /// r12, r13, r14 and r15
+    #[arg(long, default_value = "r12")]
+    ptr_register: String,
+
+    /// Whether the compiled executable exits with status 3 when the data pointer leaves the tape
+    /// in a direction that doesn't wrap
+    #[arg(long)]
+    bounds_check: bool,
+
+    /// Whether the compiled executable exits with the value of the first cell as its status
+    #[arg(long)]
+    exit_from_cell: bool,
+
+    /// Whether to emit every top-level loop as a subroutine, so profilers can attribute time to it
+    #[arg(long)]
+    functions_per_loop: bool,
+
+    /// Whether to write a map from the lines of the compiled code to the source next to it, with
+    /// the extension `.map`
+    #[arg(long)]
+    source_map: bool,
+
+    /// Whether to start a REPL, which interprets every line read from stdin on the same tape. As
+    /// stdin holds the lines, `,` reads no input
+    #[arg(long)]
+    repl: bool,
+
+    /// Whether to print the ops left after the optimizer passes of the optimization level instead
+    /// of compiling them, one per line
+    #[arg(long)]
+    dump_ir: bool,
+}
+
+/// The subcommands of the program, which replace compiling or interpreting a file
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Strip the comments from a program, keeping only the operators
+    Strip {
+        /// The file to strip, or - to read the program from stdin
+        file_path: PathBuf,
+
+        /// The file to write the stripped program to, stdout if not given
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Whether to also fold runs of `+` and `-` into their net change, see `parser::minify`
+        #[arg(long)]
+        minify: bool,
+    },
+}
+
+impl Args {
+    /// The tape given by the arguments, which both settings are built from
+    fn tape_config(&self) -> TapeConfig {
+        TapeConfig::default()
+            .wrap_left(self.wrap || self.wrap_left)
+            .wrap_right(self.wrap || self.wrap_right)
+            .tape_size(self.tape_size)
+            .cell_width(self.cell_width)
+    }
+
+    /// The settings for the lexer given by the arguments, which both settings parse with
+    fn lexer_settings(&self) -> LexerSettings {
+        LexerSettings {
+            debug_commands: self.debug_commands,
+            halt_command: self.halt_command,
+            ..Default::default()
+        }
+    }
+
+    /// The settings for the interpreter given by the arguments
+    fn interpreter_settings(&self) -> InterpreterSettings {
+        InterpreterSettings {
+            debug_commands: self.debug_commands,
+            halt_command: self.halt_command,
+            buffer_output: self.buffer_output,
+            flush_every: self.flush_every,
+            echo_input: self.echo_input,
+            encoding: self.encoding,
+            eof_policy: self.eof_policy,
+            loop_sentinel: self.loop_sentinel,
+            ..InterpreterSettings::from(&self.tape_config())
+        }
+    }
+
+    /// The settings for the compiler given by the arguments
+    fn compiler_settings(&self) -> CompilerSettings {
+        CompilerSettings {
+            opt_level: self.opt_level,
+            target: self.target,
+            debug_commands: self.debug_commands,
+            halt_command: self.halt_command,
+            buffer_output: self.buffer_output,
+            emit_as_function: self.emit_as_function,
+            entry_symbol: self.entry_symbol.clone(),
+            ptr_register: self.ptr_register.clone(),
+            bounds_check: self.bounds_check,
+            loop_sentinel: self.loop_sentinel,
+            exit_from_cell: self.exit_from_cell,
+            functions_per_loop: self.functions_per_loop,
+            ..CompilerSettings::from(&self.tape_config())
+        }
+    }
+}
+
+/// Parse the tape size, which has to hold at least one cell
+fn parse_tape_size(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("The tape needs at least one cell".to_string()),
+        Ok(size) => Ok(size),
+        Err(e) => Err(e.to_string()),
+    }
 }
 
 /// The error type for the program
@@ -40,51 +244,770 @@ enum RBFCError {
     Compiler(CompilerError),
     #[error("Error writing file: {0}")]
     WritingFile(String),
+    #[error("Error assembling or running the compiled program: {0}")]
+    Assembler(String),
+    #[error("Verification failed: {0}")]
+    Verify(String),
+    #[error("Error while parsing at line {line}, column {column}: {error}")]
+    Parsing {
+        error: ParserError,
+        line: usize,
+        column: usize,
+    },
 }
 
-fn main() -> Result<(), RBFCError> {
+impl RBFCError {
+    /// The status the process exits with for the error
+    ///
+    /// * `2` - The program couldn't be parsed
+    /// * `3` - The program failed on the tape, by moving off it or by overflowing a cell
+    /// * `4` - Reading or writing a file, the input or the output failed
+    /// * `5` - The compiled program couldn't be assembled or run
+    /// * `1` - Any other error, like exceeding the step limit or a failed verification
+    fn exit_code(&self) -> i32 {
+        match self {
+            RBFCError::ReadingFile(_) | RBFCError::WritingFile(_) => 4,
+            RBFCError::Interpreter(error) => match error {
+                InterpreterError::ParserError(_) => 2,
+                InterpreterError::TapeOverflow { .. }
+                | InterpreterError::TapeUnderflow { .. }
+                | InterpreterError::CellOverflow(_)
+                | InterpreterError::UninitRead(_)
+                | InterpreterError::InvalidInitialDp { .. } => 3,
+                InterpreterError::InputError | InterpreterError::OutputError => 4,
+                InterpreterError::UnexpectedNoneSize(_)
+                | InterpreterError::StepLimitExceeded(_)
+                | InterpreterError::Timeout(_)
+                | InterpreterError::Cancelled
+                | InterpreterError::InvalidRegion { .. } => 1,
+                #[cfg(feature = "jit")]
+                InterpreterError::JitError(_) => 1,
+            },
+            RBFCError::Compiler(error) => match error {
+                CompilerError::ParsingError(_) | CompilerError::UnexpectedEof => 2,
+                CompilerError::WriteError(_) => 4,
+                CompilerError::UnexpectedNoneSize(_)
+                | CompilerError::InvalidRegister(_)
+                | CompilerError::EmptyTape
+                | CompilerError::UnsupportedMachineCode(_) => 1,
+            },
+            RBFCError::Parsing { .. } => 2,
+            RBFCError::Assembler(_) => 5,
+            RBFCError::Verify(_) => 1,
+        }
+    }
+
+    /// Point a parsing error of the interpreter or the compiler at its line and column in the
+    /// code, other errors are kept as they are
+    fn locate(self, code: &str, settings: LexerSettings) -> RBFCError {
+        match self {
+            RBFCError::Interpreter(InterpreterError::ParserError(error))
+            | RBFCError::Compiler(CompilerError::ParsingError(error)) => {
+                let (line, column) = error.line_col(code, settings);
+                RBFCError::Parsing {
+                    error,
+                    line,
+                    column,
+                }
+            }
+            error => error,
+        }
+    }
+}
+
+fn main() {
     pretty_env_logger::init();
 
+    if let Err(e) = run() {
+        eprintln!("{e}");
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Run the program with the arguments it was called with
+fn run() -> Result<(), RBFCError> {
     let args = Args::parse();
-    let file_name = args
+
+    if let Some(Commands::Strip {
+        file_path,
+        output,
+        minify,
+    }) = &args.command
+    {
+        let (_, code) = read_code(file_path, std::io::stdin())?;
+        let stripped = if *minify {
+            parser::minify(&code)
+        } else {
+            parser::strip_comments(&code)
+        };
+        return match output {
+            Some(file) => std::fs::write(file, stripped + "\n")
+                .or(Err(RBFCError::WritingFile(file.display().to_string()))),
+            None => writeln!(std::io::stdout(), "{stripped}")
+                .or(Err(RBFCError::WritingFile("stdout".to_string()))),
+        };
+    }
+
+    if args.repl {
+        return repl(
+            std::io::stdin().lock(),
+            &mut std::io::stdout(),
+            Box::new(std::io::stdout()),
+            args.interpreter_settings(),
+        );
+    }
+
+    let file_path = args
         .file_path
-        .file_name()
-        .ok_or(RBFCError::ReadingFile("Couldn't get filename".to_string()))?
-        .to_os_string()
-        .into_string()
-        .or(Err(RBFCError::ReadingFile(
-            "Couldn't get filename".to_string(),
-        )))?;
+        .as_deref()
+        .ok_or(RBFCError::ReadingFile("No file given".to_string()))?;
+    let (file_name, code) = read_code(file_path, std::io::stdin())?;
+    run_code(&args, &file_name, code.clone())
+        .map_err(|error| error.locate(&code, args.lexer_settings()))
+}
 
-    let code = std::fs::read_to_string(args.file_path)
-        .or(Err(RBFCError::ReadingFile(file_name.clone())))?;
+/// Run the code read from the file with the given name as the arguments say
+fn run_code(args: &Args, file_name: &str, code: String) -> Result<(), RBFCError> {
+    if args.verify {
+        let mut input = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut input)
+            .or(Err(RBFCError::ReadingFile("stdin".to_string())))?;
+        return verify(&code, args, &input);
+    }
 
+    if args.dump_ir {
+        return dump_ir(code, args.compiler_settings(), &mut std::io::stdout());
+    }
+
+    let stdin_is_terminal = std::io::stdin().is_terminal();
     if args.interpret {
-        let settings = InterpreterSettings { wrap: args.wrap };
+        let ops = parser::Parser::new(&code).parse().unwrap_or_default();
+        if let Some(warning) = input_warning(&ops, true, stdin_is_terminal) {
+            eprintln!("{warning}");
+        }
+        let settings = args.interpreter_settings();
         let mut interpreter = match Interpreter::new(code, settings) {
             Ok(i) => i,
             Err(e) => return Err(RBFCError::Interpreter(e)),
         };
 
-        match interpreter.interpret() {
+        let start = Instant::now();
+        let result = interpreter.interpret();
+        if args.time {
+            eprintln!(
+                "{}",
+                time_report(interpreter.executed_ops(), start.elapsed())
+            );
+        }
+        match result {
             Ok(()) => return Ok(()),
             Err(e) => return Err(RBFCError::Interpreter(e)),
         }
     } else {
-        let settings = CompilerSettings { wrap: args.wrap };
+        let settings = args.compiler_settings();
         let compiler = match Compiler::new(code, settings) {
             Ok(c) => c,
             Err(e) => return Err(RBFCError::Compiler(e)),
         };
+        if let Some(warning) = input_warning(&compiler.optimized_ops(), false, stdin_is_terminal) {
+            eprintln!("{warning}");
+        }
 
-        let asm = compiler.compile_code();
-
-        let file = if let Some(output) = args.output {
-            format!("{}/{}", output, file_name.replace(".bf", ".asm"))
-        } else {
-            file_name.replace(".bf", ".asm").to_string()
-        };
-        std::fs::write(file.clone(), asm).or(Err(RBFCError::WritingFile(file)))?;
+        write_compiled(
+            &compiler,
+            output_path(file_name, args.output.as_deref(), args.target),
+            args.source_map,
+            &mut std::io::stdout(),
+        )?;
     }
     Ok(())
 }
+
+/// Write the compiled code to the file, together with its source map if requested, or to the
+/// output if there is no file, see `output_path`
+fn write_compiled(
+    compiler: &Compiler,
+    file: Option<String>,
+    source_map: bool,
+    output: &mut impl Write,
+) -> Result<(), RBFCError> {
+    match file {
+        Some(file) if source_map => {
+            let (asm, source_map) = compiler
+                .compile_with_sourcemap()
+                .map_err(RBFCError::Compiler)?;
+            let map_file = format!("{file}.map");
+            std::fs::write(&map_file, source_map.to_string())
+                .or(Err(RBFCError::WritingFile(map_file)))?;
+            std::fs::write(file.clone(), asm).or(Err(RBFCError::WritingFile(file)))
+        }
+        Some(file) => compiler
+            .compile_to_file(Path::new(&file))
+            .map_err(RBFCError::Compiler),
+        None => {
+            let asm = compiler.compile_code().map_err(RBFCError::Compiler)?;
+            output
+                .write_all(asm.as_bytes())
+                .or(Err(RBFCError::WritingFile("stdout".to_string())))
+        }
+    }
+}
+
+/// Print the ops the compiler emits code for, see `parser::pretty`
+fn dump_ir(
+    code: String,
+    settings: CompilerSettings,
+    output: &mut impl Write,
+) -> Result<(), RBFCError> {
+    let compiler = Compiler::new(code, settings).map_err(RBFCError::Compiler)?;
+    output
+        .write_all(parser::pretty(&compiler.optimized_ops()).as_bytes())
+        .or(Err(RBFCError::WritingFile("stdout".to_string())))
+}
+
+/// Interpret every line on the same tape and print the tape after it
+///
+/// Errors are printed without ending the loop, which ends with the input. A line containing only
+/// `:reset` clears the tape. The programs can't read input, as stdin is used for the lines, so a
+/// line containing `,` is warned about.
+///
+/// # Arguments
+/// * `lines` - Where the lines of code are read from
+/// * `report` - Where the prompt, the tape and the errors are written to
+/// * `output` - Where the output of the programs is written to
+/// * `settings` - The settings for the interpreter
+fn repl(
+    lines: impl BufRead,
+    report: &mut impl Write,
+    output: Box<dyn Write>,
+    settings: InterpreterSettings,
+) -> Result<(), RBFCError> {
+    let write_error = |_| RBFCError::WritingFile("stdout".to_string());
+    let mut interpreter =
+        Interpreter::new(String::new(), settings).map_err(RBFCError::Interpreter)?;
+    interpreter.set_output(output);
+    interpreter.set_input(Box::new(std::io::empty()));
+
+    write!(report, "> ").map_err(write_error)?;
+    report.flush().map_err(write_error)?;
+    for line in lines.lines() {
+        let line = line.or(Err(RBFCError::ReadingFile("stdin".to_string())))?;
+        if line.contains(',') {
+            writeln!(
+                report,
+                "Warning: `,` reads no input in the REPL, stdin holds the lines"
+            )
+            .map_err(write_error)?;
+        }
+        if line.trim() == ":reset" {
+            interpreter.reset();
+        } else if let Err(e) = interpreter
+            .load(line)
+            .and_then(|()| interpreter.interpret())
+        {
+            writeln!(report, "Error: {e}").map_err(write_error)?;
+        }
+        writeln!(report, "{}", interpreter.render_tape(16)).map_err(write_error)?;
+        write!(report, "> ").map_err(write_error)?;
+        report.flush().map_err(write_error)?;
+    }
+    writeln!(report).map_err(write_error)
+}
+
+/// Run the program with the interpreter and as compiled program and compare their outputs
+///
+/// Both get the same input. Only the fasm, LLVM IR and gas-intel targets can be verified, which
+/// need `fasm`, `lli` or `as` and `ld` to be installed.
+fn verify(code: &str, args: &Args, input: &[u8]) -> Result<(), RBFCError> {
+    if args.emit_as_function {
+        return Err(RBFCError::Assembler(
+            "Programs compiled to a function can't be run to verify them".to_string(),
+        ));
+    }
+    let interpreted = interpret(code, args.interpreter_settings(), input)?;
+    // Only the output is compared, a status taken from the tape would fail the run
+    let settings = CompilerSettings {
+        exit_from_cell: false,
+        ..args.compiler_settings()
+    };
+    let compiler = Compiler::new(code.to_string(), settings).map_err(RBFCError::Compiler)?;
+    let source = compiler.compile_code().map_err(RBFCError::Compiler)?;
+    let compiled = run_compiled(&source, args.target, input)?;
+    compare_outputs(&interpreted, &compiled)
+}
+
+/// The warning for a program that reads input, when it would block waiting for it
+///
+/// The interpreter reads from stdin, so there is only a warning if nothing is piped in and the
+/// input has to be typed. A compiled program is run later, so there is always a warning that it
+/// reads stdin.
+fn input_warning(
+    ops: &[Token],
+    interpreting: bool,
+    stdin_is_terminal: bool,
+) -> Option<&'static str> {
+    if !analyze::requires_input(ops) {
+        return None;
+    }
+    if !interpreting {
+        Some("Warning: the compiled program reads input, pipe it in when running it")
+    } else if stdin_is_terminal {
+        Some("Warning: the program reads input, type it or pipe it into rbfc")
+    } else {
+        None
+    }
+}
+
+/// Describe how long the interpreter took to execute the ops and how many it executed per second
+fn time_report(ops: usize, elapsed: Duration) -> String {
+    // A program that finishes below the resolution of the clock is counted as taking a nanosecond
+    let seconds = elapsed.as_secs_f64().max(1e-9);
+    format!(
+        "Executed {ops} ops in {seconds:.3}s ({rate:.0} ops/s)",
+        rate = ops as f64 / seconds
+    )
+}
+
+/// Interpret the program on the input and return its output
+fn interpret(
+    code: &str,
+    settings: InterpreterSettings,
+    input: &[u8],
+) -> Result<Vec<u8>, RBFCError> {
+    let mut interpreter =
+        Interpreter::new(code.to_string(), settings).map_err(RBFCError::Interpreter)?;
+    let (output, _) = interpreter
+        .run_with_input_queue(input.iter().copied().collect())
+        .map_err(RBFCError::Interpreter)?;
+    Ok(output)
+}
+
+/// Assemble the compiled program in a temporary directory, run it on the input and return its
+/// output
+fn run_compiled(source: &str, target: Target, input: &[u8]) -> Result<Vec<u8>, RBFCError> {
+    let dir = std::env::temp_dir().join(format!("rbfc-verify-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).or(Err(RBFCError::WritingFile(dir.display().to_string())))?;
+    let output = assemble_and_run(&dir, source, target, input);
+    let _ = std::fs::remove_dir_all(&dir);
+    output
+}
+
+/// Assemble the compiled program in the directory, run it on the input and return its output
+fn assemble_and_run(
+    dir: &Path,
+    source: &str,
+    target: Target,
+    input: &[u8],
+) -> Result<Vec<u8>, RBFCError> {
+    let source_path = dir.join(format!("program.{}", target.extension()));
+    std::fs::write(&source_path, source).or(Err(RBFCError::WritingFile(
+        source_path.display().to_string(),
+    )))?;
+
+    let mut command = match target {
+        Target::Fasm => {
+            let binary = dir.join("program");
+            let status = Command::new("fasm")
+                .arg(&source_path)
+                .arg(&binary)
+                .stdout(Stdio::null())
+                .status()
+                .map_err(|e| RBFCError::Assembler(format!("Couldn't run fasm: {e}")))?;
+            if !status.success() {
+                return Err(RBFCError::Assembler(format!("fasm failed with {status}")));
+            }
+            Command::new(binary)
+        }
+        Target::LlvmIr => {
+            let mut command = Command::new("lli");
+            // LLVM before 15 only reads the `ptr` type with opaque pointers enabled
+            if lli_version().is_some_and(|major| major < 15) {
+                command.arg("-opaque-pointers");
+            }
+            command.arg(&source_path);
+            command
+        }
+        Target::GasIntel => {
+            let object = dir.join("program.o");
+            let binary = dir.join("program");
+            for (tool, args) in [
+                ("as", [&source_path, &PathBuf::from("-o"), &object]),
+                ("ld", [&object, &PathBuf::from("-o"), &binary]),
+            ] {
+                let status = Command::new(tool)
+                    .args(args)
+                    .status()
+                    .map_err(|e| RBFCError::Assembler(format!("Couldn't run {tool}: {e}")))?;
+                if !status.success() {
+                    return Err(RBFCError::Assembler(format!("{tool} failed with {status}")));
+                }
+            }
+            Command::new(binary)
+        }
+        Target::PeWin64 | Target::Arm64 => {
+            return Err(RBFCError::Assembler(format!(
+                "Programs for {target:?} can't be run to verify them"
+            )))
+        }
+    };
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| RBFCError::Assembler(format!("Couldn't run the compiled program: {e}")))?;
+    // The input is written from another thread so a program writing a lot of output before
+    // reading can't block on a full pipe
+    let mut stdin = child.stdin.take().expect("stdin should be piped");
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child
+        .wait_with_output()
+        .map_err(|e| RBFCError::Assembler(format!("Couldn't run the compiled program: {e}")))?;
+    // The program may exit without reading all of the input
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(RBFCError::Assembler(format!(
+            "The compiled program failed with {}",
+            output.status
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// The major version of the installed `lli`
+fn lli_version() -> Option<u32> {
+    let output = Command::new("lli").arg("--version").output().ok()?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    let (_, version) = version.split_once("LLVM version ")?;
+    version.split('.').next()?.trim().parse().ok()
+}
+
+/// Compare the output of the interpreter with the output of the compiled program
+///
+/// The error shows where the outputs start to differ.
+fn compare_outputs(interpreted: &[u8], compiled: &[u8]) -> Result<(), RBFCError> {
+    if interpreted == compiled {
+        return Ok(());
+    }
+    let at = interpreted
+        .iter()
+        .zip(compiled)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let rest =
+        |output: &[u8]| String::from_utf8_lossy(&output[at..output.len().min(at + 32)]).to_string();
+    Err(RBFCError::Verify(format!(
+        "The outputs differ at byte {at}, the interpreter wrote {:?} and the compiled program wrote {:?}",
+        rest(interpreted),
+        rest(compiled)
+    )))
+}
+
+/// The path of the file the compiled code is written to, or `None` to write it to stdout
+///
+/// The file is named after the program with the extension of the target and placed in the
+/// output folder if one is given. An output folder of `-` selects stdout.
+fn output_path(file_name: &str, output: Option<&str>, target: Target) -> Option<String> {
+    let file = file_name.replace(".bf", &format!(".{}", target.extension()));
+    match output {
+        Some("-") => None,
+        Some(output) => Some(format!("{output}/{file}")),
+        None => Some(file),
+    }
+}
+
+/// Read the program from the file at the path, or from the reader if the path is `-`
+///
+/// Returns the name of the file together with the code. The name of a program read from the
+/// reader is `stdin.bf`.
+fn read_code(file_path: &Path, mut stdin: impl Read) -> Result<(String, String), RBFCError> {
+    if file_path == Path::new("-") {
+        let mut code = String::new();
+        stdin
+            .read_to_string(&mut code)
+            .or(Err(RBFCError::ReadingFile("stdin".to_string())))?;
+        return Ok(("stdin.bf".to_string(), code));
+    }
+
+    let file_name = file_path
+        .file_name()
+        .ok_or(RBFCError::ReadingFile("Couldn't get filename".to_string()))?
+        .to_os_string()
+        .into_string()
+        .or(Err(RBFCError::ReadingFile(
+            "Couldn't get filename".to_string(),
+        )))?;
+
+    let code =
+        std::fs::read_to_string(file_path).or(Err(RBFCError::ReadingFile(file_name.clone())))?;
+
+    Ok((file_name, code))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rbfc::interpreter::SharedBuffer;
+    use rbfc::lexer::LexerError;
+
+    #[test]
+    fn test_read_code_from_stdin() {
+        let stdin = "+++.".as_bytes();
+        let (file_name, code) = read_code(Path::new("-"), stdin).unwrap();
+        assert_eq!(file_name, "stdin.bf");
+        assert_eq!(code, "+++.");
+    }
+
+    #[test]
+    fn test_output_path() {
+        assert_eq!(output_path("hello.bf", Some("-"), Target::Fasm), None);
+        assert_eq!(
+            output_path("hello.bf", Some("out"), Target::LlvmIr),
+            Some("out/hello.ll".to_string())
+        );
+        assert_eq!(
+            output_path("hello.bf", None, Target::Fasm),
+            Some("hello.asm".to_string())
+        );
+
+        let args = Args::parse_from(["rbfc", "hello.bf", "-o", "-"]);
+        assert_eq!(
+            output_path("hello.bf", args.output.as_deref(), args.target),
+            None
+        );
+
+        // Compiling to stdout writes no file, while compiling into the folder writes one
+        let dir = std::env::temp_dir().join(format!("rbfc-output-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let compiler = Compiler::new("+.", args.compiler_settings()).unwrap();
+        let mut output = Vec::new();
+        let file = output_path("hello.bf", args.output.as_deref(), args.target);
+        assert!(file.is_none());
+        write_compiled(&compiler, file, true, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            compiler.compile_code().unwrap()
+        );
+        assert!(!Path::new("hello.asm").exists());
+        assert!(!Path::new("hello.asm.map").exists());
+
+        let file = output_path("hello.bf", dir.to_str(), args.target);
+        write_compiled(&compiler, file, false, &mut Vec::new()).unwrap();
+        assert!(dir.join("hello.asm").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_strip_command() {
+        let args = Args::try_parse_from(["rbfc", "strip", "x.bf", "--minify"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Commands::Strip { ref file_path, output: None, minify: true })
+                if file_path == Path::new("x.bf")
+        ));
+        assert!(args.file_path.is_none());
+
+        let args = Args::try_parse_from(["rbfc", "x.bf"]).unwrap();
+        assert!(args.command.is_none());
+        assert!(Args::try_parse_from(["rbfc"]).is_err());
+    }
+
+    #[test]
+    fn test_dump_ir() {
+        let args = Args::try_parse_from(["rbfc", "x.bf", "--dump-ir", "-O", "1"]).unwrap();
+        assert!(args.dump_ir);
+        let mut output = Vec::new();
+        dump_ir("+[-]".to_string(), args.compiler_settings(), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Plus size=1 loc=0\nClear loc=1\nEof loc=4\n"
+        );
+
+        let mut output = Vec::new();
+        dump_ir("[-]".to_string(), CompilerSettings::default(), &mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("    Minus size=1 loc=1\n"));
+    }
+
+    #[test]
+    fn test_tape_options() {
+        let args =
+            Args::try_parse_from(["rbfc", "x.bf", "--tape-size", "1024", "--cell-width", "16"])
+                .unwrap();
+
+        let tape = TapeConfig::default()
+            .tape_size(1024)
+            .cell_width(CellWidth::U16);
+        assert_eq!(args.interpreter_settings().tape, tape);
+        assert_eq!(args.compiler_settings().tape, tape);
+
+        assert!(Args::try_parse_from(["rbfc", "x.bf", "--cell-width", "12"]).is_err());
+        assert!(Args::try_parse_from(["rbfc", "x.bf", "--tape-size", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        // Running the compiled program needs `lli`, which is not installed everywhere
+        if lli_version().is_none() {
+            eprintln!("lli is not installed, skipping test_verify");
+            return;
+        }
+        let args = Args::try_parse_from(["rbfc", "x.bf", "--verify", "-t", "llvm-ir"]).unwrap();
+        let code = "+++++++++[>++++++++<-]>.";
+        verify(code, &args, b"").unwrap();
+
+        // Miscompile the subtraction and make sure the difference is caught
+        let compiler = Compiler::new(code.to_string(), args.compiler_settings()).unwrap();
+        let miscompiled = compiler.compile_code().unwrap().replace("sub i8", "add i8");
+        let interpreted = interpret(code, args.interpreter_settings(), b"").unwrap();
+        let compiled = run_compiled(&miscompiled, args.target, b"").unwrap();
+        assert!(matches!(
+            compare_outputs(&interpreted, &compiled),
+            Err(RBFCError::Verify(_))
+        ));
+    }
+
+    #[test]
+    fn test_repl() {
+        let lines = "+++>+\n<[->++<]]\n:reset\n,\n".as_bytes();
+        let mut report = Vec::new();
+        let output = SharedBuffer::new(Vec::new());
+        repl(
+            lines,
+            &mut report,
+            Box::new(output.clone()),
+            InterpreterSettings::default(),
+        )
+        .unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert_eq!(
+            report.lines().collect::<Vec<_>>(),
+            [
+                ">  03 [01]",
+                "> Error: Parsing error: Unmatched bracket at position 7",
+                " 03 [01]",
+                "> [00]",
+                "> Warning: `,` reads no input in the REPL, stdin holds the lines",
+                "Error: Unexpected input error",
+                "[00]",
+                "> "
+            ]
+        );
+        assert!(output.take_contents().is_empty());
+    }
+
+    #[test]
+    fn test_input_warning() {
+        let ops = parser::Parser::new(",.").parse().unwrap();
+        assert!(input_warning(&ops, true, true).is_some());
+        assert!(input_warning(&ops, true, false).is_none());
+        assert!(input_warning(&ops, false, false).is_some());
+
+        let ops = parser::Parser::new("+.").parse().unwrap();
+        assert!(input_warning(&ops, true, true).is_none());
+        assert!(input_warning(&ops, false, true).is_none());
+    }
+
+    #[test]
+    fn test_time_report() {
+        let args = Args::try_parse_from(["rbfc", "x.bf", "-i", "--time"]).unwrap();
+        assert!(args.time);
+        let mut interpreter = Interpreter::new("+++", args.interpreter_settings()).unwrap();
+        interpreter.interpret().unwrap();
+        assert_eq!(interpreter.executed_ops(), 1);
+        assert_eq!(
+            time_report(interpreter.executed_ops(), Duration::from_millis(500)),
+            "Executed 1 ops in 0.500s (2 ops/s)"
+        );
+    }
+
+    #[test]
+    fn test_locate() {
+        let code = "+\r\n+]";
+        let error = Interpreter::new(code, InterpreterSettings::default())
+            .err()
+            .unwrap();
+        let error = RBFCError::Interpreter(error).locate(code, LexerSettings::default());
+        assert_eq!(
+            error.to_string(),
+            "Error while parsing at line 2, column 2: Unmatched bracket at position 1"
+        );
+        assert_eq!(error.exit_code(), 2);
+
+        let error = RBFCError::Verify("lli".to_string()).locate(code, LexerSettings::default());
+        assert!(matches!(error, RBFCError::Verify(_)));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let cases = [
+            (RBFCError::ReadingFile("x.bf".to_string()), 4),
+            (RBFCError::WritingFile("x.asm".to_string()), 4),
+            (RBFCError::Interpreter(InterpreterError::OutputError), 4),
+            (RBFCError::Interpreter(InterpreterError::InputError), 4),
+            (
+                RBFCError::Interpreter(InterpreterError::ParserError(
+                    ParserError::UnmatchedBracket(0),
+                )),
+                2,
+            ),
+            (
+                RBFCError::Compiler(CompilerError::ParsingError(ParserError::UnmatchedBracket(
+                    0,
+                ))),
+                2,
+            ),
+            (
+                RBFCError::Interpreter(InterpreterError::TapeOverflow {
+                    loc: 3,
+                    attempted: 3,
+                    len: 3,
+                }),
+                3,
+            ),
+            (
+                RBFCError::Interpreter(InterpreterError::TapeUnderflow {
+                    loc: 3,
+                    attempted: -1,
+                    len: 3,
+                }),
+                3,
+            ),
+            (
+                RBFCError::Interpreter(InterpreterError::ParserError(ParserError::LexerError(
+                    LexerError::UnexpectedChar('a', 0),
+                ))),
+                2,
+            ),
+            (RBFCError::Compiler(CompilerError::UnexpectedEof), 2),
+            (RBFCError::Interpreter(InterpreterError::CellOverflow(0)), 3),
+            (RBFCError::Interpreter(InterpreterError::UninitRead(0)), 3),
+            (
+                RBFCError::Interpreter(InterpreterError::InvalidInitialDp { dp: 3, len: 3 }),
+                3,
+            ),
+            (
+                RBFCError::Compiler(CompilerError::WriteError("x.asm".to_string())),
+                4,
+            ),
+            (RBFCError::Assembler("fasm".to_string()), 5),
+            (RBFCError::Compiler(CompilerError::UnexpectedNoneSize(3)), 1),
+            (RBFCError::Compiler(CompilerError::EmptyTape), 1),
+            (
+                RBFCError::Interpreter(InterpreterError::StepLimitExceeded(10)),
+                1,
+            ),
+            (
+                RBFCError::Interpreter(InterpreterError::Timeout(Duration::from_secs(1))),
+                1,
+            ),
+            (RBFCError::Verify("lli".to_string()), 1),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error.exit_code(), code, "{error}");
+        }
+    }
+}